@@ -0,0 +1,9 @@
+-- Add up migration script here
+
+CREATE TABLE "revoked_tokens" (
+    jti VARCHAR(64) NOT NULL PRIMARY KEY,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    revoked_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX revoked_tokens_expires_at_idx ON revoked_tokens (expires_at);
@@ -0,0 +1,3 @@
+-- Add up migration script here
+
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMP WITH TIME ZONE;
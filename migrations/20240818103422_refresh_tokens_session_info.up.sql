@@ -0,0 +1,6 @@
+-- Add up migration script here
+
+ALTER TABLE "refresh_tokens"
+    ADD COLUMN user_agent TEXT,
+    ADD COLUMN ip_address VARCHAR(45),
+    ADD COLUMN last_used_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
@@ -0,0 +1,5 @@
+-- Add down migration script here
+
+ALTER TABLE "users"
+    DROP COLUMN last_login_ip,
+    DROP COLUMN last_login_at;
@@ -0,0 +1,5 @@
+-- Add up migration script here
+
+ALTER TABLE "users"
+    ADD COLUMN last_login_at TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN last_login_ip VARCHAR(45);
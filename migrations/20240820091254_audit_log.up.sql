@@ -0,0 +1,16 @@
+-- Add up migration script here
+
+CREATE TYPE audit_action AS ENUM ('login', 'login_failed', 'logout', 'password_change', 'password_reset', 'role_change');
+
+CREATE TABLE "audit_log" (
+    id UUID NOT NULL PRIMARY KEY DEFAULT (uuid_generate_v4()),
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    action audit_action NOT NULL,
+    ip_address VARCHAR(45),
+    details TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX audit_log_user_id_idx ON audit_log (user_id);
+CREATE INDEX audit_log_action_idx ON audit_log (action);
+CREATE INDEX audit_log_created_at_idx ON audit_log (created_at);
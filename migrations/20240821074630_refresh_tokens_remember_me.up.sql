@@ -0,0 +1,3 @@
+-- Add up migration script here
+
+ALTER TABLE "refresh_tokens" ADD COLUMN remember_me BOOLEAN;
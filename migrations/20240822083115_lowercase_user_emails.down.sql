@@ -0,0 +1,3 @@
+-- Add down migration script here
+
+-- The original casing is not kept, so there is nothing to restore.
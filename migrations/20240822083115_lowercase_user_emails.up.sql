@@ -0,0 +1,7 @@
+-- Add up migration script here
+
+-- Addresses that would collide once lowercased are left as-is for an operator to merge.
+UPDATE users
+SET email = LOWER(email)
+WHERE email <> LOWER(email)
+  AND (SELECT COUNT(*) FROM users other WHERE LOWER(other.email) = LOWER(users.email)) = 1;
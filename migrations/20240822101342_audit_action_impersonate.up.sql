@@ -0,0 +1,3 @@
+-- Add up migration script here
+
+ALTER TYPE audit_action ADD VALUE IF NOT EXISTS 'impersonate';
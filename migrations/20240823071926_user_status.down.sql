@@ -0,0 +1,12 @@
+-- Add down migration script here
+
+DELETE FROM "audit_log" WHERE action = 'status_change';
+
+ALTER TYPE audit_action RENAME TO audit_action_old;
+CREATE TYPE audit_action AS ENUM ('login', 'login_failed', 'logout', 'password_change', 'password_reset', 'role_change', 'impersonate');
+ALTER TABLE "audit_log" ALTER COLUMN action TYPE audit_action USING action::text::audit_action;
+DROP TYPE audit_action_old;
+
+ALTER TABLE "users" DROP COLUMN IF EXISTS status;
+
+DROP TYPE IF EXISTS user_status;
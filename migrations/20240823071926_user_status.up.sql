@@ -0,0 +1,9 @@
+-- Add up migration script here
+
+CREATE TYPE user_status AS ENUM ('active', 'pending', 'suspended', 'banned');
+
+ALTER TABLE "users" ADD COLUMN status user_status NOT NULL DEFAULT 'pending';
+
+UPDATE "users" SET status = CASE WHEN verified THEN 'active'::user_status ELSE 'pending'::user_status END;
+
+ALTER TYPE audit_action ADD VALUE IF NOT EXISTS 'status_change';
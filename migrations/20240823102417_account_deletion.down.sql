@@ -0,0 +1,12 @@
+-- Add down migration script here
+
+DELETE FROM "audit_log" WHERE action IN ('deletion_request', 'deletion_cancel');
+
+ALTER TYPE audit_action RENAME TO audit_action_old;
+CREATE TYPE audit_action AS ENUM ('login', 'login_failed', 'logout', 'password_change', 'password_reset', 'role_change', 'impersonate', 'status_change');
+ALTER TABLE "audit_log" ALTER COLUMN action TYPE audit_action USING action::text::audit_action;
+DROP TYPE audit_action_old;
+
+DROP INDEX IF EXISTS users_purge_after_idx;
+
+ALTER TABLE "users" DROP COLUMN IF EXISTS purge_after;
@@ -0,0 +1,8 @@
+-- Add up migration script here
+
+ALTER TABLE users ADD COLUMN purge_after TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX users_purge_after_idx ON users (purge_after) WHERE purge_after IS NOT NULL;
+
+ALTER TYPE audit_action ADD VALUE IF NOT EXISTS 'deletion_request';
+ALTER TYPE audit_action ADD VALUE IF NOT EXISTS 'deletion_cancel';
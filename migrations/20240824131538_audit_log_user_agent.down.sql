@@ -0,0 +1,5 @@
+-- Add down migration script here
+
+DROP INDEX IF EXISTS audit_log_user_id_created_at_idx;
+
+ALTER TABLE "audit_log" DROP COLUMN user_agent;
@@ -0,0 +1,6 @@
+-- Add up migration script here
+
+ALTER TABLE "audit_log" ADD COLUMN user_agent TEXT;
+
+-- Serves a user's login history, which is read newest first.
+CREATE INDEX audit_log_user_id_created_at_idx ON audit_log (user_id, created_at DESC);
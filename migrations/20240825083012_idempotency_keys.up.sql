@@ -0,0 +1,11 @@
+-- Add up migration script here
+
+CREATE TABLE "idempotency_keys" (
+    idempotency_key VARCHAR(255) NOT NULL PRIMARY KEY,
+    request_hash VARCHAR(64) NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idempotency_keys_expires_at_idx ON idempotency_keys (expires_at);
@@ -0,0 +1,7 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS "phone_verification_codes";
+
+ALTER TABLE users
+    DROP COLUMN IF EXISTS phone_verified,
+    DROP COLUMN IF EXISTS phone;
@@ -0,0 +1,15 @@
+-- Add up migration script here
+
+ALTER TABLE users
+    ADD COLUMN phone VARCHAR(16),
+    ADD COLUMN phone_verified BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- At most one outstanding code per user; requesting a new one replaces it.
+CREATE TABLE "phone_verification_codes" (
+    user_id UUID NOT NULL PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    phone VARCHAR(16) NOT NULL,
+    code_hash VARCHAR(64) NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
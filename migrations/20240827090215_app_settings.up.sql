@@ -0,0 +1,10 @@
+-- Add up migration script here
+
+-- Settings admins can change at runtime. The CHECK keeps it to a single row.
+CREATE TABLE "app_settings" (
+    id BOOLEAN NOT NULL PRIMARY KEY DEFAULT TRUE CHECK (id),
+    registration_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO app_settings DEFAULT VALUES;
@@ -0,0 +1,5 @@
+-- Add up migration script here
+
+-- The last TOTP time step accepted for the user, so a code can't be used twice.
+ALTER TABLE users
+    ADD COLUMN totp_last_step BIGINT;
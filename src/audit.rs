@@ -0,0 +1,39 @@
+use actix_web::HttpRequest;
+use uuid::Uuid;
+
+use crate::{db::AuditLogExt, models::AuditAction, utils, AppState};
+
+/// Records a security-relevant event in the audit log.
+///
+/// The write happens on a background task so a slow or failing insert never
+/// delays or fails the response; errors are only logged.
+pub fn record(
+    app_state: &AppState,
+    req: &HttpRequest,
+    user_id: Option<Uuid>,
+    action: AuditAction,
+    details: Option<String>,
+) {
+    let db_client = app_state.db_client.clone();
+    let ip_address = utils::ip::client_ip(&req.connection_info(), app_state.env.trust_proxy);
+    let user_agent = req
+        .headers()
+        .get(actix_web::http::header::USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .map(|ua| ua.to_string());
+
+    actix_web::rt::spawn(async move {
+        if let Err(e) = db_client
+            .save_audit_event(
+                user_id,
+                action,
+                ip_address.as_deref(),
+                user_agent.as_deref(),
+                details.as_deref(),
+            )
+            .await
+        {
+            log::error!("Error writing audit log entry: {}", e);
+        }
+    });
+}
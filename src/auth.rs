@@ -6,10 +6,46 @@ use futures_util::FutureExt;
 use std::rc::Rc;
 use std::task::{Context, Poll};
 
-use crate::db::UserExt;
+use crate::db::{ApiKeyExt, RevokedTokenExt, UserExt};
 use crate::error::{ErrorMessage, ErrorResponse, HttpError};
-use crate::models::{User, UserRole};
-use crate::{utils, AppState};
+use crate::models::{Permission, User, UserRole, UserStatus};
+use crate::utils::token::{hash_token, TokenClaims};
+use crate::{request_id, AppState};
+
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenSource {
+    Header,
+    Cookie,
+}
+
+impl TokenSource {
+    fn extract(self, req: &ServiceRequest) -> Option<String> {
+        match self {
+            TokenSource::Header => req
+                .headers()
+                .get(http::header::AUTHORIZATION)
+                .and_then(|h| h.to_str().ok())
+                .and_then(|h| h.strip_prefix("Bearer "))
+                .map(|token| token.trim().to_string())
+                .filter(|token| !token.is_empty()),
+            TokenSource::Cookie => req.cookie("token").map(|c| c.value().to_string()),
+        }
+    }
+}
+
+impl std::str::FromStr for TokenSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "header" => Ok(TokenSource::Header),
+            "cookie" => Ok(TokenSource::Cookie),
+            other => Err(format!("Unsupported token source: {}", other)),
+        }
+    }
+}
 
 pub struct Authenticated(User);
 
@@ -23,7 +59,15 @@ impl FromRequest for Authenticated {
     ) -> Self::Future {
         let value = req.extensions().get::<User>().cloned();
         let result = match value {
-            Some(user) => Ok(Authenticated(user)),
+            Some(user) => match status_error(user.status) {
+                Some(error) => Err(ErrorForbidden(ErrorResponse {
+                    status: "fail".to_string(),
+                    message: error.to_string(),
+                    request_id: request_id::current(),
+                    errors: None,
+                })),
+                None => Ok(Authenticated(user)),
+            },
             None => Err(ErrorInternalServerError(HttpError::server_error(
                 "Authentication Error",
             ))),
@@ -32,6 +76,16 @@ impl FromRequest for Authenticated {
     }
 }
 
+// Only active accounts get through; pending ones until their email is verified,
+// whether or not login requires that.
+fn status_error(status: UserStatus) -> Option<ErrorMessage> {
+    match status {
+        UserStatus::Active => None,
+        UserStatus::Pending => Some(ErrorMessage::EmailNotVerified),
+        status => status.lock_error(),
+    }
+}
+
 impl std::ops::Deref for Authenticated{
     type Target = User;
 
@@ -42,14 +96,53 @@ impl std::ops::Deref for Authenticated{
 
 pub struct RequireAuth {
     pub allowed_roles: Rc<Vec<UserRole>>,
+    pub allow_impersonation: bool,
+    pub required_permission: Option<Permission>,
 }
 
 impl RequireAuth {
     pub fn allowed_roles(allowed_roles: Vec<UserRole>) -> Self {
         RequireAuth {
             allowed_roles: Rc::new(allowed_roles),
+            allow_impersonation: true,
+            required_permission: None,
+        }
+    }
+
+    /// Allows any logged-in user, whatever their role.
+    pub fn any_authenticated() -> Self {
+        RequireAuth::min_role(UserRole::User)
+    }
+
+    /// Allows `min_role` and every role above it.
+    pub fn min_role(min_role: UserRole) -> Self {
+        RequireAuth::allowed_roles(
+            UserRole::ALL
+                .into_iter()
+                .filter(|role| *role >= min_role)
+                .collect(),
+        )
+    }
+
+    /// Allows every role granted `permission`, and API keys scoped to it.
+    pub fn permission(permission: Permission) -> Self {
+        RequireAuth {
+            required_permission: Some(permission),
+            ..RequireAuth::allowed_roles(
+                UserRole::ALL
+                    .into_iter()
+                    .filter(|role| role.has_permission(permission))
+                    .collect(),
+            )
         }
     }
+
+    /// Rejects tokens issued through admin impersonation and API keys, for routes
+    /// that change credentials or privileges.
+    pub fn deny_impersonation(mut self) -> Self {
+        self.allow_impersonation = false;
+        self
+    }
 }
 
 impl<S> Transform<S, ServiceRequest> for RequireAuth
@@ -70,6 +163,8 @@ where
         ready(Ok(AuthMiddleware {
             service: Rc::new(service),
             allowed_roles: self.allowed_roles.clone(),
+            allow_impersonation: self.allow_impersonation,
+            required_permission: self.required_permission,
         }))
     }
 }
@@ -77,6 +172,8 @@ where
 pub struct AuthMiddleware<S> {
     service: Rc<S>,
     allowed_roles: Rc<Vec<UserRole>>,
+    allow_impersonation: bool,
+    required_permission: Option<Permission>,
 }
 
 impl<S> Service<ServiceRequest> for AuthMiddleware<S>
@@ -97,68 +194,368 @@ where
 
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        let token = req
-            .cookie("token")
-            .map(|c| c.value().to_string())
-            .or_else(|| {
-                req.headers()
-                    .get(http::header::AUTHORIZATION)
-                    .map(|h| h.to_str().unwrap().split_at(7).1.to_string())   
-            });
-
-        if token.is_none() {
+        let app_state = req.app_data::<web::Data<AppState>>().unwrap();
+        let token = app_state
+            .env
+            .token_sources
+            .iter()
+            .find_map(|source| source.extract(&req));
+
+        let Some(token) = token else {
+            if let Some(api_key) = req
+                .headers()
+                .get(API_KEY_HEADER)
+                .and_then(|h| h.to_str().ok())
+                .map(|key| key.trim().to_string())
+                .filter(|key| !key.is_empty())
+            {
+                return self.call_with_api_key(req, api_key);
+            }
+
             let json_error = ErrorResponse {
                 status: "fail".to_string(),
                 message: ErrorMessage::TokenNotProvided.to_string(),
+                request_id: request_id::current(),
+                errors: None,
             };
 
             return Box::pin(ready(Err(ErrorUnauthorized(json_error))));
-        }
+        };
 
-        let app_state = req.app_data::<web::Data<AppState>>().unwrap();
-        let user_id = match utils::token::decode_token(
-            &token.unwrap(), 
-            app_state.env.jwt_secret.as_bytes(),
-        )  {
-            Ok(id) => id,
+        let claims = match app_state.token_service.decode_token(&token) {
+            Ok(claims) => claims,
             Err(e) => {
                 return Box::pin(ready(Err(ErrorUnauthorized(ErrorResponse {
                     status: "fail".to_string(),
                     message: e.message,
+                    request_id: request_id::current(),
+                    errors: None,
                 }))))
             }
         };
 
+        if !self.allow_impersonation && claims.impersonated_by.is_some() {
+            return Box::pin(ready(Err(ErrorForbidden(ErrorResponse {
+                status: "fail".to_string(),
+                message: ErrorMessage::ImpersonationNotAllowed.to_string(),
+                request_id: request_id::current(),
+                errors: None,
+            }))));
+        }
+
+        // The role claim lets forbidden requests be turned away without touching the
+        // database; the stored user's role still has the final say below.
+        if let Some(role) = claims.role {
+            if !self.allowed_roles.contains(&role) {
+                return Box::pin(ready(Err(ErrorForbidden(ErrorResponse {
+                    status: "fail".to_string(),
+                    message: ErrorMessage::PermissionDenied.to_string(),
+                    request_id: request_id::current(),
+                    errors: None,
+                }))));
+            }
+        }
+
         let cloned_app_state = app_state.clone();
         let allowed_roles = self.allowed_roles.clone();
         let srv = Rc::clone(&self.service);
 
         async move {
-            let user_id = uuid::Uuid::parse_str(user_id.as_str()).unwrap();
+            let revoked = cloned_app_state
+                    .db_client
+                    .is_token_revoked(&claims.jti)
+                    .await
+                    .map_err(HttpError::from)?;
+
+            if revoked {
+                return Err(ErrorUnauthorized(ErrorResponse {
+                    status: "fail".to_string(),
+                    message: ErrorMessage::InvalidToken.to_string(),
+                    request_id: request_id::current(),
+                    errors: None,
+                }));
+            }
+
+            let user_id = uuid::Uuid::parse_str(claims.sub.as_str()).map_err(|_| {
+                ErrorUnauthorized(ErrorResponse {
+                    status: "fail".to_string(),
+                    message: ErrorMessage::InvalidToken.to_string(),
+                    request_id: request_id::current(),
+                    errors: None,
+                })
+            })?;
             let result = cloned_app_state
                     .db_client
-                    .get_user(Some(user_id.clone()), None, None)
+                    .get_user(Some(user_id), None, None)
                     .await
-                    .map_err(|e| ErrorInternalServerError(HttpError::server_error(e.to_string())))?;
+                    .map_err(HttpError::from)?;
 
             let user = result.ok_or(ErrorUnauthorized(ErrorResponse {
                 status: "fail".to_string(),
                 message: ErrorMessage::UserNoLongerExist.to_string(),
+                request_id: request_id::current(),
+                errors: None,
             }))?;
 
-            if allowed_roles.contains(&user.role) {
-                req.extensions_mut().insert::<User>(user);
-                let res = srv.call(req).await?;
-                Ok(res)
-            }else {
-                let json_error = ErrorResponse{
+            authorize_user(&user, &allowed_roles)?;
+
+            req.extensions_mut().insert::<User>(user);
+            req.extensions_mut().insert::<TokenClaims>(claims);
+            let res = srv.call(req).await?;
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
+
+impl<S> AuthMiddleware<S>
+where
+    S: Service<
+            ServiceRequest,
+            Response = ServiceResponse<actix_web::body::BoxBody>,
+            Error = actix_web::Error,
+        > + 'static,
+{
+    // A key acts for its owner only within its scopes, so keys are limited to routes
+    // guarded by a permission and never accepted where impersonation is refused.
+    // No TokenClaims are attached, which is why token-only handlers stay out of reach.
+    fn call_with_api_key(
+        &self,
+        req: ServiceRequest,
+        api_key: String,
+    ) -> LocalBoxFuture<'static, Result<ServiceResponse<actix_web::body::BoxBody>, actix_web::Error>> {
+        let Some(permission) = self.required_permission.filter(|_| self.allow_impersonation) else {
+            return Box::pin(ready(Err(ErrorForbidden(ErrorResponse {
+                status: "fail".to_string(),
+                message: ErrorMessage::ApiKeyNotAllowed.to_string(),
+                request_id: request_id::current(),
+                errors: None,
+            }))));
+        };
+
+        let app_state = req.app_data::<web::Data<AppState>>().unwrap().clone();
+        let allowed_roles = self.allowed_roles.clone();
+        let srv = Rc::clone(&self.service);
+
+        async move {
+            let api_key = app_state
+                .db_client
+                .use_api_key(&hash_token(&api_key))
+                .await
+                .map_err(HttpError::from)?
+                .ok_or_else(|| {
+                    ErrorUnauthorized(ErrorResponse {
+                        status: "fail".to_string(),
+                        message: ErrorMessage::InvalidApiKey.to_string(),
+                        request_id: request_id::current(),
+                        errors: None,
+                    })
+                })?;
+
+            if !api_key.permissions().contains(&permission) {
+                return Err(ErrorForbidden(ErrorResponse {
                     status: "fail".to_string(),
                     message: ErrorMessage::PermissionDenied.to_string(),
-                };
-                Err(ErrorForbidden(json_error))
+                    request_id: request_id::current(),
+                    errors: None,
+                }));
             }
 
+            let user = app_state
+                .db_client
+                .get_user(Some(api_key.user_id), None, None)
+                .await
+                .map_err(HttpError::from)?
+                .ok_or_else(|| {
+                    ErrorUnauthorized(ErrorResponse {
+                        status: "fail".to_string(),
+                        message: ErrorMessage::UserNoLongerExist.to_string(),
+                        request_id: request_id::current(),
+                        errors: None,
+                    })
+                })?;
+
+            // The owner's current role still applies, so a key stops granting
+            // anything the owner has since lost.
+            authorize_user(&user, &allowed_roles)?;
+
+            req.extensions_mut().insert::<User>(user);
+            srv.call(req).await
         }
         .boxed_local()
     }
-}
\ No newline at end of file
+}
+
+fn authorize_user(user: &User, allowed_roles: &[UserRole]) -> Result<(), actix_web::Error> {
+    if let Some(error) = status_error(user.status) {
+        return Err(ErrorForbidden(ErrorResponse {
+            status: "fail".to_string(),
+            message: error.to_string(),
+            request_id: request_id::current(),
+            errors: None,
+        }));
+    }
+
+    if !allowed_roles.contains(&user.role) {
+        return Err(ErrorForbidden(ErrorResponse {
+            status: "fail".to_string(),
+            message: ErrorMessage::PermissionDenied.to_string(),
+            request_id: request_id::current(),
+            errors: None,
+        }));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Email;
+    use crate::utils::token::{FakeTokenService, TokenService};
+    use actix_web::test::{call_service, init_service, try_call_service, TestRequest};
+    use actix_web::{App, HttpResponse};
+    use sqlx::PgPool;
+    use std::sync::Arc;
+
+    #[test]
+    fn min_role_allows_the_role_and_every_role_above_it() {
+        let moderators = RequireAuth::min_role(UserRole::Moderator);
+
+        assert!(moderators.allowed_roles.contains(&UserRole::Moderator));
+        assert!(moderators.allowed_roles.contains(&UserRole::Admin));
+        assert!(!moderators.allowed_roles.contains(&UserRole::User));
+    }
+
+    async fn moderator_token(app_state: &AppState) -> String {
+        let db_client = &app_state.db_client;
+        let user = db_client
+            .save_user("Mod", &Email::parse("mod@example.com").unwrap(), "hashed")
+            .await
+            .unwrap();
+        db_client.verify_user(user.id).await.unwrap();
+        db_client.update_user_role(user.id, UserRole::Moderator).await.unwrap();
+
+        app_state
+            .token_service
+            .create_token(&user.id.to_string(), UserRole::Moderator, &user.email, uuid::Uuid::new_v4(), 60)
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn min_role_gate_admits_moderator_to_user_routes_only(pool: PgPool) {
+        let app_state = AppState::for_tests(pool);
+        let token = moderator_token(&app_state).await;
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(app_state))
+                .route(
+                    "/user",
+                    web::get()
+                        .to(HttpResponse::Ok)
+                        .wrap(RequireAuth::min_role(UserRole::User)),
+                )
+                .route(
+                    "/admin",
+                    web::get()
+                        .to(HttpResponse::Ok)
+                        .wrap(RequireAuth::min_role(UserRole::Admin)),
+                ),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/user")
+            .insert_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), http::StatusCode::OK);
+
+        let req = TestRequest::get()
+            .uri("/admin")
+            .insert_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        let res = try_call_service(&app, req).await;
+        let status = match res {
+            Ok(res) => res.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+        assert_eq!(status, http::StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn deny_impersonation_rejects_impersonation_tokens(pool: PgPool) {
+        let token_service = Arc::new(FakeTokenService::default());
+        let app_state = AppState {
+            token_service: token_service.clone(),
+            ..AppState::for_tests(pool)
+        };
+        let user = app_state
+            .db_client
+            .save_user("John", &Email::parse("john@example.com").unwrap(), "hashed")
+            .await
+            .unwrap();
+        app_state.db_client.verify_user(user.id).await.unwrap();
+        let token = token_service
+            .create_token(&user.id.to_string(), user.role, &user.email, uuid::Uuid::new_v4(), 60)
+            .unwrap();
+        let impersonation_token = token_service
+            .create_impersonation_token(&user.id.to_string(), user.role, &user.email, "admin", 15)
+            .unwrap();
+        let app = init_service(
+            App::new().app_data(web::Data::new(app_state)).route(
+                "/",
+                web::get()
+                    .to(HttpResponse::Ok)
+                    .wrap(RequireAuth::any_authenticated().deny_impersonation()),
+            ),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .insert_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), http::StatusCode::OK);
+
+        let req = TestRequest::get()
+            .insert_header((http::header::AUTHORIZATION, format!("Bearer {}", impersonation_token)))
+            .to_request();
+        let res = try_call_service(&app, req).await;
+        let status = match res {
+            Ok(res) => res.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+        assert_eq!(status, http::StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn pending_users_are_turned_away(pool: PgPool) {
+        let app_state = AppState::for_tests(pool);
+        let user = app_state
+            .db_client
+            .save_user("John", &Email::parse("john@example.com").unwrap(), "hashed")
+            .await
+            .unwrap();
+        assert_eq!(user.status, UserStatus::Pending);
+        let token = app_state
+            .token_service
+            .create_token(&user.id.to_string(), user.role, &user.email, uuid::Uuid::new_v4(), 60)
+            .unwrap();
+        let app = init_service(
+            App::new().app_data(web::Data::new(app_state)).route(
+                "/",
+                web::get().to(HttpResponse::Ok).wrap(RequireAuth::any_authenticated()),
+            ),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .insert_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        let err = try_call_service(&app, req).await.unwrap_err();
+
+        assert_eq!(err.as_response_error().status_code(), http::StatusCode::FORBIDDEN);
+        assert!(err.to_string().contains(&ErrorMessage::EmailNotVerified.to_string()));
+    }
+}
@@ -0,0 +1,83 @@
+use async_trait::async_trait;
+use sha1::{Digest, Sha1};
+use std::time::Duration;
+
+const HIBP_RANGE_URL: &str = "https://api.pwnedpasswords.com/range/";
+const HIBP_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[async_trait]
+pub trait BreachChecker: Send + Sync + std::fmt::Debug {
+    /// Returns whether `password` appears in a known data breach.
+    async fn is_breached(&self, password: &str) -> Result<bool, String>;
+}
+
+/// Checks passwords against the HaveIBeenPwned range API. Only the first five
+/// hex characters of the SHA-1 hash ever leave the server.
+#[derive(Debug, Clone)]
+pub struct HibpBreachChecker {
+    client: reqwest::Client,
+}
+
+impl HibpBreachChecker {
+    pub fn new() -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(HIBP_TIMEOUT)
+            .user_agent("rust_auth")
+            .build()
+            .unwrap();
+
+        HibpBreachChecker { client }
+    }
+}
+
+#[async_trait]
+impl BreachChecker for HibpBreachChecker {
+    async fn is_breached(&self, password: &str) -> Result<bool, String> {
+        let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(5);
+
+        let body = self
+            .client
+            .get(format!("{}{}", HIBP_RANGE_URL, prefix))
+            .header("Add-Padding", "true")
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?
+            .text()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        // Each line is "SUFFIX:COUNT"; padded entries have a count of zero.
+        let breached = body.lines().any(|line| {
+            line.split_once(':').is_some_and(|(candidate, count)| {
+                candidate == suffix && count.trim() != "0"
+            })
+        });
+
+        Ok(breached)
+    }
+}
+
+/// Treats the listed passwords as breached, or fails every check when `unavailable`,
+/// and counts the checks made.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct FakeBreachChecker {
+    pub breached: Vec<String>,
+    pub unavailable: bool,
+    pub checks: std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl BreachChecker for FakeBreachChecker {
+    async fn is_breached(&self, password: &str) -> Result<bool, String> {
+        self.checks.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+
+        if self.unavailable {
+            return Err("breach API unavailable".to_string());
+        }
+        Ok(self.breached.iter().any(|breached| breached == password))
+    }
+}
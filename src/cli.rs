@@ -0,0 +1,80 @@
+use crate::{
+    config::Config,
+    db::{DBClient, UserExt},
+    error::ErrorMessage,
+    models::Email,
+    utils::{name, password},
+};
+
+#[derive(Debug)]
+pub struct CreateAdminArgs {
+    pub name: String,
+    pub email: String,
+    pub password: String,
+}
+
+/// Parses `--create-admin --name <name> --email <email> --password <password>`.
+/// Returns `Ok(None)` when `--create-admin` is absent so the server starts as usual.
+pub fn parse_create_admin(args: &[String]) -> Result<Option<CreateAdminArgs>, String> {
+    if !args.iter().any(|arg| arg == "--create-admin") {
+        return Ok(None);
+    }
+
+    let value_of = |flag: &str| -> Result<String, String> {
+        args.iter()
+            .position(|arg| arg == flag)
+            .and_then(|i| args.get(i + 1))
+            .filter(|value| !value.starts_with("--"))
+            .cloned()
+            .ok_or_else(|| format!("{} <value> is required with --create-admin", flag))
+    };
+
+    Ok(Some(CreateAdminArgs {
+        name: value_of("--name")?,
+        email: value_of("--email")?,
+        password: value_of("--password")?,
+    }))
+}
+
+/// Inserts a verified admin account and returns its id.
+pub async fn create_admin(
+    db_client: &DBClient,
+    config: &Config,
+    args: CreateAdminArgs,
+) -> Result<uuid::Uuid, String> {
+    let name = name::trim(&args.name);
+    name::validate(&name, &config.name_policy).map_err(|e| e.to_string())?;
+    let email = Email::parse(&args.email).map_err(|e| e.to_string())?;
+    password::validate_strength(&args.password, &config.password_policy)
+        .map_err(|e| e.to_string())?;
+
+    let existing = db_client
+        .get_user(None, None, Some(&email))
+        .await
+        .map_err(|e| e.to_string())?;
+    if existing.is_some() {
+        return Err(ErrorMessage::EmailExist.to_string());
+    }
+
+    let hashed_password =
+        password::hash(&args.password, &config.argon2_params).map_err(|e| e.to_string())?;
+
+    let user = db_client
+        .save_admin_user(name, &email, hashed_password)
+        .await
+        .map_err(|e| match e {
+            // A soft-deleted account still holds the email.
+            sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+                ErrorMessage::EmailExist.to_string()
+            }
+            e => e.to_string(),
+        })?;
+
+    // The operator vouches for the address, so the admin can log in without verifying it.
+    db_client
+        .verify_user(user.id)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(user.id)
+}
@@ -1,22 +1,592 @@
+use actix_web::cookie::SameSite;
+use argon2::Params;
+use ipnet::IpNet;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::auth::TokenSource;
+use crate::error::ErrorFormat;
+use crate::logging::LogFormat;
+use crate::security_headers::FrameOptions;
+use crate::utils::{
+    self,
+    name::{NamePolicy, MAX_NAME_LENGTH},
+    password::PasswordPolicy,
+    token::JwtAlgorithm,
+};
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    pub secret: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SecurityHeadersConfig {
+    pub frame_options: FrameOptions,
+    pub content_security_policy: Option<String>,
+    /// Seconds for `Strict-Transport-Security`; only sent when TLS is enabled, and 0 turns it off.
+    pub hsts_max_age: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TracingConfig {
+    /// OTLP/HTTP traces URL, including the `/v1/traces` path.
+    pub otlp_endpoint: String,
+    pub service_name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct TwilioConfig {
+    pub account_sid: String,
+    pub auth_token: String,
+    pub from: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub from: String,
+}
+
+/// Features that can be switched off individually, gathered so the enabled set
+/// can be reported at startup.
+#[derive(Debug, Clone)]
+pub struct Features {
+    pub two_factor: bool,
+    pub phone_verification: bool,
+    pub require_verified_email: bool,
+    pub login_on_register: bool,
+    pub invite_only: bool,
+    pub check_breached_passwords: bool,
+    pub compression: bool,
+    pub security_headers: bool,
+    pub tracing: bool,
+}
+
+impl std::fmt::Display for Features {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let features = [
+            ("two_factor", self.two_factor),
+            ("phone_verification", self.phone_verification),
+            ("require_verified_email", self.require_verified_email),
+            ("login_on_register", self.login_on_register),
+            ("invite_only", self.invite_only),
+            ("check_breached_passwords", self.check_breached_passwords),
+            ("compression", self.compression),
+            ("security_headers", self.security_headers),
+            ("tracing", self.tracing),
+        ];
+        let features: Vec<String> = features
+            .iter()
+            .map(|(name, enabled)| format!("{}={}", name, if *enabled { "on" } else { "off" }))
+            .collect();
+
+        write!(f, "{}", features.join(", "))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
+    pub features: Features,
     pub database_url: String,
-    pub jwt_secret: String,
-    pub jwt_maxage: i64,
+    pub replica_database_url: Option<String>,
+    pub redis_url: Option<String>,
+    pub db_max_connections: u32,
+    pub db_min_connections: u32,
+    pub db_acquire_timeout: u64,
+    pub db_idle_timeout: u64,
+    pub db_query_timeout: u64,
+    pub run_migrations: bool,
+    pub jwt_algorithm: JwtAlgorithm,
+    pub jwt_signing_key: Vec<u8>,
+    /// Keys accepted when verifying tokens; the first pairs with `jwt_signing_key`.
+    pub jwt_verifying_keys: Vec<Vec<u8>>,
+    pub jwt_maxage_minutes: i64,
+    pub jwt_issuer: Option<String>,
+    pub jwt_audience: Option<String>,
+    pub jwt_require_issuer_audience: bool,
+    pub refresh_token_maxage_minutes: i64,
+    pub remember_me_maxage_minutes: i64,
+    pub impersonation_token_maxage_minutes: i64,
+    pub token_refresh_threshold_seconds: i64,
+    pub idempotency_key_maxage_minutes: i64,
+    pub verification_token_maxage_minutes: i64,
+    pub invitation_maxage_minutes: i64,
+    pub password_reset_token_maxage_minutes: i64,
+    pub trust_proxy: bool,
+    pub totp_issuer: String,
+    pub argon2_params: Params,
+    pub password_policy: PasswordPolicy,
+    pub name_policy: NamePolicy,
+    pub password_history_depth: usize,
+    pub account_deletion_grace_days: i64,
+    pub photo_upload_dir: String,
+    pub photo_max_size: usize,
+    pub max_body_size: usize,
+    pub smtp: Option<SmtpConfig>,
+    /// Without SMTP, also log email bodies, reset and verification tokens included. Development only.
+    pub log_email_bodies: bool,
+    pub twilio: Option<TwilioConfig>,
+    pub phone_verification_code_maxage_minutes: i64,
+    pub webhook: Option<WebhookConfig>,
+    pub token_sources: Vec<TokenSource>,
+    pub cors_allowed_origins: Vec<String>,
+    pub rate_limit_allowlist: Vec<IpNet>,
+    pub cors_permissive: bool,
+    pub shutdown_timeout: u64,
+    pub metrics_port: Option<u16>,
+    pub user_batch_max_size: usize,
+    pub introspection_secret: Option<String>,
+    pub cookie_secure: bool,
+    pub cookie_same_site: SameSite,
+    pub cookie_domain: Option<String>,
+    pub security_headers: SecurityHeadersConfig,
+    pub host: String,
+    pub tls: Option<TlsConfig>,
+    pub log_format: LogFormat,
+    /// Where spans are exported when `features.tracing` is on.
+    pub tracing: TracingConfig,
+    /// Requests slower than this are logged at WARN; 0 turns the log off.
+    pub slow_request_threshold_ms: u64,
+    pub error_format: ErrorFormat,
     pub port: u16,
 }
 
+/// Every problem found in the environment, reported together so a misconfigured
+/// deployment can be fixed in one pass.
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for error in &self.0 {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// HMAC keys shorter than the hash output (256 bits for HS256) weaken the signature.
+const MIN_JWT_SECRET_LENGTH: usize = 32;
+
+/// Reads environment variables, recording missing or unparsable ones instead of panicking.
+#[derive(Default)]
+struct EnvReader {
+    errors: Vec<String>,
+}
+
+impl EnvReader {
+    fn required(&mut self, key: &str) -> String {
+        match std::env::var(key) {
+            Ok(value) if !value.trim().is_empty() => value,
+            _ => {
+                self.errors.push(format!("{} must be set", key));
+                String::new()
+            }
+        }
+    }
+
+    fn parse_required<T>(&mut self, key: &str) -> T
+    where
+        T: FromStr + Default,
+        T::Err: Display,
+    {
+        let value = self.required(key);
+        if value.is_empty() {
+            return T::default();
+        }
+        self.parse(key, &value).unwrap_or_default()
+    }
+
+    fn parse_or<T>(&mut self, key: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match std::env::var(key) {
+            Ok(value) => self.parse(key, &value).unwrap_or(default),
+            Err(_) => default,
+        }
+    }
+
+    fn parse_optional<T>(&mut self, key: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = std::env::var(key).ok()?;
+        self.parse(key, &value)
+    }
+
+    fn parse<T>(&mut self, key: &str, value: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match value.trim().parse::<T>() {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                self.errors.push(format!("{} has invalid value '{}': {}", key, value, e));
+                None
+            }
+        }
+    }
+
+    fn read_file(&mut self, key: &str, path: &str) -> Vec<u8> {
+        if path.is_empty() {
+            return Vec::new();
+        }
+        std::fs::read(path).unwrap_or_else(|e| {
+            self.errors.push(format!("{}: failed to read {}: {}", key, path, e));
+            Vec::new()
+        })
+    }
+}
+
 impl Config {
-    pub fn init() -> Config {
-        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-        let jwt_secret = std::env::var("JWT_SECRET_KEY").expect("JWT_SECRET_KEY must be set");
-        let jwt_maxage = std::env::var("JWT_MAXAGE").expect("JWT_MAXAGE must be set");
+    pub fn init() -> Result<Config, ConfigError> {
+        let mut env = EnvReader::default();
+
+        let database_url = env.required("DATABASE_URL");
+        // User lookups, listings and counts are read from here when set; writes stay on the primary.
+        let replica_database_url = std::env::var("REPLICA_DATABASE_URL")
+            .ok()
+            .filter(|url| !url.is_empty());
+        // Rate limit state is kept in Redis when set, so every replica shares it.
+        let redis_url = std::env::var("REDIS_URL")
+            .ok()
+            .filter(|url| !url.is_empty());
+        // Defaults match sqlx's own, except for the connection cap.
+        let db_max_connections = env.parse_or("DB_MAX_CONNECTIONS", 10);
+        let db_min_connections = env.parse_or("DB_MIN_CONNECTIONS", 0);
+        let db_acquire_timeout = env.parse_or("DB_ACQUIRE_TIMEOUT", 30);
+        let db_idle_timeout = env.parse_or("DB_IDLE_TIMEOUT", 600);
+        let db_query_timeout = env.parse_or("DB_QUERY_TIMEOUT", 30);
+        // Turn off where migrations are applied separately, e.g. by a deploy job.
+        let run_migrations = env.parse_or("RUN_MIGRATIONS", true);
+        let jwt_maxage_minutes = env.parse_required("JWT_MAXAGE");
+        let jwt_algorithm = env.parse_or("JWT_ALGORITHM", JwtAlgorithm::HS256);
+
+        let (jwt_signing_key, jwt_verifying_keys) = if jwt_algorithm.is_asymmetric() {
+            let private_key_path = env.required("JWT_PRIVATE_KEY_PATH");
+            let public_key_path = env.required("JWT_PUBLIC_KEY_PATH");
 
-        Config {
+            (
+                env.read_file("JWT_PRIVATE_KEY_PATH", &private_key_path),
+                vec![env.read_file("JWT_PUBLIC_KEY_PATH", &public_key_path)],
+            )
+        } else {
+            // The first secret signs new tokens; the rest only verify tokens signed
+            // before a rotation, until they expire.
+            let secrets: Vec<Vec<u8>> = env
+                .required("JWT_SECRET_KEY")
+                .split(',')
+                .map(str::trim)
+                .filter(|secret| !secret.is_empty())
+                .map(|secret| secret.as_bytes().to_vec())
+                .collect();
+            if secrets.iter().any(|secret| secret.len() < MIN_JWT_SECRET_LENGTH) {
+                env.errors.push(format!(
+                    "Every JWT_SECRET_KEY secret must be at least {} bytes long for {:?}",
+                    MIN_JWT_SECRET_LENGTH, jwt_algorithm
+                ));
+            }
+            (secrets.first().cloned().unwrap_or_default(), secrets)
+        };
+
+        let jwt_issuer = std::env::var("JWT_ISSUER")
+            .ok()
+            .filter(|issuer| !issuer.is_empty());
+        let jwt_audience = std::env::var("JWT_AUDIENCE")
+            .ok()
+            .filter(|audience| !audience.is_empty());
+        // Off by default so tokens issued before JWT_ISSUER/JWT_AUDIENCE were set keep working.
+        let jwt_require_issuer_audience = env.parse_or("JWT_REQUIRE_ISSUER_AUDIENCE", false);
+        if jwt_require_issuer_audience && jwt_issuer.is_none() && jwt_audience.is_none() {
+            env.errors.push(
+                "JWT_REQUIRE_ISSUER_AUDIENCE requires JWT_ISSUER or JWT_AUDIENCE to be set"
+                    .to_string(),
+            );
+        }
+
+        let refresh_token_maxage_minutes = env.parse_or("REFRESH_TOKEN_MAXAGE", 10080);
+        let remember_me_maxage_minutes = env.parse_or("REMEMBER_ME_MAXAGE", 43200);
+        let impersonation_token_maxage_minutes = env.parse_or("IMPERSONATION_TOKEN_MAXAGE", 15);
+        let token_refresh_threshold_seconds = env.parse_or("TOKEN_REFRESH_THRESHOLD", 300);
+        let features = Features {
+            two_factor: env.parse_or("TWO_FACTOR_ENABLED", true),
+            phone_verification: env.parse_or("PHONE_VERIFICATION_ENABLED", false),
+            require_verified_email: env.parse_or("REQUIRE_VERIFIED_EMAIL", false),
+            // Ignored while REQUIRE_VERIFIED_EMAIL is on, since the new account couldn't log in yet.
+            login_on_register: env.parse_or("LOGIN_ON_REGISTER", false),
+            invite_only: env.parse_or("INVITE_ONLY", false),
+            check_breached_passwords: env.parse_or("CHECK_BREACHED_PASSWORDS", false),
+            compression: env.parse_or("COMPRESSION_ENABLED", true),
+            security_headers: env.parse_or("SECURITY_HEADERS_ENABLED", true),
+            tracing: env.parse_or("TRACING_ENABLED", false),
+        };
+        let idempotency_key_maxage_minutes = env.parse_or("IDEMPOTENCY_KEY_MAXAGE", 1440);
+        let verification_token_maxage_minutes = env.parse_or("VERIFICATION_TOKEN_MAXAGE", 1440);
+        let invitation_maxage_minutes = env.parse_or("INVITATION_MAXAGE", 10080);
+        let password_reset_token_maxage_minutes = env.parse_or("PASSWORD_RESET_TOKEN_MAXAGE", 30);
+        let trust_proxy = env.parse_or("TRUST_PROXY", false);
+        let totp_issuer = std::env::var("TOTP_ISSUER").unwrap_or_else(|_| "rust_auth".to_string());
+
+        // Defaults match argon2's own (OWASP-recommended) parameters.
+        let argon2_params = Params::new(
+            env.parse_or("ARGON2_MEM_COST", Params::DEFAULT_M_COST),
+            env.parse_or("ARGON2_TIME_COST", Params::DEFAULT_T_COST),
+            env.parse_or("ARGON2_PARALLELISM", Params::DEFAULT_P_COST),
+            None,
+        )
+        .unwrap_or_else(|e| {
+            env.errors.push(format!("Invalid Argon2 parameters: {}", e));
+            Params::default()
+        });
+
+        let password_policy = PasswordPolicy {
+            min_length: env.parse_or("PASSWORD_MIN_LENGTH", 8),
+            require_uppercase: env.parse_or("PASSWORD_REQUIRE_UPPERCASE", true),
+            require_lowercase: env.parse_or("PASSWORD_REQUIRE_LOWERCASE", true),
+            require_digit: env.parse_or("PASSWORD_REQUIRE_DIGIT", true),
+            require_symbol: env.parse_or("PASSWORD_REQUIRE_SYMBOL", true),
+        };
+        let name_policy = NamePolicy {
+            min_length: env.parse_or("NAME_MIN_LENGTH", 1),
+            max_length: env.parse_or("NAME_MAX_LENGTH", MAX_NAME_LENGTH),
+        };
+        if name_policy.min_length == 0
+            || name_policy.min_length > name_policy.max_length
+            || name_policy.max_length > MAX_NAME_LENGTH
+        {
+            env.errors.push(format!(
+                "NAME_MIN_LENGTH and NAME_MAX_LENGTH must satisfy 1 <= min <= max <= {}",
+                MAX_NAME_LENGTH
+            ));
+        }
+        let password_history_depth = env.parse_or("PASSWORD_HISTORY_DEPTH", 5);
+        let account_deletion_grace_days = env.parse_or("ACCOUNT_DELETION_GRACE_DAYS", 30);
+        let photo_upload_dir =
+            std::env::var("PHOTO_UPLOAD_DIR").unwrap_or_else(|_| "uploads".to_string());
+        let photo_max_size = env.parse_or("PHOTO_MAX_SIZE", 2097152);
+        // Applies to JSON, form and raw bodies; photo uploads are bounded by PHOTO_MAX_SIZE instead.
+        let max_body_size = env.parse_or("MAX_BODY_SIZE", 65536);
+        // Email is only delivered over SMTP when SMTP_HOST is set; otherwise it is logged.
+        let smtp = std::env::var("SMTP_HOST").ok().map(|host| SmtpConfig {
+            host,
+            port: env.parse_or("SMTP_PORT", 587),
+            username: env.required("SMTP_USERNAME"),
+            password: env.required("SMTP_PASSWORD"),
+            from: env.required("SMTP_FROM"),
+        });
+        let log_email_bodies = env.parse_or("LOG_EMAIL_BODIES", false);
+        // Texts are only delivered through Twilio when TWILIO_ACCOUNT_SID is set; otherwise they are logged.
+        let twilio = std::env::var("TWILIO_ACCOUNT_SID")
+            .ok()
+            .filter(|sid| !sid.is_empty())
+            .map(|account_sid| TwilioConfig {
+                account_sid,
+                auth_token: env.required("TWILIO_AUTH_TOKEN"),
+                from: env.required("TWILIO_FROM_NUMBER"),
+            });
+        let phone_verification_code_maxage_minutes = env.parse_or("PHONE_VERIFICATION_CODE_MAXAGE", 10);
+        // Account events are only sent out when WEBHOOK_URL is set.
+        let webhook = std::env::var("WEBHOOK_URL")
+            .ok()
+            .filter(|url| !url.is_empty())
+            .map(|url| WebhookConfig {
+                url,
+                secret: env.required("WEBHOOK_SECRET"),
+            });
+        let token_sources = std::env::var("TOKEN_SOURCES")
+            .unwrap_or_else(|_| "header,cookie".to_string())
+            .split(',')
+            .filter_map(|source| env.parse::<TokenSource>("TOKEN_SOURCES", source))
+            .collect();
+        let cors_allowed_origins = std::env::var("CORS_ALLOWED_ORIGINS")
+            .unwrap_or_else(|_| "http://localhost:3000,http://localhost:8000".to_string())
+            .split(',')
+            .map(|origin| origin.trim().to_string())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+        let cors_permissive = env.parse_or("CORS_PERMISSIVE", false);
+        // For internal callers such as monitoring that would otherwise trip the rate limits.
+        let rate_limit_allowlist = std::env::var("RATE_LIMIT_ALLOWLIST")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|network| !network.is_empty())
+            .filter_map(|network| match utils::ip::parse_network(network) {
+                Ok(network) => Some(network),
+                Err(e) => {
+                    env.errors.push(format!(
+                        "RATE_LIMIT_ALLOWLIST has invalid value '{}': {}",
+                        network, e
+                    ));
+                    None
+                }
+            })
+            .collect();
+        let shutdown_timeout = env.parse_or("SHUTDOWN_TIMEOUT", 30);
+        // When set, /metrics moves off the public port onto this one.
+        let metrics_port = env.parse_optional("METRICS_PORT");
+        let user_batch_max_size = env.parse_or("USER_BATCH_MAX_SIZE", 100);
+        // Token introspection is disabled unless resource servers are given this secret.
+        let introspection_secret = std::env::var("INTROSPECTION_SECRET")
+            .ok()
+            .filter(|secret| !secret.is_empty());
+        let cookie_secure = env.parse_or("COOKIE_SECURE", true);
+        let cookie_same_site =
+            std::env::var("COOKIE_SAME_SITE").unwrap_or_else(|_| "lax".to_string());
+        let cookie_same_site = match cookie_same_site.to_lowercase().as_str() {
+            "strict" => SameSite::Strict,
+            "lax" => SameSite::Lax,
+            "none" => SameSite::None,
+            other => {
+                env.errors.push(format!(
+                    "COOKIE_SAME_SITE has invalid value '{}': expected strict, lax or none",
+                    other
+                ));
+                SameSite::Lax
+            }
+        };
+        let cookie_domain = std::env::var("COOKIE_DOMAIN").ok();
+        // Browsers drop SameSite=None cookies that aren't also Secure.
+        if cookie_same_site == SameSite::None && !cookie_secure {
+            env.errors.push("COOKIE_SAME_SITE=none requires COOKIE_SECURE=true".to_string());
+        }
+        // The default policy only forbids framing, so the bundled API docs keep loading their scripts.
+        let content_security_policy = match std::env::var("CONTENT_SECURITY_POLICY") {
+            Ok(policy) => Some(policy).filter(|policy| !policy.trim().is_empty()),
+            Err(_) => Some("frame-ancestors 'none'".to_string()),
+        };
+        if let Some(policy) = &content_security_policy {
+            if actix_web::http::header::HeaderValue::from_str(policy).is_err() {
+                env.errors.push("CONTENT_SECURITY_POLICY is not a valid header value".to_string());
+            }
+        }
+        let security_headers = SecurityHeadersConfig {
+            frame_options: env.parse_or("FRAME_OPTIONS", FrameOptions::Deny),
+            content_security_policy,
+            hsts_max_age: env.parse_or("HSTS_MAX_AGE", 31_536_000),
+        };
+        let log_format = env.parse_or("LOG_FORMAT", LogFormat::Text);
+        let slow_request_threshold_ms = env.parse_or("SLOW_REQUEST_THRESHOLD_MS", 1000);
+        let tracing = TracingConfig {
+            otlp_endpoint: std::env::var("OTLP_ENDPOINT")
+                .unwrap_or_else(|_| "http://localhost:4318/v1/traces".to_string()),
+            service_name: std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "rust_auth".to_string()),
+        };
+        if features.tracing && tracing.otlp_endpoint.parse::<actix_web::http::Uri>().is_err() {
+            env.errors.push("OTLP_ENDPOINT is not a valid URL".to_string());
+        }
+        let host = std::env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
+        // HTTPS is served directly when both are set; otherwise plain HTTP, e.g. behind a proxy.
+        let tls_cert_path = std::env::var("TLS_CERT_PATH").ok().filter(|path| !path.is_empty());
+        let tls_key_path = std::env::var("TLS_KEY_PATH").ok().filter(|path| !path.is_empty());
+        let tls = match (tls_cert_path, tls_key_path) {
+            (Some(cert_path), Some(key_path)) => {
+                for (key, path) in [("TLS_CERT_PATH", &cert_path), ("TLS_KEY_PATH", &key_path)] {
+                    if !std::path::Path::new(path).is_file() {
+                        env.errors.push(format!("{}: {} does not exist or is not a file", key, path));
+                    }
+                }
+                Some(TlsConfig { cert_path, key_path })
+            }
+            (None, None) => None,
+            _ => {
+                env.errors.push("TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string());
+                None
+            }
+        };
+        let error_format = env.parse_or("ERROR_FORMAT", ErrorFormat::Json);
+
+        if !env.errors.is_empty() {
+            return Err(ConfigError(env.errors));
+        }
+
+        Ok(Config {
+            features,
             database_url,
-            jwt_secret,
-            jwt_maxage: jwt_maxage.parse::<i64>().unwrap(),
+            replica_database_url,
+            redis_url,
+            db_max_connections,
+            db_min_connections,
+            db_acquire_timeout,
+            db_idle_timeout,
+            db_query_timeout,
+            run_migrations,
+            jwt_algorithm,
+            jwt_signing_key,
+            jwt_verifying_keys,
+            jwt_maxage_minutes,
+            jwt_issuer,
+            jwt_audience,
+            jwt_require_issuer_audience,
+            refresh_token_maxage_minutes,
+            remember_me_maxage_minutes,
+            impersonation_token_maxage_minutes,
+            token_refresh_threshold_seconds,
+            idempotency_key_maxage_minutes,
+            verification_token_maxage_minutes,
+            invitation_maxage_minutes,
+            password_reset_token_maxage_minutes,
+            trust_proxy,
+            totp_issuer,
+            argon2_params,
+            password_policy,
+            name_policy,
+            password_history_depth,
+            account_deletion_grace_days,
+            photo_upload_dir,
+            photo_max_size,
+            max_body_size,
+            smtp,
+            log_email_bodies,
+            twilio,
+            phone_verification_code_maxage_minutes,
+            webhook,
+            token_sources,
+            cors_allowed_origins,
+            rate_limit_allowlist,
+            cors_permissive,
+            shutdown_timeout,
+            metrics_port,
+            user_batch_max_size,
+            introspection_secret,
+            cookie_secure,
+            cookie_same_site,
+            cookie_domain,
+            security_headers,
+            log_format,
+            tracing,
+            slow_request_threshold_ms,
+            error_format,
+            host,
+            tls,
             port: 8000,
-        }
+        })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+impl Config {
+    /// The configuration in `.env`, which tests share.
+    pub fn for_tests() -> Config {
+        dotenv::dotenv().ok();
+        Config::init().expect("the .env configuration should be valid")
+    }
+}
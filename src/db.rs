@@ -1,16 +1,91 @@
-use crate::models::{User, UserRole};
+use crate::models::{ApiKey, AppSettings, AuditAction, AuditLog, Email, IdempotencyKey, Invitation, PasswordResetToken, PhoneNumber, PhoneVerificationCode, RefreshToken, Session, SortOrder, User, UserRole, UserSortField, UserStatus, VerificationToken};
 use async_trait::async_trait;
-use sqlx::{Pool, Postgres};
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres, QueryBuilder};
+use std::future::Future;
+use std::time::Duration;
+use tracing::instrument;
 use uuid::Uuid;
 
+/// Narrows user listings; `None` fields don't filter.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UserFilter<'a> {
+    /// Matched case-insensitively against name and email.
+    pub search: Option<&'a str>,
+    pub role: Option<UserRole>,
+    pub verified: Option<bool>,
+    /// Inclusive bounds on `created_at`.
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+/// Outcome of a role change, which is refused if it would leave no admin.
+#[derive(Debug)]
+pub enum RoleUpdate<T> {
+    Updated(T),
+    UserNotFound,
+    /// Nothing was changed, since the change would have demoted the last admin.
+    LastAdmin,
+}
+
 #[derive(Debug, Clone)]
 pub struct DBClient {
     pool: Pool<Postgres>,
+    replica: Option<Pool<Postgres>>,
+    query_timeout: Option<Duration>,
 }
 
 impl DBClient {
     pub fn new(pool: Pool<Postgres>) -> Self {
-        DBClient { pool }
+        DBClient { pool, replica: None, query_timeout: None }
+    }
+
+    /// Sends user lookups, listings and counts to `replica`. Those reads may lag
+    /// behind the primary by the replication delay; writes, transactions and the
+    /// last-admin count stay on the primary.
+    pub fn with_replica(mut self, replica: Pool<Postgres>) -> Self {
+        self.replica = Some(replica);
+        self
+    }
+
+    /// Bounds every user query, so a hung statement fails the request instead
+    /// of holding its connection until the pool runs dry.
+    pub fn with_query_timeout(mut self, query_timeout: Duration) -> Self {
+        self.query_timeout = Some(query_timeout);
+        self
+    }
+
+    /// Gives up on `query` once the query timeout passes, dropping it so its
+    /// connection is released. Reported as a timed-out I/O error.
+    async fn with_timeout<T>(
+        &self,
+        query: impl Future<Output = Result<T, sqlx::Error>> + Send,
+    ) -> Result<T, sqlx::Error> {
+        let Some(query_timeout) = self.query_timeout else {
+            return query.await;
+        };
+
+        tokio::time::timeout(query_timeout, query)
+            .await
+            .unwrap_or_else(|_| {
+                Err(sqlx::Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "query timed out",
+                )))
+            })
+    }
+
+    fn read_pool(&self) -> &Pool<Postgres> {
+        self.replica.as_ref().unwrap_or(&self.pool)
+    }
+
+    pub async fn ping(&self) -> Result<(), sqlx::Error> {
+        sqlx::query!("SELECT 1 as one").fetch_one(&self.pool).await?;
+        if let Some(replica) = &self.replica {
+            sqlx::query!("SELECT 1 as one").fetch_one(replica).await?;
+        }
+
+        Ok(())
     }
 }
 
@@ -20,7 +95,7 @@ pub trait UserExt {
         &self,
         user_id: Option<Uuid>,
         name: Option<&str>,
-        email: Option<&str>
+        email: Option<&Email>
     ) -> Result<Option<User>, sqlx::Error>;
 
     async fn get_users(
@@ -29,104 +104,2159 @@ pub trait UserExt {
         limit: usize,
     ) -> Result<Vec<User>, sqlx::Error>;
 
+    async fn get_users_after(
+        &self,
+        cursor: Option<Uuid>,
+        filter: UserFilter<'_>,
+        limit: usize,
+    ) -> Result<Vec<User>, sqlx::Error>;
+
+    async fn get_users_by_ids(&self, ids: &[Uuid]) -> Result<Vec<User>, sqlx::Error>;
+
+    async fn get_users_by_role(
+        &self,
+        role: UserRole,
+        page: u32,
+        limit: usize,
+    ) -> Result<Vec<User>, sqlx::Error>;
+
+    async fn search_users(
+        &self,
+        filter: UserFilter<'_>,
+        sort_by: UserSortField,
+        order: SortOrder,
+        page: u32,
+        limit: usize,
+    ) -> Result<Vec<User>, sqlx::Error>;
+
     async fn save_user<T: Into<String> + Send>(
         &self,
         name: T,
-        email: T,
+        email: &Email,
         password: T,
     ) -> Result<User, sqlx::Error>;
 
+    async fn verify_user(&self, user_id: Uuid) -> Result<User, sqlx::Error>;
+
+    async fn update_user_password(
+        &self,
+        user_id: Uuid,
+        password: String,
+    ) -> Result<Option<User>, sqlx::Error>;
+
+    async fn update_user_totp_secret(
+        &self,
+        user_id: Uuid,
+        totp_secret: Option<String>,
+    ) -> Result<User, sqlx::Error>;
+
+    async fn update_user_photo(&self, user_id: Uuid, photo: String) -> Result<Option<User>, sqlx::Error>;
+
+    /// Applies the given fields in a single statement, leaving `None` ones alone.
+    /// A changed email marks the account unverified, as `update_user_email` does.
+    async fn update_user_profile(
+        &self,
+        user_id: Uuid,
+        name: Option<&str>,
+        email: Option<&Email>,
+        photo: Option<&str>,
+    ) -> Result<Option<User>, sqlx::Error>;
+
+    async fn record_login(&self, user_id: Uuid, ip_address: Option<&str>) -> Result<(), sqlx::Error>;
+
+    async fn update_user_email(&self, user_id: Uuid, email: &Email) -> Result<Option<User>, sqlx::Error>;
+
+    /// Sets the phone number, which stays unverified until a texted code is confirmed.
+    async fn update_user_phone(&self, user_id: Uuid, phone: &PhoneNumber) -> Result<Option<User>, sqlx::Error>;
+
+    /// Marks the phone verified, provided it is still `phone`.
+    async fn verify_user_phone(&self, user_id: Uuid, phone: &str) -> Result<Option<User>, sqlx::Error>;
+
+    async fn enable_user_totp(&self, user_id: Uuid) -> Result<User, sqlx::Error>;
+
+    /// Records `step` as the user's last accepted TOTP time step. Returns false,
+    /// recording nothing, when a code from that step or a later one was already used.
+    async fn use_totp_step(&self, user_id: Uuid, step: i64) -> Result<bool, sqlx::Error>;
+
+    /// Changes the role, unless that would demote the last admin.
+    async fn update_user_role(
+        &self,
+        user_id: Uuid,
+        role: UserRole,
+    ) -> Result<RoleUpdate<User>, sqlx::Error>;
+
+    async fn update_user_status(
+        &self,
+        user_id: Uuid,
+        status: UserStatus,
+    ) -> Result<Option<User>, sqlx::Error>;
+
+    /// Applies every role change or none of them: nothing changes if any of the
+    /// users does not exist or the batch as a whole would leave no admin.
+    async fn update_user_roles(
+        &self,
+        roles: &[(Uuid, UserRole)],
+    ) -> Result<RoleUpdate<Vec<User>>, sqlx::Error>;
+
+    async fn count_users(&self, filter: UserFilter<'_>) -> Result<i64, sqlx::Error>;
+
+    async fn count_users_by_role(&self) -> Result<Vec<(UserRole, i64)>, sqlx::Error>;
+
+    async fn soft_delete_user(&self, user_id: Uuid) -> Result<Option<User>, sqlx::Error>;
+
+    async fn restore_user(&self, user_id: Uuid) -> Result<Option<User>, sqlx::Error>;
+
+    /// Soft-deletes the user and marks them for permanent deletion at `purge_after`.
+    async fn schedule_user_deletion(
+        &self,
+        user_id: Uuid,
+        purge_after: DateTime<Utc>,
+    ) -> Result<Option<User>, sqlx::Error>;
+
+    /// A soft-deleted user whose scheduled deletion has not happened yet.
+    async fn get_user_pending_deletion(&self, email: &Email) -> Result<Option<User>, sqlx::Error>;
+
+    async fn cancel_user_deletion(&self, user_id: Uuid) -> Result<Option<User>, sqlx::Error>;
+
+    /// Permanently deletes users past their grace period and returns their photo paths.
+    async fn purge_deleted_users(&self) -> Result<Vec<String>, sqlx::Error>;
+
     async fn save_admin_user<T: Into<String> + Send>(
         &self,
         name: T,
-        email: T,
+        email: &Email,
         password: T,
     ) -> Result<User, sqlx::Error>;
 }
 
 #[async_trait]
 impl UserExt for DBClient {
+    #[instrument(skip_all)]
     async fn get_user(
         &self,
         user_id: Option<Uuid>,
         name: Option<&str>,
-        email: Option<&str>
+        email: Option<&Email>
     ) -> Result<Option<User>, sqlx::Error> {
-        let mut user: Option<User> = None;
+        self.with_timeout(async {
+            let mut user: Option<User> = None;
 
-        if let Some(user_id) = user_id {
-            user = sqlx::query_as!(
-                User,
-                r#"SELECT id, name, email, password, photo, verified, created_at, updated_at, role as "role: UserRole" FROM users WHERE id = $1"#,
-                user_id
-            ).fetch_optional(&self.pool).await?;
-        }else if let Some(name) = name {
-            user = sqlx::query_as!(
-                User,
-                r#"SELECT id, name, email, password, photo, verified, created_at, updated_at, role as "role: UserRole" FROM users WHERE name = $1"#,
-                name
-            ).fetch_optional(&self.pool).await?;
-        } else if let Some(email) = email {
-            user = sqlx::query_as!(
-                User,
-                r#"SELECT id, name, email, password, photo, verified, created_at, updated_at, role as "role: UserRole" FROM users WHERE email = $1"#,
-                email
-            ).fetch_optional(&self.pool).await?;
-        }
+            if let Some(user_id) = user_id {
+                user = sqlx::query_as!(
+                    User,
+                    r#"SELECT id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus" FROM users WHERE id = $1 AND deleted_at IS NULL"#,
+                    user_id
+                ).fetch_optional(self.read_pool()).await?;
+            }else if let Some(name) = name {
+                // Names are not unique; a name shared by several accounts matches none of them.
+                let mut users = sqlx::query_as!(
+                    User,
+                    r#"SELECT id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus" FROM users WHERE name = $1 AND deleted_at IS NULL LIMIT 2"#,
+                    name
+                ).fetch_all(self.read_pool()).await?;
+                if users.len() == 1 {
+                    user = users.pop();
+                }
+            } else if let Some(email) = email {
+                user = sqlx::query_as!(
+                    User,
+                    r#"SELECT id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus" FROM users WHERE email = $1 AND deleted_at IS NULL"#,
+                    email.as_str()
+                ).fetch_optional(self.read_pool()).await?;
+            }
 
-        Ok(user)
+            Ok(user)
+        })
+        .await
     }
 
+    #[instrument(skip_all)]
     async fn get_users(&self, page: u32, limit: usize) -> Result<Vec<User>, sqlx::Error> {
-        let offset = (page - 1) * limit as u32;
+        self.with_timeout(async {
+            let offset = page_offset(page, limit);
 
-        let users = sqlx::query_as!(
-            User,
-            r#"SELECT id, name, email, password, photo, verified, created_at, updated_at, role as "role: UserRole" FROM users 
-            ORDER BY created_at DESC LIMIT $1 OFFSET $2"#,
-            limit as i64,
-            offset as i64,
-        ).fetch_all(&self.pool)
-        .await?;
+            let users = sqlx::query_as!(
+                User,
+                r#"SELECT id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus" FROM users 
+                WHERE deleted_at IS NULL
+                ORDER BY created_at DESC, id DESC LIMIT $1 OFFSET $2"#,
+                limit as i64,
+                offset,
+            ).fetch_all(self.read_pool())
+            .await?;
+
+            Ok(users)
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn get_users_by_role(
+        &self,
+        role: UserRole,
+        page: u32,
+        limit: usize,
+    ) -> Result<Vec<User>, sqlx::Error> {
+        self.with_timeout(async {
+            let offset = page_offset(page, limit);
+
+            let users = sqlx::query_as!(
+                User,
+                r#"SELECT id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus" FROM users 
+                WHERE role = $1 AND deleted_at IS NULL
+                ORDER BY created_at DESC, id DESC LIMIT $2 OFFSET $3"#,
+                role as UserRole,
+                limit as i64,
+                offset,
+            ).fetch_all(self.read_pool())
+            .await?;
+
+            Ok(users)
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn get_users_after(
+        &self,
+        cursor: Option<Uuid>,
+        filter: UserFilter<'_>,
+        limit: usize,
+    ) -> Result<Vec<User>, sqlx::Error> {
+        self.with_timeout(async {
+            let mut query = QueryBuilder::<Postgres>::new(format!("SELECT {} FROM users", USER_COLUMNS));
+            push_user_filters(&mut query, filter);
+            if let Some(cursor) = cursor {
+                query
+                    .push(" AND (created_at, id) < (SELECT created_at, id FROM users WHERE id = ")
+                    .push_bind(cursor)
+                    .push(")");
+            }
+            query
+                .push(" ORDER BY created_at DESC, id DESC LIMIT ")
+                .push_bind(limit as i64);
+
+            let users = query.build_query_as::<User>().fetch_all(self.read_pool()).await?;
+
+            Ok(users)
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn get_users_by_ids(&self, ids: &[Uuid]) -> Result<Vec<User>, sqlx::Error> {
+        self.with_timeout(async {
+            let users = sqlx::query_as!(
+                User,
+                r#"SELECT id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus" FROM users WHERE id = ANY($1) AND deleted_at IS NULL"#,
+                ids
+            ).fetch_all(self.read_pool())
+            .await?;
+
+            Ok(users)
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn search_users(
+        &self,
+        filter: UserFilter<'_>,
+        sort_by: UserSortField,
+        order: SortOrder,
+        page: u32,
+        limit: usize,
+    ) -> Result<Vec<User>, sqlx::Error> {
+        self.with_timeout(async {
+            let offset = page_offset(page, limit);
+
+            let mut query = QueryBuilder::<Postgres>::new(format!("SELECT {} FROM users", USER_COLUMNS));
+            push_user_filters(&mut query, filter);
+            // The sort column and direction come from allowlisted enums, so they are
+            // safe to inline; everything else is bound.
+            query
+                .push(format!(
+                    " ORDER BY {} {}, id {} LIMIT ",
+                    sort_by.to_column(),
+                    order.to_str(),
+                    order.to_str(),
+                ))
+                .push_bind(limit as i64)
+                .push(" OFFSET ")
+                .push_bind(offset);
+
+            let users = query.build_query_as::<User>().fetch_all(self.read_pool()).await?;
 
-        Ok(users)
+            Ok(users)
+        })
+        .await
     }
 
+    #[instrument(skip_all)]
     async fn save_user<T: Into<String> + Send>(
         &self,
         name: T,
-        email: T,
+        email: &Email,
         password: T,
     ) -> Result<User, sqlx::Error> {
-        let user = sqlx::query_as!(
-            User,
-            r#"INSERT INTO users (name, email, password) VALUES ($1, $2, $3) RETURNING id, name, email, password, photo, verified, created_at, updated_at, role as "role: UserRole""#,
-            name.into(),
-            email.into(),
-            password.into(),
-        ).fetch_one(&self.pool)
-        .await?;
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"INSERT INTO users (name, email, password) VALUES ($1, $2, $3) RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                name.into(),
+                email.as_str(),
+                password.into(),
+            ).fetch_one(&self.pool)
+            .await?;
 
-        Ok(user)
+            Ok(user)
+        })
+        .await
     }
 
+    #[instrument(skip_all)]
     async fn save_admin_user<T: Into<String> + Send>(
         &self,
         name: T,
-        email: T,
+        email: &Email,
         password: T,
     ) -> Result<User, sqlx::Error> {
-        let user = sqlx::query_as!(
-            User,
-            r#"INSERT INTO users (name, email, password, role) VALUES ($1, $2, $3, $4) RETURNING id, name, email, password, photo, verified, created_at, updated_at, role as "role: UserRole""#,
-            name.into(),
-            email.into(),
-            password.into(),
-            UserRole::Admin as UserRole,
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"INSERT INTO users (name, email, password, role) VALUES ($1, $2, $3, $4) RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                name.into(),
+                email.as_str(),
+                password.into(),
+                UserRole::Admin as UserRole,
+            )
+            .fetch_one(&self.pool)
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn verify_user(&self, user_id: Uuid) -> Result<User, sqlx::Error> {
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"UPDATE users SET verified = TRUE, status = CASE WHEN status = 'pending' THEN 'active' ELSE status END, updated_at = NOW() WHERE id = $1 RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                user_id
+            )
+            .fetch_one(&self.pool)
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn update_user_password(
+        &self,
+        user_id: Uuid,
+        password: String,
+    ) -> Result<Option<User>, sqlx::Error> {
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"UPDATE users SET password = $1, updated_at = NOW() WHERE id = $2 AND deleted_at IS NULL RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                password,
+                user_id
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn update_user_photo(&self, user_id: Uuid, photo: String) -> Result<Option<User>, sqlx::Error> {
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"UPDATE users SET photo = $1, updated_at = NOW() WHERE id = $2 AND deleted_at IS NULL RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                photo,
+                user_id
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn update_user_profile(
+        &self,
+        user_id: Uuid,
+        name: Option<&str>,
+        email: Option<&Email>,
+        photo: Option<&str>,
+    ) -> Result<Option<User>, sqlx::Error> {
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"UPDATE users SET
+                    name = COALESCE($1, name),
+                    verified = CASE WHEN $2::VARCHAR IS NOT NULL AND $2 <> email THEN FALSE ELSE verified END,
+                    status = CASE WHEN $2::VARCHAR IS NOT NULL AND $2 <> email AND status = 'active' THEN 'pending' ELSE status END,
+                    email = COALESCE($2, email),
+                    photo = COALESCE($3, photo),
+                    updated_at = NOW()
+                WHERE id = $4 AND deleted_at IS NULL RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                name,
+                email.map(Email::as_str),
+                photo,
+                user_id
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn record_login(&self, user_id: Uuid, ip_address: Option<&str>) -> Result<(), sqlx::Error> {
+        self.with_timeout(async {
+            sqlx::query!(
+                r#"UPDATE users SET last_login_at = NOW(), last_login_ip = $1 WHERE id = $2"#,
+                ip_address,
+                user_id
+            )
+            .execute(&self.pool)
+            .await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn update_user_email(&self, user_id: Uuid, email: &Email) -> Result<Option<User>, sqlx::Error> {
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"UPDATE users SET email = $1, verified = FALSE, status = CASE WHEN status = 'active' THEN 'pending' ELSE status END, updated_at = NOW() WHERE id = $2 AND deleted_at IS NULL RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                email.as_str(),
+                user_id
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn update_user_phone(&self, user_id: Uuid, phone: &PhoneNumber) -> Result<Option<User>, sqlx::Error> {
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"UPDATE users SET phone = $1, phone_verified = FALSE, updated_at = NOW() WHERE id = $2 AND deleted_at IS NULL RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                phone.as_str(),
+                user_id
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn verify_user_phone(&self, user_id: Uuid, phone: &str) -> Result<Option<User>, sqlx::Error> {
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"UPDATE users SET phone_verified = TRUE, updated_at = NOW() WHERE id = $1 AND phone = $2 AND deleted_at IS NULL RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                user_id,
+                phone
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn update_user_totp_secret(
+        &self,
+        user_id: Uuid,
+        totp_secret: Option<String>,
+    ) -> Result<User, sqlx::Error> {
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"UPDATE users SET totp_secret = $1, totp_enabled = FALSE, totp_last_step = NULL, updated_at = NOW() WHERE id = $2 RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                totp_secret,
+                user_id
+            )
+            .fetch_one(&self.pool)
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn enable_user_totp(&self, user_id: Uuid) -> Result<User, sqlx::Error> {
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"UPDATE users SET totp_enabled = TRUE, updated_at = NOW() WHERE id = $1 RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                user_id
+            )
+            .fetch_one(&self.pool)
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn use_totp_step(&self, user_id: Uuid, step: i64) -> Result<bool, sqlx::Error> {
+        self.with_timeout(async {
+            // A single conditional update, so two requests with the same code can't both pass.
+            let result = sqlx::query!(
+                r#"UPDATE users SET totp_last_step = $1 WHERE id = $2 AND (totp_last_step IS NULL OR totp_last_step < $1)"#,
+                step,
+                user_id
+            )
+            .execute(&self.pool)
+            .await?;
+
+            Ok(result.rows_affected() > 0)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn update_user_role(
+        &self,
+        user_id: Uuid,
+        role: UserRole,
+    ) -> Result<RoleUpdate<User>, sqlx::Error> {
+        let update = match self.update_user_roles(&[(user_id, role)]).await? {
+            RoleUpdate::Updated(mut users) => match users.pop() {
+                Some(user) => RoleUpdate::Updated(user),
+                None => RoleUpdate::UserNotFound,
+            },
+            RoleUpdate::UserNotFound => RoleUpdate::UserNotFound,
+            RoleUpdate::LastAdmin => RoleUpdate::LastAdmin,
+        };
+
+        Ok(update)
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn update_user_status(
+        &self,
+        user_id: Uuid,
+        status: UserStatus,
+    ) -> Result<Option<User>, sqlx::Error> {
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"UPDATE users SET status = $1, updated_at = NOW() WHERE id = $2 AND deleted_at IS NULL RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                status as UserStatus,
+                user_id
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn update_user_roles(
+        &self,
+        roles: &[(Uuid, UserRole)],
+    ) -> Result<RoleUpdate<Vec<User>>, sqlx::Error> {
+        self.with_timeout(async {
+            let mut tx = self.pool.begin().await?;
+
+            // Locking every admin row makes concurrent role changes queue up here,
+            // so each one counts the admins the previous ones left behind.
+            let admin_ids = sqlx::query_scalar!(
+                r#"SELECT id FROM users WHERE role = $1 AND deleted_at IS NULL ORDER BY id FOR UPDATE"#,
+                UserRole::Admin as UserRole
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let mut users = Vec::with_capacity(roles.len());
+            for (user_id, role) in roles {
+                let user = sqlx::query_as!(
+                    User,
+                    r#"UPDATE users SET role = $1, updated_at = NOW() WHERE id = $2 AND deleted_at IS NULL RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                    *role as UserRole,
+                    user_id
+                )
+                .fetch_optional(&mut *tx)
+                .await?;
+
+                let Some(user) = user else {
+                    tx.rollback().await?;
+                    return Ok(RoleUpdate::UserNotFound);
+                };
+                users.push(user);
+            }
+
+            // Checked once the whole batch is applied, so swapping the admin role
+            // between users is fine but demoting every admin is not.
+            let demotes_admin = users
+                .iter()
+                .any(|user| user.role != UserRole::Admin && admin_ids.contains(&user.id));
+            if demotes_admin {
+                let admin_count = sqlx::query_scalar!(
+                    r#"SELECT COUNT(*) as "count!" FROM users WHERE role = $1 AND deleted_at IS NULL"#,
+                    UserRole::Admin as UserRole
+                )
+                .fetch_one(&mut *tx)
+                .await?;
+
+                if admin_count < 1 {
+                    tx.rollback().await?;
+                    return Ok(RoleUpdate::LastAdmin);
+                }
+            }
+
+            tx.commit().await?;
+
+            Ok(RoleUpdate::Updated(users))
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn count_users(&self, filter: UserFilter<'_>) -> Result<i64, sqlx::Error> {
+        self.with_timeout(async {
+            let mut query = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM users");
+            push_user_filters(&mut query, filter);
+
+            let count = query.build_query_scalar::<i64>().fetch_one(self.read_pool()).await?;
+
+            Ok(count)
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn count_users_by_role(&self) -> Result<Vec<(UserRole, i64)>, sqlx::Error> {
+        self.with_timeout(async {
+            let rows = sqlx::query!(
+                r#"SELECT role as "role!: UserRole", COUNT(*) as "count!" FROM users WHERE deleted_at IS NULL GROUP BY role"#
+            )
+            .fetch_all(self.read_pool())
+            .await?;
+
+            // GROUP BY omits roles nobody holds, so fill those in with zero.
+            let counts = UserRole::ALL
+                .into_iter()
+                .map(|role| {
+                    let count = rows
+                        .iter()
+                        .find(|row| row.role == role)
+                        .map_or(0, |row| row.count);
+                    (role, count)
+                })
+                .collect();
+
+            Ok(counts)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn soft_delete_user(&self, user_id: Uuid) -> Result<Option<User>, sqlx::Error> {
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"UPDATE users SET deleted_at = NOW(), updated_at = NOW() WHERE id = $1 AND deleted_at IS NULL RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                user_id
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn restore_user(&self, user_id: Uuid) -> Result<Option<User>, sqlx::Error> {
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"UPDATE users SET deleted_at = NULL, purge_after = NULL, updated_at = NOW() WHERE id = $1 AND deleted_at IS NOT NULL RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                user_id
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn schedule_user_deletion(
+        &self,
+        user_id: Uuid,
+        purge_after: DateTime<Utc>,
+    ) -> Result<Option<User>, sqlx::Error> {
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"UPDATE users SET deleted_at = NOW(), purge_after = $1, updated_at = NOW() WHERE id = $2 AND deleted_at IS NULL RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                purge_after,
+                user_id
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn get_user_pending_deletion(&self, email: &Email) -> Result<Option<User>, sqlx::Error> {
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"SELECT id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus" FROM users WHERE email = $1 AND deleted_at IS NOT NULL AND purge_after > NOW()"#,
+                email.as_str()
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn cancel_user_deletion(&self, user_id: Uuid) -> Result<Option<User>, sqlx::Error> {
+        self.with_timeout(async {
+            let user = sqlx::query_as!(
+                User,
+                r#"UPDATE users SET deleted_at = NULL, purge_after = NULL, updated_at = NOW() WHERE id = $1 AND purge_after > NOW() RETURNING id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role as "role: UserRole", status as "status: UserStatus""#,
+                user_id
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn purge_deleted_users(&self) -> Result<Vec<String>, sqlx::Error> {
+        self.with_timeout(async {
+            let photos = sqlx::query_scalar!(
+                r#"DELETE FROM users WHERE purge_after <= NOW() RETURNING photo"#
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            Ok(photos)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+pub trait RefreshTokenExt {
+    #[allow(clippy::too_many_arguments)]
+    async fn save_refresh_token(
+        &self,
+        user_id: Uuid,
+        family_id: Uuid,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+        remember_me: Option<bool>,
+    ) -> Result<RefreshToken, sqlx::Error>;
+
+    async fn get_refresh_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<RefreshToken>, sqlx::Error>;
+
+    async fn rotate_refresh_token(
+        &self,
+        token_id: Uuid,
+        new_token_hash: &str,
+        expires_at: DateTime<Utc>,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+    ) -> Result<Option<RefreshToken>, sqlx::Error>;
+
+    async fn revoke_refresh_token_family(
+        &self,
+        family_id: Uuid,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn revoke_user_refresh_tokens(&self, user_id: Uuid) -> Result<(), sqlx::Error>;
+
+    async fn get_user_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, sqlx::Error>;
+
+    async fn revoke_user_session(&self, user_id: Uuid, family_id: Uuid) -> Result<bool, sqlx::Error>;
+}
+
+#[async_trait]
+impl RefreshTokenExt for DBClient {
+    #[allow(clippy::too_many_arguments)]
+    async fn save_refresh_token(
+        &self,
+        user_id: Uuid,
+        family_id: Uuid,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+        remember_me: Option<bool>,
+    ) -> Result<RefreshToken, sqlx::Error> {
+        let refresh_token = sqlx::query_as!(
+            RefreshToken,
+            r#"INSERT INTO refresh_tokens (user_id, family_id, token_hash, expires_at, user_agent, ip_address, remember_me) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id, user_id, family_id, token_hash, expires_at, revoked_at, user_agent, ip_address, last_used_at, created_at, remember_me"#,
+            user_id,
+            family_id,
+            token_hash,
+            expires_at,
+            user_agent,
+            ip_address,
+            remember_me,
         )
         .fetch_one(&self.pool)
         .await?;
 
-        Ok(user)
+        Ok(refresh_token)
+    }
+
+    async fn get_refresh_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<RefreshToken>, sqlx::Error> {
+        let refresh_token = sqlx::query_as!(
+            RefreshToken,
+            r#"SELECT id, user_id, family_id, token_hash, expires_at, revoked_at, user_agent, ip_address, last_used_at, created_at, remember_me FROM refresh_tokens WHERE token_hash = $1"#,
+            token_hash
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(refresh_token)
     }
-}
\ No newline at end of file
+
+    async fn rotate_refresh_token(
+        &self,
+        token_id: Uuid,
+        new_token_hash: &str,
+        expires_at: DateTime<Utc>,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+    ) -> Result<Option<RefreshToken>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Only one caller can revoke a given token, so a concurrent replay of the
+        // same token finds nothing to rotate and is treated as reuse.
+        let revoked = sqlx::query!(
+            r#"UPDATE refresh_tokens SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL RETURNING user_id, family_id, remember_me"#,
+            token_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(revoked) = revoked else {
+            tx.rollback().await?;
+            return Ok(None);
+        };
+
+        let refresh_token = sqlx::query_as!(
+            RefreshToken,
+            r#"INSERT INTO refresh_tokens (user_id, family_id, token_hash, expires_at, user_agent, ip_address, remember_me) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id, user_id, family_id, token_hash, expires_at, revoked_at, user_agent, ip_address, last_used_at, created_at, remember_me"#,
+            revoked.user_id,
+            revoked.family_id,
+            new_token_hash,
+            expires_at,
+            user_agent,
+            ip_address,
+            revoked.remember_me,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(Some(refresh_token))
+    }
+
+    async fn revoke_refresh_token_family(
+        &self,
+        family_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"UPDATE refresh_tokens SET revoked_at = NOW() WHERE family_id = $1 AND revoked_at IS NULL"#,
+            family_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn revoke_user_refresh_tokens(&self, user_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"UPDATE refresh_tokens SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL"#,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_user_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, sqlx::Error> {
+        // Each live family has exactly one unrevoked token, which carries the most
+        // recent client details; the family's first token marks when it started.
+        let sessions = sqlx::query_as!(
+            Session,
+            r#"SELECT r.family_id as id, r.user_agent, r.ip_address, r.last_used_at,
+            (SELECT MIN(f.created_at) FROM refresh_tokens f WHERE f.family_id = r.family_id) as "created_at!"
+            FROM refresh_tokens r
+            WHERE r.user_id = $1 AND r.revoked_at IS NULL AND r.expires_at > NOW()
+            ORDER BY r.last_used_at DESC"#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(sessions)
+    }
+
+    async fn revoke_user_session(&self, user_id: Uuid, family_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"UPDATE refresh_tokens SET revoked_at = NOW() WHERE user_id = $1 AND family_id = $2 AND revoked_at IS NULL AND expires_at > NOW()"#,
+            user_id,
+            family_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+pub trait RevokedTokenExt {
+    async fn revoke_token(
+        &self,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn is_token_revoked(&self, jti: &str) -> Result<bool, sqlx::Error>;
+
+    async fn delete_expired_revoked_tokens(&self) -> Result<u64, sqlx::Error>;
+}
+
+#[async_trait]
+impl RevokedTokenExt for DBClient {
+    async fn revoke_token(
+        &self,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"INSERT INTO revoked_tokens (jti, expires_at) VALUES ($1, $2) ON CONFLICT (jti) DO NOTHING"#,
+            jti,
+            expires_at,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn is_token_revoked(&self, jti: &str) -> Result<bool, sqlx::Error> {
+        let revoked = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM revoked_tokens WHERE jti = $1) as "exists!""#,
+            jti
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(revoked)
+    }
+
+    async fn delete_expired_revoked_tokens(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(r#"DELETE FROM revoked_tokens WHERE expires_at < NOW()"#)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+pub trait IdempotencyKeyExt {
+    /// Returns the key unless it is unknown or expired.
+    async fn get_idempotency_key(&self, key: &str) -> Result<Option<IdempotencyKey>, sqlx::Error>;
+
+    /// Records `key`, taking over an expired entry with the same key.
+    async fn save_idempotency_key(
+        &self,
+        key: &str,
+        request_hash: &str,
+        user_id: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn delete_expired_idempotency_keys(&self) -> Result<u64, sqlx::Error>;
+}
+
+#[async_trait]
+impl IdempotencyKeyExt for DBClient {
+    async fn get_idempotency_key(&self, key: &str) -> Result<Option<IdempotencyKey>, sqlx::Error> {
+        let idempotency_key = sqlx::query_as!(
+            IdempotencyKey,
+            r#"SELECT idempotency_key, request_hash, user_id, expires_at, created_at FROM idempotency_keys WHERE idempotency_key = $1 AND expires_at > NOW()"#,
+            key
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(idempotency_key)
+    }
+
+    async fn save_idempotency_key(
+        &self,
+        key: &str,
+        request_hash: &str,
+        user_id: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"INSERT INTO idempotency_keys (idempotency_key, request_hash, user_id, expires_at) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (idempotency_key) DO UPDATE SET request_hash = EXCLUDED.request_hash, user_id = EXCLUDED.user_id, expires_at = EXCLUDED.expires_at, created_at = NOW()
+            WHERE idempotency_keys.expires_at <= NOW()"#,
+            key,
+            request_hash,
+            user_id,
+            expires_at,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_expired_idempotency_keys(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(r#"DELETE FROM idempotency_keys WHERE expires_at < NOW()"#)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+pub trait InvitationExt {
+    async fn save_invitation(
+        &self,
+        token_hash: &str,
+        email: &Email,
+        invited_by: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Invitation, sqlx::Error>;
+
+    async fn get_invitation(&self, token_hash: &str) -> Result<Option<Invitation>, sqlx::Error>;
+
+    /// Marks the invite used if it is unused, unexpired and for `email`. Only
+    /// one of several concurrent registrations with the same token gets it.
+    async fn claim_invitation(
+        &self,
+        token_hash: &str,
+        email: &Email,
+    ) -> Result<Option<Invitation>, sqlx::Error>;
+
+    /// Undoes `claim_invitation` when the account could not be created after all.
+    async fn release_invitation(&self, invitation_id: Uuid) -> Result<(), sqlx::Error>;
+}
+
+#[async_trait]
+impl InvitationExt for DBClient {
+    async fn save_invitation(
+        &self,
+        token_hash: &str,
+        email: &Email,
+        invited_by: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Invitation, sqlx::Error> {
+        let invitation = sqlx::query_as!(
+            Invitation,
+            r#"INSERT INTO invitations (token_hash, email, invited_by, expires_at) VALUES ($1, $2, $3, $4)
+            RETURNING id, token_hash, email, invited_by, expires_at, used_at, created_at"#,
+            token_hash,
+            email.as_str(),
+            invited_by,
+            expires_at,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(invitation)
+    }
+
+    async fn get_invitation(&self, token_hash: &str) -> Result<Option<Invitation>, sqlx::Error> {
+        let invitation = sqlx::query_as!(
+            Invitation,
+            r#"SELECT id, token_hash, email, invited_by, expires_at, used_at, created_at FROM invitations WHERE token_hash = $1"#,
+            token_hash
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(invitation)
+    }
+
+    async fn claim_invitation(
+        &self,
+        token_hash: &str,
+        email: &Email,
+    ) -> Result<Option<Invitation>, sqlx::Error> {
+        let invitation = sqlx::query_as!(
+            Invitation,
+            r#"UPDATE invitations SET used_at = NOW()
+            WHERE token_hash = $1 AND email = $2 AND used_at IS NULL AND expires_at > NOW()
+            RETURNING id, token_hash, email, invited_by, expires_at, used_at, created_at"#,
+            token_hash,
+            email.as_str(),
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(invitation)
+    }
+
+    async fn release_invitation(&self, invitation_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"UPDATE invitations SET used_at = NULL WHERE id = $1"#,
+            invitation_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+pub trait AppSettingsExt {
+    /// Always read from the primary, so a change applies to the next request.
+    async fn get_app_settings(&self) -> Result<AppSettings, sqlx::Error>;
+
+    async fn set_registration_enabled(&self, enabled: bool) -> Result<AppSettings, sqlx::Error>;
+}
+
+#[async_trait]
+impl AppSettingsExt for DBClient {
+    async fn get_app_settings(&self) -> Result<AppSettings, sqlx::Error> {
+        let settings = sqlx::query_as!(
+            AppSettings,
+            r#"SELECT registration_enabled, updated_at FROM app_settings"#
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(settings)
+    }
+
+    async fn set_registration_enabled(&self, enabled: bool) -> Result<AppSettings, sqlx::Error> {
+        let settings = sqlx::query_as!(
+            AppSettings,
+            r#"UPDATE app_settings SET registration_enabled = $1, updated_at = NOW() RETURNING registration_enabled, updated_at"#,
+            enabled
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(settings)
+    }
+}
+
+#[async_trait]
+pub trait AuditLogExt {
+    async fn save_audit_event(
+        &self,
+        user_id: Option<Uuid>,
+        action: AuditAction,
+        ip_address: Option<&str>,
+        user_agent: Option<&str>,
+        details: Option<&str>,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn get_audit_log(
+        &self,
+        user_id: Option<Uuid>,
+        action: Option<AuditAction>,
+        page: u32,
+        limit: usize,
+    ) -> Result<Vec<AuditLog>, sqlx::Error>;
+
+    async fn count_audit_log(
+        &self,
+        user_id: Option<Uuid>,
+        action: Option<AuditAction>,
+    ) -> Result<i64, sqlx::Error>;
+
+    /// The user's successful and failed login attempts, newest first.
+    async fn get_login_history(
+        &self,
+        user_id: Uuid,
+        page: u32,
+        limit: usize,
+    ) -> Result<Vec<AuditLog>, sqlx::Error>;
+
+    async fn count_login_history(&self, user_id: Uuid) -> Result<i64, sqlx::Error>;
+}
+
+#[async_trait]
+impl AuditLogExt for DBClient {
+    async fn save_audit_event(
+        &self,
+        user_id: Option<Uuid>,
+        action: AuditAction,
+        ip_address: Option<&str>,
+        user_agent: Option<&str>,
+        details: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"INSERT INTO audit_log (user_id, action, ip_address, user_agent, details) VALUES ($1, $2, $3, $4, $5)"#,
+            user_id,
+            action as AuditAction,
+            ip_address,
+            user_agent,
+            details,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_audit_log(
+        &self,
+        user_id: Option<Uuid>,
+        action: Option<AuditAction>,
+        page: u32,
+        limit: usize,
+    ) -> Result<Vec<AuditLog>, sqlx::Error> {
+        let offset = page_offset(page, limit);
+
+        let entries = sqlx::query_as!(
+            AuditLog,
+            r#"SELECT id, user_id, action as "action: AuditAction", ip_address, user_agent, details, created_at FROM audit_log
+            WHERE ($1::UUID IS NULL OR user_id = $1)
+            AND ($2::audit_action IS NULL OR action = $2)
+            ORDER BY created_at DESC, id DESC LIMIT $3 OFFSET $4"#,
+            user_id,
+            action as Option<AuditAction>,
+            limit as i64,
+            offset,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(entries)
+    }
+
+    async fn count_audit_log(
+        &self,
+        user_id: Option<Uuid>,
+        action: Option<AuditAction>,
+    ) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) FROM audit_log
+            WHERE ($1::UUID IS NULL OR user_id = $1)
+            AND ($2::audit_action IS NULL OR action = $2)"#,
+            user_id,
+            action as Option<AuditAction>,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count.unwrap_or(0))
+    }
+
+    async fn get_login_history(
+        &self,
+        user_id: Uuid,
+        page: u32,
+        limit: usize,
+    ) -> Result<Vec<AuditLog>, sqlx::Error> {
+        let offset = page_offset(page, limit);
+
+        let entries = sqlx::query_as!(
+            AuditLog,
+            r#"SELECT id, user_id, action as "action: AuditAction", ip_address, user_agent, details, created_at FROM audit_log
+            WHERE user_id = $1 AND action IN ('login', 'login_failed')
+            ORDER BY created_at DESC, id DESC LIMIT $2 OFFSET $3"#,
+            user_id,
+            limit as i64,
+            offset,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(entries)
+    }
+
+    async fn count_login_history(&self, user_id: Uuid) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) FROM audit_log WHERE user_id = $1 AND action IN ('login', 'login_failed')"#,
+            user_id,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count.unwrap_or(0))
+    }
+}
+
+#[async_trait]
+pub trait VerificationTokenExt {
+    async fn save_verification_token(
+        &self,
+        user_id: Uuid,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<VerificationToken, sqlx::Error>;
+
+    async fn consume_verification_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<VerificationToken>, sqlx::Error>;
+}
+
+#[async_trait]
+impl VerificationTokenExt for DBClient {
+    async fn save_verification_token(
+        &self,
+        user_id: Uuid,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<VerificationToken, sqlx::Error> {
+        let verification_token = sqlx::query_as!(
+            VerificationToken,
+            r#"INSERT INTO verification_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, $3) RETURNING id, user_id, token_hash, expires_at, created_at"#,
+            user_id,
+            token_hash,
+            expires_at,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(verification_token)
+    }
+
+    async fn consume_verification_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<VerificationToken>, sqlx::Error> {
+        let verification_token = sqlx::query_as!(
+            VerificationToken,
+            r#"DELETE FROM verification_tokens WHERE token_hash = $1 RETURNING id, user_id, token_hash, expires_at, created_at"#,
+            token_hash
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(verification_token)
+    }
+}
+
+#[async_trait]
+pub trait PhoneVerificationCodeExt {
+    /// Stores the code for `phone`, replacing any code the user was sent before.
+    async fn save_phone_verification_code(
+        &self,
+        user_id: Uuid,
+        phone: &str,
+        code_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn get_phone_verification_code(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<PhoneVerificationCode>, sqlx::Error>;
+
+    /// Counts a wrong guess and returns the attempts made so far.
+    async fn record_phone_verification_attempt(&self, user_id: Uuid) -> Result<i32, sqlx::Error>;
+
+    async fn delete_phone_verification_code(&self, user_id: Uuid) -> Result<(), sqlx::Error>;
+}
+
+#[async_trait]
+impl PhoneVerificationCodeExt for DBClient {
+    async fn save_phone_verification_code(
+        &self,
+        user_id: Uuid,
+        phone: &str,
+        code_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"INSERT INTO phone_verification_codes (user_id, phone, code_hash, expires_at) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id) DO UPDATE SET phone = EXCLUDED.phone, code_hash = EXCLUDED.code_hash, attempts = 0, expires_at = EXCLUDED.expires_at, created_at = NOW()"#,
+            user_id,
+            phone,
+            code_hash,
+            expires_at,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_phone_verification_code(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<PhoneVerificationCode>, sqlx::Error> {
+        let code = sqlx::query_as!(
+            PhoneVerificationCode,
+            r#"SELECT user_id, phone, code_hash, attempts, expires_at, created_at FROM phone_verification_codes WHERE user_id = $1"#,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(code)
+    }
+
+    async fn record_phone_verification_attempt(&self, user_id: Uuid) -> Result<i32, sqlx::Error> {
+        let attempts = sqlx::query_scalar!(
+            r#"UPDATE phone_verification_codes SET attempts = attempts + 1 WHERE user_id = $1 RETURNING attempts"#,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(attempts.unwrap_or(0))
+    }
+
+    async fn delete_phone_verification_code(&self, user_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"DELETE FROM phone_verification_codes WHERE user_id = $1"#,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+pub trait PasswordResetTokenExt {
+    async fn save_password_reset_token(
+        &self,
+        user_id: Uuid,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<PasswordResetToken, sqlx::Error>;
+
+    async fn get_password_reset_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<PasswordResetToken>, sqlx::Error>;
+
+    async fn consume_password_reset_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<PasswordResetToken>, sqlx::Error>;
+}
+
+#[async_trait]
+impl PasswordResetTokenExt for DBClient {
+    async fn save_password_reset_token(
+        &self,
+        user_id: Uuid,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<PasswordResetToken, sqlx::Error> {
+        let password_reset_token = sqlx::query_as!(
+            PasswordResetToken,
+            r#"INSERT INTO password_reset_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, $3) RETURNING id, user_id, token_hash, expires_at, created_at"#,
+            user_id,
+            token_hash,
+            expires_at,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(password_reset_token)
+    }
+
+    async fn get_password_reset_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<PasswordResetToken>, sqlx::Error> {
+        let password_reset_token = sqlx::query_as!(
+            PasswordResetToken,
+            r#"SELECT id, user_id, token_hash, expires_at, created_at FROM password_reset_tokens WHERE token_hash = $1"#,
+            token_hash
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(password_reset_token)
+    }
+
+    async fn consume_password_reset_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<PasswordResetToken>, sqlx::Error> {
+        let password_reset_token = sqlx::query_as!(
+            PasswordResetToken,
+            r#"DELETE FROM password_reset_tokens WHERE token_hash = $1 RETURNING id, user_id, token_hash, expires_at, created_at"#,
+            token_hash
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(password_reset_token)
+    }
+}
+
+#[async_trait]
+pub trait TotpRecoveryCodeExt {
+    async fn replace_recovery_codes(
+        &self,
+        user_id: Uuid,
+        code_hashes: &[String],
+    ) -> Result<(), sqlx::Error>;
+
+    async fn use_recovery_code(
+        &self,
+        user_id: Uuid,
+        code_hash: &str,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn count_unused_recovery_codes(&self, user_id: Uuid) -> Result<i64, sqlx::Error>;
+}
+
+#[async_trait]
+impl TotpRecoveryCodeExt for DBClient {
+    async fn replace_recovery_codes(
+        &self,
+        user_id: Uuid,
+        code_hashes: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"DELETE FROM totp_recovery_codes WHERE user_id = $1"#,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"INSERT INTO totp_recovery_codes (user_id, code_hash) SELECT $1, * FROM UNNEST($2::VARCHAR[])"#,
+            user_id,
+            code_hashes,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn use_recovery_code(
+        &self,
+        user_id: Uuid,
+        code_hash: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"UPDATE totp_recovery_codes SET used_at = NOW() WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL"#,
+            user_id,
+            code_hash
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn count_unused_recovery_codes(&self, user_id: Uuid) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) FROM totp_recovery_codes WHERE user_id = $1 AND used_at IS NULL"#,
+            user_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count.unwrap_or(0))
+    }
+}
+
+#[async_trait]
+pub trait PasswordHistoryExt {
+    /// Hashes of the user's previous passwords, newest first.
+    async fn get_password_history(
+        &self,
+        user_id: Uuid,
+        limit: usize,
+    ) -> Result<Vec<String>, sqlx::Error>;
+
+    /// Records a replaced password hash and drops entries beyond the newest `depth`.
+    async fn add_password_history(
+        &self,
+        user_id: Uuid,
+        password: &str,
+        depth: usize,
+    ) -> Result<(), sqlx::Error>;
+}
+
+#[async_trait]
+impl PasswordHistoryExt for DBClient {
+    async fn get_password_history(
+        &self,
+        user_id: Uuid,
+        limit: usize,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let passwords = sqlx::query_scalar!(
+            r#"SELECT password FROM password_history WHERE user_id = $1 ORDER BY created_at DESC LIMIT $2"#,
+            user_id,
+            limit as i64
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(passwords)
+    }
+
+    async fn add_password_history(
+        &self,
+        user_id: Uuid,
+        password: &str,
+        depth: usize,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"INSERT INTO password_history (user_id, password) VALUES ($1, $2)"#,
+            user_id,
+            password
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"DELETE FROM password_history WHERE user_id = $1 AND id NOT IN (SELECT id FROM password_history WHERE user_id = $1 ORDER BY created_at DESC LIMIT $2)"#,
+            user_id,
+            depth as i64
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+pub trait ApiKeyExt {
+    async fn save_api_key(
+        &self,
+        user_id: Uuid,
+        name: &str,
+        key_prefix: &str,
+        key_hash: &str,
+        scopes: &[String],
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<ApiKey, sqlx::Error>;
+
+    /// The user's keys that have not been revoked, newest first. Expired keys are
+    /// included so users can see why a key stopped working.
+    async fn get_user_api_keys(&self, user_id: Uuid) -> Result<Vec<ApiKey>, sqlx::Error>;
+
+    /// Looks up a key that is neither revoked nor expired and marks it as used.
+    async fn use_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, sqlx::Error>;
+
+    async fn revoke_api_key(&self, user_id: Uuid, key_id: Uuid) -> Result<bool, sqlx::Error>;
+}
+
+#[async_trait]
+impl ApiKeyExt for DBClient {
+    async fn save_api_key(
+        &self,
+        user_id: Uuid,
+        name: &str,
+        key_prefix: &str,
+        key_hash: &str,
+        scopes: &[String],
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<ApiKey, sqlx::Error> {
+        let api_key = sqlx::query_as!(
+            ApiKey,
+            r#"INSERT INTO api_keys (user_id, name, key_prefix, key_hash, scopes, expires_at) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id, user_id, name, key_prefix, key_hash, scopes, expires_at, last_used_at, revoked_at, created_at"#,
+            user_id,
+            name,
+            key_prefix,
+            key_hash,
+            scopes,
+            expires_at
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(api_key)
+    }
+
+    async fn get_user_api_keys(&self, user_id: Uuid) -> Result<Vec<ApiKey>, sqlx::Error> {
+        let api_keys = sqlx::query_as!(
+            ApiKey,
+            r#"SELECT id, user_id, name, key_prefix, key_hash, scopes, expires_at, last_used_at, revoked_at, created_at FROM api_keys WHERE user_id = $1 AND revoked_at IS NULL ORDER BY created_at DESC"#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(api_keys)
+    }
+
+    async fn use_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, sqlx::Error> {
+        let api_key = sqlx::query_as!(
+            ApiKey,
+            r#"UPDATE api_keys SET last_used_at = NOW() WHERE key_hash = $1 AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > NOW()) RETURNING id, user_id, name, key_prefix, key_hash, scopes, expires_at, last_used_at, revoked_at, created_at"#,
+            key_hash
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(api_key)
+    }
+
+    async fn revoke_api_key(&self, user_id: Uuid, key_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"UPDATE api_keys SET revoked_at = NOW() WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL"#,
+            key_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+// Column list for the dynamically built user queries, which can't use the
+// `query_as!` type overrides.
+const USER_COLUMNS: &str = "id, name, email, password, photo, verified, totp_secret, totp_enabled, created_at, updated_at, deleted_at, last_login_at, last_login_ip, phone, phone_verified, role, status";
+
+/// Appends the WHERE clause for live users matching `filter`, binding only the
+/// conditions that are set.
+fn push_user_filters(query: &mut QueryBuilder<'_, Postgres>, filter: UserFilter<'_>) {
+    query.push(" WHERE deleted_at IS NULL");
+
+    if let Some(search) = filter.search {
+        // Wildcards in the search text are matched literally.
+        let search = search.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+        let pattern = format!("%{}%", search);
+        query
+            .push(" AND (name ILIKE ")
+            .push_bind(pattern.clone())
+            .push(" ESCAPE '\\' OR email ILIKE ")
+            .push_bind(pattern)
+            .push(" ESCAPE '\\')");
+    }
+    if let Some(role) = filter.role {
+        query.push(" AND role = ").push_bind(role);
+    }
+    if let Some(verified) = filter.verified {
+        query.push(" AND verified = ").push_bind(verified);
+    }
+    if let Some(created_after) = filter.created_after {
+        query.push(" AND created_at >= ").push_bind(created_after);
+    }
+    if let Some(created_before) = filter.created_before {
+        query.push(" AND created_at <= ").push_bind(created_before);
+    }
+}
+
+/// Rows to skip for a 1-based page; page 0 is treated as the first page instead of underflowing.
+fn page_offset(page: u32, limit: usize) -> i64 {
+    i64::from(page.saturating_sub(1)).saturating_mul(i64::try_from(limit).unwrap_or(i64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::PgPool;
+
+    fn email(address: &str) -> Email {
+        Email::parse(address).unwrap()
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn save_user_then_get_user_by_id_name_and_email(pool: PgPool) {
+        let db_client = DBClient::new(pool);
+        let saved = db_client
+            .save_user("John", &email("john@example.com"), "hashed")
+            .await
+            .unwrap();
+
+        assert_eq!(saved.email, "john@example.com");
+        assert_eq!(saved.role, UserRole::User);
+        assert!(!saved.verified);
+
+        let by_id = db_client.get_user(Some(saved.id), None, None).await.unwrap();
+        let by_name = db_client.get_user(None, Some("John"), None).await.unwrap();
+        let by_email = db_client
+            .get_user(None, None, Some(&email("john@example.com")))
+            .await
+            .unwrap();
+
+        assert_eq!(by_id.map(|user| user.id), Some(saved.id));
+        assert_eq!(by_name.map(|user| user.id), Some(saved.id));
+        assert_eq!(by_email.map(|user| user.id), Some(saved.id));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn get_user_returns_none_for_unknown_user(pool: PgPool) {
+        let db_client = DBClient::new(pool);
+
+        let user = db_client.get_user(Some(Uuid::new_v4()), None, None).await.unwrap();
+
+        assert!(user.is_none());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn save_user_rejects_duplicate_email(pool: PgPool) {
+        let db_client = DBClient::new(pool);
+        db_client
+            .save_user("John", &email("john@example.com"), "hashed")
+            .await
+            .unwrap();
+
+        let result = db_client
+            .save_user("Johnny", &email("john@example.com"), "hashed")
+            .await;
+
+        match result {
+            Err(sqlx::Error::Database(db_err)) => assert!(db_err.is_unique_violation()),
+            other => panic!("expected a unique violation, got {:?}", other.map(|user| user.id)),
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn update_user_password_and_photo(pool: PgPool) {
+        let db_client = DBClient::new(pool);
+        let user = db_client
+            .save_user("John", &email("john@example.com"), "hashed")
+            .await
+            .unwrap();
+
+        let updated = db_client
+            .update_user_password(user.id, "rehashed".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(updated.password, "rehashed");
+
+        let updated = db_client
+            .update_user_photo(user.id, "photo.png".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(updated.photo, "photo.png");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn update_user_email_marks_the_user_unverified(pool: PgPool) {
+        let db_client = DBClient::new(pool);
+        let user = db_client
+            .save_user("John", &email("john@example.com"), "hashed")
+            .await
+            .unwrap();
+        db_client.verify_user(user.id).await.unwrap();
+
+        let updated = db_client
+            .update_user_email(user.id, &email("johnny@example.com"))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(updated.email, "johnny@example.com");
+        assert!(!updated.verified);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn update_user_profile_leaves_unset_fields_alone(pool: PgPool) {
+        let db_client = DBClient::new(pool);
+        let user = db_client
+            .save_user("John", &email("john@example.com"), "hashed")
+            .await
+            .unwrap();
+
+        let updated = db_client
+            .update_user_profile(user.id, Some("Johnny"), None, None)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(updated.name, "Johnny");
+        assert_eq!(updated.email, user.email);
+        assert_eq!(updated.photo, user.photo);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn update_user_profile_updates_name_and_email(pool: PgPool) {
+        let db_client = DBClient::new(pool);
+        let user = db_client
+            .save_user("John", &email("john@example.com"), "hashed")
+            .await
+            .unwrap();
+        db_client.verify_user(user.id).await.unwrap();
+
+        let updated = db_client
+            .update_user_profile(user.id, Some("Johnny"), Some(&email("johnny@example.com")), Some("photo.png"))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(updated.name, "Johnny");
+        assert_eq!(updated.email, "johnny@example.com");
+        assert_eq!(updated.photo, "photo.png");
+        assert!(!updated.verified);
+        assert_eq!(updated.status, UserStatus::Pending);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn update_user_phone_leaves_the_phone_unverified(pool: PgPool) {
+        let db_client = DBClient::new(pool);
+        let user = db_client
+            .save_user("John", &email("john@example.com"), "hashed")
+            .await
+            .unwrap();
+
+        let updated = db_client
+            .update_user_phone(user.id, &PhoneNumber::parse("+1 415 555 0123").unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(updated.phone.as_deref(), Some("+14155550123"));
+        assert!(!updated.phone_verified);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn update_user_totp_secret_disables_totp_until_enabled(pool: PgPool) {
+        let db_client = DBClient::new(pool);
+        let user = db_client
+            .save_user("John", &email("john@example.com"), "hashed")
+            .await
+            .unwrap();
+
+        let updated = db_client
+            .update_user_totp_secret(user.id, Some("SECRET".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(updated.totp_secret.as_deref(), Some("SECRET"));
+        assert!(!updated.totp_enabled);
+
+        let updated = db_client.enable_user_totp(user.id).await.unwrap();
+        assert!(updated.totp_enabled);
+
+        let updated = db_client.update_user_totp_secret(user.id, None).await.unwrap();
+        assert_eq!(updated.totp_secret, None);
+        assert!(!updated.totp_enabled);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn update_user_email_rejects_an_email_already_in_use(pool: PgPool) {
+        let db_client = DBClient::new(pool);
+        db_client
+            .save_user("John", &email("john@example.com"), "hashed")
+            .await
+            .unwrap();
+        let jane = db_client
+            .save_user("Jane", &email("jane@example.com"), "hashed")
+            .await
+            .unwrap();
+
+        let result = db_client
+            .update_user_email(jane.id, &email("john@example.com"))
+            .await;
+
+        match result {
+            Err(sqlx::Error::Database(db_err)) => assert!(db_err.is_unique_violation()),
+            other => panic!("expected a unique violation, got {:?}", other.map(|user| user.map(|u| u.id))),
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn update_user_role_and_status(pool: PgPool) {
+        let db_client = DBClient::new(pool);
+        let user = db_client
+            .save_user("John", &email("john@example.com"), "hashed")
+            .await
+            .unwrap();
+
+        let RoleUpdate::Updated(updated) = db_client
+            .update_user_role(user.id, UserRole::Moderator)
+            .await
+            .unwrap()
+        else {
+            panic!("expected the role to be updated");
+        };
+        assert_eq!(updated.role, UserRole::Moderator);
+
+        let updated = db_client
+            .update_user_status(user.id, UserStatus::Suspended)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(updated.status, UserStatus::Suspended);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn update_on_unknown_user_returns_none(pool: PgPool) {
+        let db_client = DBClient::new(pool);
+
+        let updated = db_client
+            .update_user_password(Uuid::new_v4(), "rehashed".to_string())
+            .await
+            .unwrap();
+
+        assert!(updated.is_none());
+    }
+
+    #[test]
+    fn page_offset_starts_at_zero_for_the_first_page() {
+        assert_eq!(page_offset(1, 10), 0);
+        assert_eq!(page_offset(2, 10), 10);
+    }
+
+    #[test]
+    fn page_offset_treats_page_zero_as_the_first_page() {
+        assert_eq!(page_offset(0, 10), 0);
+    }
+
+    #[test]
+    fn page_offset_does_not_overflow() {
+        assert_eq!(page_offset(u32::MAX, 100), (u32::MAX as i64 - 1) * 100);
+        assert_eq!(page_offset(u32::MAX, usize::MAX), i64::MAX);
+    }
+
+    async fn save_admin(db_client: &DBClient, name: &str) -> User {
+        let user = db_client
+            .save_user(name, &email(&format!("{}@example.com", name.to_lowercase())), "hashed")
+            .await
+            .unwrap();
+        db_client.update_user_role(user.id, UserRole::Admin).await.unwrap();
+        user
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn update_user_role_refuses_to_demote_the_last_admin(pool: PgPool) {
+        let db_client = DBClient::new(pool);
+        let admin = save_admin(&db_client, "Admin").await;
+
+        let update = db_client.update_user_role(admin.id, UserRole::User).await.unwrap();
+
+        assert!(matches!(update, RoleUpdate::LastAdmin));
+        let admin = db_client.get_user(Some(admin.id), None, None).await.unwrap().unwrap();
+        assert_eq!(admin.role, UserRole::Admin);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn update_user_roles_allows_swapping_the_admin_role(pool: PgPool) {
+        let db_client = DBClient::new(pool);
+        let admin = save_admin(&db_client, "Admin").await;
+        let user = db_client
+            .save_user("John", &email("john@example.com"), "hashed")
+            .await
+            .unwrap();
+
+        let update = db_client
+            .update_user_roles(&[(admin.id, UserRole::User), (user.id, UserRole::Admin)])
+            .await
+            .unwrap();
+
+        assert!(matches!(update, RoleUpdate::Updated(_)));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn concurrent_demotions_leave_one_admin(pool: PgPool) {
+        let db_client = DBClient::new(pool);
+        let first = save_admin(&db_client, "First").await;
+        let second = save_admin(&db_client, "Second").await;
+
+        let (first_update, second_update) = futures_util::join!(
+            db_client.update_user_role(first.id, UserRole::User),
+            db_client.update_user_role(second.id, UserRole::User),
+        );
+
+        let updated = [first_update.unwrap(), second_update.unwrap()]
+            .iter()
+            .filter(|update| matches!(update, RoleUpdate::Updated(_)))
+            .count();
+        assert_eq!(updated, 1);
+        let admins = db_client
+            .count_users(UserFilter {
+                role: Some(UserRole::Admin),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(admins, 1);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn user_search_matches_wildcards_literally(pool: PgPool) {
+        let db_client = DBClient::new(pool);
+        db_client.save_user("jo_hn", &email("one@example.com"), "hashed").await.unwrap();
+        db_client.save_user("joxhn", &email("two@example.com"), "hashed").await.unwrap();
+        db_client.save_user("100% real", &email("three@example.com"), "hashed").await.unwrap();
+
+        let count = |search: &'static str| {
+            db_client.count_users(UserFilter {
+                search: Some(search),
+                ..Default::default()
+            })
+        };
+
+        assert_eq!(count("jo_hn").await.unwrap(), 1);
+        assert_eq!(count("%").await.unwrap(), 1);
+        assert_eq!(count("\\").await.unwrap(), 0);
+    }
+}
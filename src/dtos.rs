@@ -1,52 +1,328 @@
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use utoipa::{IntoParams, ToSchema};
-use validator::Validate;
+use validator::{Validate, ValidationError};
 
-use crate::models::User;
+use crate::{
+    models::{ApiKey, AuditAction, AuditLog, DEFAULT_PHOTO, Invitation, Permission, Session, SortOrder, User, UserRole, UserSortField, UserStatus},
+    utils::{name::NamePolicy, password::{PasswordPolicy, SecretPassword}, token::TokenClaims},
+};
+
+fn trim_name<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(deserializer).map(|name| crate::utils::name::trim(&name))
+}
+
+fn trim_optional_name<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer).map(|name| name.map(|name| crate::utils::name::trim(&name)))
+}
+
+/// Emails are matched case-insensitively, so they are lowercased as requests are parsed.
+fn lowercase_email<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(deserializer).map(|email| email.to_lowercase())
+}
 
 #[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
 pub struct RegisterUserDto {
-    #[validate(length(min = 1, message = "Name is required"))]
+    #[validate(custom(function = "crate::utils::name::validate", arg = "&'v_a NamePolicy"))]
+    #[serde(deserialize_with = "trim_name")]
     pub name: String,
     #[validate(
         length(min = 1, message = "Email is required"),
         email(message = "Email is invalid")
     )]
+    #[serde(deserialize_with = "lowercase_email")]
     pub email: String,
     #[validate(
-        length(min = 1, message = "Password is required"),
-        length(min = 6, message = "Password must be at least 6 characters")
+        custom(function = "crate::utils::password::validate_min_length::<1>", message = "Password is required"),
+        custom(function = "crate::utils::password::validate_strength", arg = "&'v_a PasswordPolicy")
     )]
-    pub password: String,
+    #[schema(value_type = String, format = Password)]
+    pub password: SecretPassword,
 
     #[
         validate(
-            length(min = 1, message = "Confirm Password is required"),
+            custom(function = "crate::utils::password::validate_min_length::<1>", message = "Confirm Password is required"),
             must_match(other = "password", message="passwords do not match")
         )
     ]
     #[serde(rename = "passwordConfirm")]
-    pub password_confirm: String,
+    #[schema(value_type = String, format = Password)]
+    pub password_confirm: SecretPassword,
+
+    /// Required when INVITE_ONLY is on; must be an invite sent to `email`.
+    #[serde(rename = "inviteToken", default, skip_serializing_if = "Option::is_none")]
+    pub invite_token: Option<String>,
 }
 
 #[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
 pub struct LoginUserDto {
+    /// The account's email or name. Older clients send it as `email`.
+    #[validate(length(min = 1, message = "Email or name is required"))]
+    #[serde(alias = "email", deserialize_with = "trim_name")]
+    pub identifier: String,
+    #[validate(
+        custom(function = "crate::utils::password::validate_min_length::<1>", message = "Password is required"),
+        custom(function = "crate::utils::password::validate_min_length::<6>", message = "Password must be at least 6 characters")
+    )]
+    #[schema(value_type = String, format = Password)]
+    pub password: SecretPassword,
+    #[serde(rename = "rememberMe")]
+    pub remember_me: Option<bool>,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CancelDeletionDto {
+    #[validate(length(min = 1, message = "Email is required"), email(message = "Email is invalid"))]
+    #[serde(deserialize_with = "lowercase_email")]
+    pub email: String,
+    #[validate(custom(function = "crate::utils::password::validate_min_length::<1>", message = "Password is required"))]
+    #[schema(value_type = String, format = Password)]
+    pub password: SecretPassword,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct VerifyPasswordDto {
+    #[validate(custom(function = "crate::utils::password::validate_min_length::<1>", message = "Password is required"))]
+    #[schema(value_type = String, format = Password)]
+    pub password: SecretPassword,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct VerifyPasswordResponseDto {
+    pub status: String,
+    pub valid: bool,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ForgotPasswordDto {
     #[validate(length(min = 1, message = "Email is required"), email(message = "Email is invalid"))]
+    #[serde(deserialize_with = "lowercase_email")]
     pub email: String,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ResetPasswordDto {
+    #[validate(length(min = 1, message = "Token is required"))]
+    pub token: String,
+    #[validate(
+        custom(function = "crate::utils::password::validate_min_length::<1>", message = "Password is required"),
+        custom(function = "crate::utils::password::validate_strength", arg = "&'v_a PasswordPolicy")
+    )]
+    #[schema(value_type = String, format = Password)]
+    pub password: SecretPassword,
+
+    #[
+        validate(
+            custom(function = "crate::utils::password::validate_min_length::<1>", message = "Confirm Password is required"),
+            must_match(other = "password", message="passwords do not match")
+        )
+    ]
+    #[serde(rename = "passwordConfirm")]
+    #[schema(value_type = String, format = Password)]
+    pub password_confirm: SecretPassword,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UserPasswordUpdateDto {
+    #[validate(custom(function = "crate::utils::password::validate_min_length::<1>", message = "Old password is required"))]
+    #[serde(rename = "oldPassword")]
+    #[schema(value_type = String, format = Password)]
+    pub old_password: SecretPassword,
+    #[validate(
+        custom(function = "crate::utils::password::validate_min_length::<1>", message = "New password is required"),
+        custom(function = "crate::utils::password::validate_strength", arg = "&'v_a PasswordPolicy")
+    )]
+    #[serde(rename = "newPassword")]
+    #[schema(value_type = String, format = Password)]
+    pub new_password: SecretPassword,
+    #[validate(
+        custom(function = "crate::utils::password::validate_min_length::<1>", message = "New password confirm is required"),
+        must_match(other = "new_password", message = "new passwords do not match")
+    )]
+    #[serde(rename = "newPasswordConfirm")]
+    #[schema(value_type = String, format = Password)]
+    pub new_password_confirm: SecretPassword,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AdminPasswordUpdateDto {
     #[validate(
-        length(min = 1, message = "Password is required"),
-        length(min = 6, message = "Password must be at least 6 characters")
+        custom(function = "crate::utils::password::validate_min_length::<1>", message = "New password is required"),
+        custom(function = "crate::utils::password::validate_strength", arg = "&'v_a PasswordPolicy")
     )]
-    pub password: String,
+    #[serde(rename = "newPassword")]
+    #[schema(value_type = String, format = Password)]
+    pub new_password: SecretPassword,
+    #[validate(
+        custom(function = "crate::utils::password::validate_min_length::<1>", message = "New password confirm is required"),
+        must_match(other = "new_password", message = "new passwords do not match")
+    )]
+    #[serde(rename = "newPasswordConfirm")]
+    #[schema(value_type = String, format = Password)]
+    pub new_password_confirm: SecretPassword,
+    /// Signs the user out of every session; defaults to true.
+    #[serde(rename = "revokeSessions")]
+    pub revoke_sessions: Option<bool>,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct EmailUpdateDto {
+    #[validate(length(min = 1, message = "Email is required"), email(message = "Email is invalid"))]
+    #[serde(deserialize_with = "lowercase_email")]
+    pub email: String,
+}
+
+/// Only the fields present are changed.
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ProfileUpdateDto {
+    #[validate(custom(function = "crate::utils::name::validate", arg = "&'v_a NamePolicy"))]
+    #[serde(default, deserialize_with = "trim_optional_name", skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[validate(email(message = "Email is invalid"))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    /// Only `default.png`, to remove the current photo; new photos are
+    /// uploaded with `PUT /api/users/me/photo`.
+    #[validate(custom(function = "validate_photo_reset"))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub photo: Option<String>,
+}
+
+fn validate_photo_reset(photo: &str) -> Result<(), ValidationError> {
+    if photo == DEFAULT_PHOTO {
+        return Ok(());
+    }
+
+    let mut error = ValidationError::new("photo_reset_only");
+    error.message = Some(
+        format!("Photo can only be reset to {}; upload a new one with PUT /api/users/me/photo", DEFAULT_PHOTO).into(),
+    );
+    Err(error)
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PhoneUpdateDto {
+    #[validate(length(min = 1, max = 32, message = "Phone number is required"))]
+    pub phone: String,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PhoneVerifyDto {
+    #[validate(length(equal = 6, message = "Code must be 6 digits"))]
+    pub code: String,
+}
+
+#[derive(Validate, Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RoleUpdateDto {
+    pub role: UserRole,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RoleAssignmentDto {
+    pub id: String,
+    pub role: UserRole,
+}
+
+#[derive(Validate, Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BulkRoleUpdateDto {
+    #[validate(length(min = 1, message = "At least one role assignment is required"))]
+    pub users: Vec<RoleAssignmentDto>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct StatusUpdateDto {
+    pub status: UserStatus,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PhotoUploadDto {
+    #[schema(value_type = String, format = Binary)]
+    pub photo: Vec<u8>,
 }
 
 #[derive(Serialize, Deserialize, Validate, IntoParams)]
 pub struct RequestQueryDto {
-    #[validate(range(min = 1))]
-    pub page: Option<usize>,
-    #[validate(range(min = 1, max = 50))]
+    #[validate(range(min = 1, message = "Page must be at least 1"))]
+    pub page: Option<u32>,
+    #[validate(range(min = 1, max = 100, message = "Limit must be between 1 and 100"))]
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+    #[validate(length(max = 100))]
+    pub search: Option<String>,
+    pub sort_by: Option<UserSortField>,
+    pub order: Option<SortOrder>,
+    /// Only list users with this role.
+    pub role: Option<UserRole>,
+    /// Only list users whose email is (`true`) or isn't (`false`) verified.
+    pub verified: Option<bool>,
+    /// Only list users registered at or after this RFC 3339 timestamp.
+    pub created_after: Option<DateTime<Utc>>,
+    /// Only list users registered at or before this RFC 3339 timestamp.
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, IntoParams)]
+pub struct UserExportQueryDto {
+    /// Only export users with this role.
+    pub role: Option<UserRole>,
+    /// Only export users whose email is (`true`) or isn't (`false`) verified.
+    pub verified: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Validate, IntoParams)]
+pub struct DirectoryQueryDto {
+    #[validate(range(min = 1, message = "Page must be at least 1"))]
+    pub page: Option<u32>,
+    #[validate(range(min = 1, max = 100, message = "Limit must be between 1 and 100"))]
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Validate, IntoParams)]
+pub struct LoginHistoryQueryDto {
+    #[validate(range(min = 1, message = "Page must be at least 1"))]
+    pub page: Option<u32>,
+    #[validate(range(min = 1, max = 100, message = "Limit must be between 1 and 100"))]
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Validate, IntoParams)]
+pub struct UsersByRoleQueryDto {
+    #[validate(range(min = 1, message = "Page must be at least 1"))]
+    pub page: Option<u32>,
+    #[validate(range(min = 1, max = 100, message = "Limit must be between 1 and 100"))]
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Validate, IntoParams)]
+pub struct AuditLogQueryDto {
+    #[validate(range(min = 1, message = "Page must be at least 1"))]
+    pub page: Option<u32>,
+    #[validate(range(min = 1, max = 100, message = "Limit must be between 1 and 100"))]
     pub limit: Option<usize>,
+    pub user_id: Option<String>,
+    pub action: Option<AuditAction>,
+}
+
+#[derive(Serialize, Deserialize, Validate, IntoParams)]
+pub struct EmailAvailabilityQueryDto {
+    #[validate(length(min = 1, message = "Email is required"), email(message = "Email is invalid"))]
+    #[serde(deserialize_with = "lowercase_email")]
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, Validate, IntoParams)]
+pub struct VerifyEmailQueryDto {
+    #[validate(length(min = 1, message = "Token is required"))]
+    pub token: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -55,12 +331,20 @@ pub struct FilterUserDto {
     pub name: String,
     pub email: String,
     pub role: String,
+    pub status: UserStatus,
     pub photo: String,
     pub verified: bool,
     #[serde(rename = "createdAt")]
     pub created_at: DateTime<Utc>,
     #[serde(rename = "updatedAt")]
     pub updated_at: DateTime<Utc>,
+    #[serde(rename = "lastLoginAt")]
+    pub last_login_at: Option<DateTime<Utc>>,
+    #[serde(rename = "lastLoginIp")]
+    pub last_login_ip: Option<String>,
+    pub phone: Option<String>,
+    #[serde(rename = "phoneVerified")]
+    pub phone_verified: bool,
 }
 
 impl FilterUserDto {
@@ -72,8 +356,13 @@ impl FilterUserDto {
             photo: user.photo.to_owned(),
             verified: user.verified,
             role: user.role.to_str().to_string(),
+            status: user.status,
             created_at: user.created_at.unwrap(),
             updated_at: user.updated_at.unwrap(),
+            last_login_at: user.last_login_at,
+            last_login_ip: user.last_login_ip.to_owned(),
+            phone: user.phone.to_owned(),
+            phone_verified: user.phone_verified,
         }
     }
 
@@ -82,6 +371,185 @@ impl FilterUserDto {
     }
 }
 
+/// The subset of a user that is safe to show to moderators: no email or account details.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DirectoryUserDto {
+    pub id: String,
+    pub name: String,
+    pub role: String,
+}
+
+impl DirectoryUserDto {
+    pub fn filter_users(users: &[User]) -> Vec<DirectoryUserDto> {
+        users
+            .iter()
+            .map(|user| DirectoryUserDto {
+                id: user.id.to_string(),
+                name: user.name.to_owned(),
+                role: user.role.to_str().to_string(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DirectoryResponseDto {
+    pub status: String,
+    pub users: Vec<DirectoryUserDto>,
+    pub results: usize,
+    pub page: u32,
+    pub limit: usize,
+    #[serde(rename = "totalPages")]
+    pub total_pages: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SessionDto {
+    pub id: String,
+    #[serde(rename = "userAgent")]
+    pub user_agent: Option<String>,
+    #[serde(rename = "ipAddress")]
+    pub ip_address: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: DateTime<Utc>,
+    pub current: bool,
+}
+
+impl SessionDto {
+    pub fn filter_sessions(sessions: &[Session], current_session_id: Option<&str>) -> Vec<SessionDto> {
+        sessions
+            .iter()
+            .map(|session| {
+                let id = session.id.to_string();
+                SessionDto {
+                    current: current_session_id == Some(id.as_str()),
+                    id,
+                    user_agent: session.user_agent.to_owned(),
+                    ip_address: session.ip_address.to_owned(),
+                    created_at: session.created_at,
+                    last_used_at: session.last_used_at,
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SessionListResponseDto {
+    pub status: String,
+    pub sessions: Vec<SessionDto>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AuditLogDto {
+    pub id: String,
+    #[serde(rename = "userId")]
+    pub user_id: Option<String>,
+    pub action: AuditAction,
+    #[serde(rename = "ipAddress")]
+    pub ip_address: Option<String>,
+    #[serde(rename = "userAgent")]
+    pub user_agent: Option<String>,
+    pub details: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl AuditLogDto {
+    pub fn filter_entries(entries: &[AuditLog]) -> Vec<AuditLogDto> {
+        entries
+            .iter()
+            .map(|entry| AuditLogDto {
+                id: entry.id.to_string(),
+                user_id: entry.user_id.map(|id| id.to_string()),
+                action: entry.action,
+                ip_address: entry.ip_address.to_owned(),
+                user_agent: entry.user_agent.to_owned(),
+                details: entry.details.to_owned(),
+                created_at: entry.created_at,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AuditLogListResponseDto {
+    pub status: String,
+    pub entries: Vec<AuditLogDto>,
+    pub results: usize,
+    pub page: u32,
+    pub limit: usize,
+    #[serde(rename = "totalPages")]
+    pub total_pages: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LoginAttemptDto {
+    pub id: String,
+    pub success: bool,
+    #[serde(rename = "ipAddress")]
+    pub ip_address: Option<String>,
+    #[serde(rename = "userAgent")]
+    pub user_agent: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl LoginAttemptDto {
+    pub fn filter_attempts(entries: &[AuditLog]) -> Vec<LoginAttemptDto> {
+        entries
+            .iter()
+            .map(|entry| LoginAttemptDto {
+                id: entry.id.to_string(),
+                success: entry.action == AuditAction::Login,
+                ip_address: entry.ip_address.to_owned(),
+                user_agent: entry.user_agent.to_owned(),
+                created_at: entry.created_at,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LoginHistoryResponseDto {
+    pub status: String,
+    pub attempts: Vec<LoginAttemptDto>,
+    pub results: usize,
+    pub page: u32,
+    pub limit: usize,
+    #[serde(rename = "totalPages")]
+    pub total_pages: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RoleCountDto {
+    pub role: UserRole,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RoleStatsResponseDto {
+    pub status: String,
+    pub roles: Vec<RoleCountDto>,
+    pub total: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UsersByRoleResponseDto {
+    pub status: String,
+    pub role: UserRole,
+    pub users: Vec<FilterUserDto>,
+    pub results: usize,
+    pub page: u32,
+    pub limit: usize,
+    /// Active users holding the role, across all pages.
+    pub total: i64,
+    #[serde(rename = "totalPages")]
+    pub total_pages: usize,
+}
+
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UserData {
     pub user: FilterUserDto,
@@ -98,16 +566,312 @@ pub struct UserListResponseDto {
     pub status: String,
     pub users: Vec<FilterUserDto>,
     pub results: usize,
+    pub page: u32,
+    pub limit: usize,
+    /// Users matching the filters, across all pages.
+    pub total: i64,
+    #[serde(rename = "totalPages")]
+    pub total_pages: usize,
+    #[serde(rename = "hasNext")]
+    pub has_next: bool,
+    #[serde(rename = "hasPrev")]
+    pub has_prev: bool,
+    #[serde(rename = "nextCursor")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UserBatchResponseDto {
+    pub status: String,
+    /// One entry per requested id, in request order; `null` where no user exists.
+    pub users: Vec<Option<FilterUserDto>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeletionRequestResponseDto {
+    pub status: String,
+    /// When the account is permanently deleted unless the request is cancelled first.
+    #[serde(rename = "purgeAfter")]
+    pub purge_after: DateTime<Utc>,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateInvitationDto {
+    #[validate(length(min = 1, message = "Email is required"), email(message = "Email is invalid"))]
+    #[serde(deserialize_with = "lowercase_email")]
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct InvitationDto {
+    pub id: String,
+    pub email: String,
+    #[serde(rename = "invitedBy")]
+    pub invited_by: Option<String>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: DateTime<Utc>,
+    #[serde(rename = "usedAt")]
+    pub used_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl InvitationDto {
+    pub fn filter_invitation(invitation: &Invitation) -> Self {
+        InvitationDto {
+            id: invitation.id.to_string(),
+            email: invitation.email.to_owned(),
+            invited_by: invitation.invited_by.map(|id| id.to_string()),
+            expires_at: invitation.expires_at,
+            used_at: invitation.used_at,
+            created_at: invitation.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct InvitationCreatedResponseDto {
+    pub status: String,
+    pub invitation: InvitationDto,
+    /// The invite token, also emailed to the invitee. It is not stored and
+    /// cannot be retrieved again.
+    pub token: String,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RegistrationSettingDto {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RegistrationSettingResponseDto {
+    pub status: String,
+    pub enabled: bool,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Everything stored about the user, for `GET /api/users/me/export`.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UserDataExportDto {
+    pub status: String,
+    #[serde(rename = "exportedAt")]
+    pub exported_at: DateTime<Utc>,
+    pub user: FilterUserDto,
+    #[serde(rename = "twoFactorEnabled")]
+    pub two_factor_enabled: bool,
+    pub sessions: Vec<SessionDto>,
+    #[serde(rename = "auditLog")]
+    pub audit_log: Vec<AuditLogDto>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PermissionsResponseDto {
+    pub status: String,
+    pub role: UserRole,
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Validate, Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateApiKeyDto {
+    #[validate(length(min = 1, max = 100, message = "Name must be between 1 and 100 characters"))]
+    pub name: String,
+    #[validate(length(min = 1, message = "At least one scope is required"))]
+    pub scopes: Vec<Permission>,
+    /// Leave out for a key that never expires.
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyDto {
+    pub id: String,
+    pub name: String,
+    /// The first characters of the key, to tell keys apart.
+    pub prefix: String,
+    pub scopes: Vec<Permission>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl ApiKeyDto {
+    pub fn filter_api_key(api_key: &ApiKey) -> Self {
+        ApiKeyDto {
+            id: api_key.id.to_string(),
+            name: api_key.name.to_owned(),
+            prefix: api_key.key_prefix.to_owned(),
+            scopes: api_key.permissions(),
+            expires_at: api_key.expires_at,
+            last_used_at: api_key.last_used_at,
+            created_at: api_key.created_at,
+        }
+    }
+
+    pub fn filter_api_keys(api_keys: &[ApiKey]) -> Vec<ApiKeyDto> {
+        api_keys.iter().map(ApiKeyDto::filter_api_key).collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyCreatedResponseDto {
+    pub status: String,
+    #[serde(rename = "apiKey")]
+    pub api_key: ApiKeyDto,
+    /// The full key. It is not stored and cannot be retrieved again.
+    pub key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyListResponseDto {
+    pub status: String,
+    #[serde(rename = "apiKeys")]
+    pub api_keys: Vec<ApiKeyDto>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BulkRoleUpdateResponseDto {
+    pub status: String,
+    /// The updated users, in request order.
+    pub users: Vec<FilterUserDto>,
+    pub results: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TokenClaimsResponseDto {
+    pub status: String,
+    pub claims: TokenClaims,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TokenStatusResponseDto {
+    pub status: String,
+    #[serde(rename = "expiresInSeconds")]
+    pub expires_in_seconds: i64,
+    #[serde(rename = "shouldRefresh")]
+    pub should_refresh: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IntrospectRequestDto {
+    pub token: String,
+}
+
+/// RFC 7662 introspection response; only `active` is present for inactive tokens.
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct IntrospectResponseDto {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<UserRole>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EmailAvailabilityResponseDto {
+    pub status: String,
+    pub available: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UserLoginResponseDto {
     pub status: String,
     pub token: String,
+    pub user: FilterUserDto,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TwoFactorChallengeResponseDto {
+    pub status: String,
+    #[serde(rename = "challengeToken")]
+    pub challenge_token: String,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TwoFactorLoginDto {
+    #[validate(length(min = 1, message = "Challenge token is required"))]
+    #[serde(rename = "challengeToken")]
+    pub challenge_token: String,
+    #[validate(length(min = 1, message = "Code is required"))]
+    pub code: String,
+    #[serde(rename = "rememberMe")]
+    pub remember_me: Option<bool>,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TotpCodeDto {
+    #[validate(length(equal = 6, message = "Code must be 6 digits"))]
+    pub code: String,
+}
+
+/// Proof of identity for regenerating recovery codes: a current code from the
+/// authenticator app or the account password.
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RecoveryCodesRegenerateDto {
+    #[validate(length(equal = 6, message = "Code must be 6 digits"))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[validate(custom(function = "crate::utils::password::validate_min_length::<1>", message = "Password is required"))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, format = Password)]
+    pub password: Option<SecretPassword>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RecoveryCodesStatusResponseDto {
+    pub status: String,
+    /// Recovery codes that have not been used yet.
+    pub remaining: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TotpSetupResponseDto {
+    pub status: String,
+    pub secret: String,
+    #[serde(rename = "otpauthUrl")]
+    pub otpauth_url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TotpRecoveryCodesResponseDto {
+    pub status: String,
+    #[serde(rename = "recoveryCodes")]
+    pub recovery_codes: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
 pub struct Response {
     pub status: &'static str,
     pub message: String,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn query(value: serde_json::Value) -> RequestQueryDto {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn request_query_rejects_page_zero() {
+        assert!(query(json!({ "page": 0 })).validate().is_err());
+        assert!(query(json!({ "page": 1 })).validate().is_ok());
+    }
+
+    #[test]
+    fn request_query_limits_limit_to_1_through_100() {
+        assert!(query(json!({ "limit": 0 })).validate().is_err());
+        assert!(query(json!({ "limit": 101 })).validate().is_err());
+        assert!(query(json!({ "limit": 1 })).validate().is_ok());
+        assert!(query(json!({ "limit": 100 })).validate().is_ok());
+    }
+}
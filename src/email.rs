@@ -0,0 +1,108 @@
+use async_trait::async_trait;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+
+use crate::config::SmtpConfig;
+
+#[async_trait]
+pub trait EmailSender: Send + Sync + std::fmt::Debug {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String>;
+}
+
+/// Logs messages instead of delivering them. Used when no SMTP server is
+/// configured. Bodies carry reset and verification tokens, so they are only
+/// logged when `include_body` is set, which is meant for local development.
+#[derive(Debug, Default)]
+pub struct LogEmailSender {
+    include_body: bool,
+}
+
+impl LogEmailSender {
+    pub fn new(include_body: bool) -> Self {
+        LogEmailSender { include_body }
+    }
+}
+
+#[async_trait]
+impl EmailSender for LogEmailSender {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        if self.include_body {
+            log::info!("Email to {}: {}\n{}", to, subject, body);
+        } else {
+            log::info!("Email to {}: {} (not sent, SMTP_HOST is not set)", to, subject);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SmtpEmailSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpEmailSender {
+    pub fn new(config: &SmtpConfig) -> Result<Self, String> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+            .map_err(|e| e.to_string())?
+            .port(config.port)
+            .credentials(Credentials::new(
+                config.username.clone(),
+                config.password.clone(),
+            ))
+            .build();
+        let from = config.from.parse::<Mailbox>().map_err(|e| e.to_string())?;
+
+        Ok(SmtpEmailSender { transport, from })
+    }
+}
+
+#[async_trait]
+impl EmailSender for SmtpEmailSender {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to.parse::<Mailbox>().map_err(|e| e.to_string())?)
+            .subject(subject)
+            .body(body.to_string())
+            .map_err(|e| e.to_string())?;
+
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+}
+
+/// A message handed to `RecordingEmailSender`.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct SentEmail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Keeps every message so tests can check what would have been sent.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct RecordingEmailSender {
+    pub sent: std::sync::Mutex<Vec<SentEmail>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl EmailSender for RecordingEmailSender {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        self.sent.lock().unwrap().push(SentEmail {
+            to: to.to_string(),
+            subject: subject.to_string(),
+            body: body.to_string(),
+        });
+        Ok(())
+    }
+}
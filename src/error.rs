@@ -1,15 +1,73 @@
+use std::collections::BTreeMap;
 use std::fmt;
 
-use actix_web::{HttpResponse, ResponseError};
+use std::sync::OnceLock;
+
+use actix_web::error::{JsonPayloadError, UrlencodedError};
+use actix_web::{http::{header, StatusCode}, HttpRequest, HttpResponse, ResponseError};
 use serde::{Deserialize, Serialize};
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+use crate::request_id;
+
+/// Body shape used for error responses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorFormat {
+    /// `{status, message, requestId}`
+    Json,
+    /// RFC 7807 `application/problem+json`
+    Problem,
+}
+
+impl std::str::FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "json" => Ok(ErrorFormat::Json),
+            "problem" => Ok(ErrorFormat::Problem),
+            other => Err(format!("Unsupported error format: {}", other)),
+        }
+    }
+}
 
-use crate::dtos::Response;
+// `ResponseError` has no access to app state, so the configured format is kept here.
+static ERROR_FORMAT: OnceLock<ErrorFormat> = OnceLock::new();
 
+/// Sets the error body shape for the life of the process; later calls are ignored.
+pub fn set_error_format(format: ErrorFormat) {
+    let _ = ERROR_FORMAT.set(format);
+}
+
+fn error_format() -> ErrorFormat {
+    ERROR_FORMAT.get().copied().unwrap_or(ErrorFormat::Json)
+}
+
+/// Validation messages keyed by field path, e.g. `{"email": ["Email is invalid"]}`.
+pub type FieldErrors = BTreeMap<String, Vec<String>>;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ErrorResponse {
     pub status: String,
     pub message: String,
+    #[serde(rename = "requestId", skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub errors: Option<FieldErrors>,
+}
+
+/// RFC 7807 problem details, with the request id as an extension member.
+#[derive(Debug, Serialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    #[serde(rename = "requestId", skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<FieldErrors>,
 }
 
 impl fmt::Display for ErrorResponse {
@@ -31,17 +89,76 @@ pub enum ErrorMessage {
     UserNoLongerExist,
     TokenNotProvided,
     PermissionDenied,
+    RefreshTokenReused,
+    EmailNotVerified,
+    InvalidVerificationToken,
+    InvalidPasswordResetToken,
+    TooManyRequests,
+    InvalidTotpCode,
+    TwoFactorAlreadyEnabled,
+    TwoFactorNotSetUp,
+    UserNotFound,
+    InvalidUserId,
+    InvalidRole,
+    InvalidPhoneNumber,
+    PhoneNotSet,
+    InvalidPhoneVerificationCode,
+    PhoneVerificationUnavailable,
+    LastAdminDemotion,
+    InvalidCursor,
+    BreachedPassword,
+    PhotoRequired,
+    UnsupportedPhotoType,
+    PhotoTooLarge(usize),
+    InvalidSessionId,
+    SessionNotFound,
+    IncorrectOldPassword,
+    IncorrectPassword,
+    RegistrationClosed,
+    InvitationRequired,
+    InvalidInvitation,
+    InvitationExpired,
+    InvitationAlreadyUsed,
+    InvitationEmailMismatch,
+    ReauthenticationRequired,
+    DuplicateRecord,
+    RecordNotFound,
+    DatabaseUnavailable,
+    DatabaseBusy,
+    DatabaseTimeout,
+    BatchTooLarge(usize),
+    RequestBodyTooLarge(usize),
+    CannotImpersonateSelf,
+    ImpersonationNotAllowed,
+    PasswordReused(usize),
+    InvalidEmail,
+    InvalidIntrospectionCredentials,
+    AccountSuspended,
+    AccountBanned,
+    CannotChangeOwnStatus,
+    DuplicateUserId,
+    InvalidApiKey,
+    ApiKeyNotAllowed,
+    ApiKeyScopeNotGranted,
+    InvalidApiKeyId,
+    ApiKeyNotFound,
+    ApiKeyExpiryInPast,
+    ValidationFailed,
+    InvalidDateRange,
+    InvalidIdempotencyKey,
+    TwoFactorUnavailable,
+    IdempotencyKeyReused,
 }
 
-impl ToString for ErrorMessage {
-    fn to_string(&self) -> String {
-        self.to_str().to_owned()
+impl fmt::Display for ErrorMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_str())
     }
 }
 
-impl Into<String> for ErrorMessage {
-    fn into(self) -> String {
-        self.to_string()
+impl From<ErrorMessage> for String {
+    fn from(value: ErrorMessage) -> Self {
+        value.to_string()
     }
 }
 
@@ -59,6 +176,65 @@ impl ErrorMessage {
             ErrorMessage::InvalidToken => "Authentication token is invalid or expired".to_string(),
             ErrorMessage::TokenNotProvided => "You are not logged in, please provide a token".to_string(),
             ErrorMessage::PermissionDenied => "Your are not allowrd to perform this action".to_string(),
+            ErrorMessage::RefreshTokenReused => "Refresh token has already been used, please log in again".to_string(),
+            ErrorMessage::EmailNotVerified => "Please verify your email address before logging in".to_string(),
+            ErrorMessage::InvalidVerificationToken => "Verification token is invalid or expired".to_string(),
+            ErrorMessage::InvalidPasswordResetToken => "Password reset token is invalid or expired".to_string(),
+            ErrorMessage::TooManyRequests => "Too many requests, please try again later".to_string(),
+            ErrorMessage::InvalidTotpCode => "Two-factor authentication code is invalid".to_string(),
+            ErrorMessage::TwoFactorAlreadyEnabled => "Two-factor authentication is already enabled".to_string(),
+            ErrorMessage::TwoFactorNotSetUp => "Two-factor authentication has not been set up".to_string(),
+            ErrorMessage::UserNotFound => "User not found".to_string(),
+            ErrorMessage::InvalidUserId => "User id must be a valid UUID".to_string(),
+            ErrorMessage::InvalidPhoneNumber => "Phone number must be in international format, e.g. +14155550123".to_string(),
+            ErrorMessage::PhoneNotSet => "Set a phone number before verifying it".to_string(),
+            ErrorMessage::InvalidPhoneVerificationCode => "Invalid or expired verification code".to_string(),
+            ErrorMessage::PhoneVerificationUnavailable => "Phone verification is disabled on this server".to_string(),
+            ErrorMessage::InvalidRole => "Role must be one of user, moderator or admin".to_string(),
+            ErrorMessage::LastAdminDemotion => "Cannot change the role of the last remaining admin".to_string(),
+            ErrorMessage::InvalidCursor => "Pagination cursor is invalid".to_string(),
+            ErrorMessage::BreachedPassword => "This password has appeared in a data breach, please choose another".to_string(),
+            ErrorMessage::PhotoRequired => "A photo file is required".to_string(),
+            ErrorMessage::UnsupportedPhotoType => "Photo must be a JPEG or PNG image".to_string(),
+            ErrorMessage::PhotoTooLarge(max_size) => format!("Photo must not be larger than {} bytes", max_size),
+            ErrorMessage::InvalidSessionId => "Session id is invalid".to_string(),
+            ErrorMessage::SessionNotFound => "Session not found".to_string(),
+            ErrorMessage::IncorrectOldPassword => "Old password is incorrect".to_string(),
+            ErrorMessage::IncorrectPassword => "Password is incorrect".to_string(),
+            ErrorMessage::RegistrationClosed => "New registrations are currently closed".to_string(),
+            ErrorMessage::InvitationRequired => "Registration is by invitation only".to_string(),
+            ErrorMessage::InvalidInvitation => "Invitation is invalid".to_string(),
+            ErrorMessage::InvitationExpired => "Invitation has expired".to_string(),
+            ErrorMessage::InvitationAlreadyUsed => "Invitation has already been used".to_string(),
+            ErrorMessage::InvitationEmailMismatch => "Invitation was sent to a different email address".to_string(),
+            ErrorMessage::ReauthenticationRequired => "Confirm it is you with either a code from your authenticator app or your password".to_string(),
+            ErrorMessage::DuplicateRecord => "A record with these details already exists".to_string(),
+            ErrorMessage::RecordNotFound => "The requested record was not found".to_string(),
+            ErrorMessage::DatabaseUnavailable => "Service temporarily unavailable. Please try again later".to_string(),
+            ErrorMessage::DatabaseBusy => "Service temporarily unavailable. Please try again shortly".to_string(),
+            ErrorMessage::DatabaseTimeout => "The request took too long. Please try again later".to_string(),
+            ErrorMessage::BatchTooLarge(max_size) => format!("At most {} ids can be requested at once", max_size),
+            ErrorMessage::RequestBodyTooLarge(max_size) => format!("Request body must not be larger than {} bytes", max_size),
+            ErrorMessage::CannotImpersonateSelf => "You cannot impersonate yourself".to_string(),
+            ErrorMessage::ImpersonationNotAllowed => "This action is not allowed while impersonating another user".to_string(),
+            ErrorMessage::InvalidEmail => "Email is invalid".to_string(),
+            ErrorMessage::AccountSuspended => "Your account has been suspended".to_string(),
+            ErrorMessage::AccountBanned => "Your account has been banned".to_string(),
+            ErrorMessage::CannotChangeOwnStatus => "You cannot change the status of your own account".to_string(),
+            ErrorMessage::DuplicateUserId => "Each user id may only appear once".to_string(),
+            ErrorMessage::InvalidApiKey => "API key is invalid, expired or revoked".to_string(),
+            ErrorMessage::ApiKeyNotAllowed => "API keys cannot be used for this action".to_string(),
+            ErrorMessage::ApiKeyScopeNotGranted => "API key scopes must be permissions your role grants".to_string(),
+            ErrorMessage::InvalidApiKeyId => "API key id is invalid".to_string(),
+            ErrorMessage::ApiKeyNotFound => "API key not found".to_string(),
+            ErrorMessage::ApiKeyExpiryInPast => "API key expiry must be in the future".to_string(),
+            ErrorMessage::TwoFactorUnavailable => "Two-factor authentication is disabled on this server".to_string(),
+            ErrorMessage::InvalidIdempotencyKey => "Idempotency-Key must be 1 to 255 visible ASCII characters".to_string(),
+            ErrorMessage::IdempotencyKeyReused => "Idempotency-Key was already used for a different request".to_string(),
+            ErrorMessage::InvalidDateRange => "created_after must not be later than created_before".to_string(),
+            ErrorMessage::ValidationFailed => "One or more fields are invalid".to_string(),
+            ErrorMessage::InvalidIntrospectionCredentials => "Introspection client credentials are missing or invalid".to_string(),
+            ErrorMessage::PasswordReused(depth) => format!("Password must differ from your current and last {} passwords", depth),
         }
     }
 }
@@ -67,6 +243,9 @@ impl ErrorMessage {
 pub struct HttpError {
     pub message: String,
     pub status: u16,
+    pub errors: Option<FieldErrors>,
+    /// Seconds sent in a `Retry-After` header, for errors clients can retry.
+    pub retry_after: Option<u64>,
 }
 
 impl HttpError {
@@ -74,6 +253,8 @@ impl HttpError {
         HttpError {
             message: message.into(),
             status,
+            errors: None,
+            retry_after: None,
         }
     }
 
@@ -81,6 +262,8 @@ impl HttpError {
         HttpError {
             message: message.into(),
             status: 500,
+            errors: None,
+            retry_after: None,
         }
     }
 
@@ -88,6 +271,8 @@ impl HttpError {
         HttpError {
             message: message.into(),
             status: 400,
+            errors: None,
+            retry_after: None,
         }
     }
 
@@ -95,6 +280,8 @@ impl HttpError {
         HttpError {
             message: message.into(),
             status: 409,
+            errors: None,
+            retry_after: None,
         }
     }
 
@@ -102,41 +289,202 @@ impl HttpError {
         HttpError {
             message: message.into(),
             status: 401,
+            errors: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        HttpError {
+            message: message.into(),
+            status: 403,
+            errors: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        HttpError {
+            message: message.into(),
+            status: 404,
+            errors: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn payload_too_large(message: impl Into<String>) -> Self {
+        HttpError {
+            message: message.into(),
+            status: 413,
+            errors: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn unprocessable_entity(message: impl Into<String>) -> Self {
+        HttpError {
+            message: message.into(),
+            status: 422,
+            errors: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn service_unavailable(message: impl Into<String>) -> Self {
+        HttpError {
+            message: message.into(),
+            status: 503,
+            errors: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    /// A 400 that lists every failed field, so clients can show errors inline.
+    pub fn validation(errors: ValidationErrors) -> Self {
+        let mut fields = FieldErrors::new();
+        collect_field_errors(&errors, "", &mut fields);
+
+        HttpError {
+            message: ErrorMessage::ValidationFailed.to_string(),
+            status: 400,
+            errors: Some(fields),
+            retry_after: None,
         }
     }
 
     pub fn into_http_response(self) -> HttpResponse {
-        match  self.status {
-            400 => HttpResponse::BadRequest().json(Response {
-                status: "fail",
-                message: self.message.into(),
-            }),
-            401 => HttpResponse::Unauthorized().json(Response {
-                status: "fail",
-                message: self.message.into(),
-            }),
-            409 => HttpResponse::Conflict().json(Response {
-                status: "fail",
-                message: self.message.into(),
-            }),
-            500 => HttpResponse::InternalServerError().json(Response {
-                status: "fail",
-                message: self.message.into(),
-            }),
+        let (status, body_status, message) = match self.status {
+            400 | 401 | 403 | 404 | 409 | 413 | 422 | 500 | 503 => (
+                StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+                "fail",
+                self.message,
+            ),
             _ => {
-                eprintln!(
-                    "Warning: Missing pattern match. Converted status code {} for 500.",
+                log::warn!(
+                    "Missing pattern match. Converted status code {} for 500.",
                     self.status
                 );
 
-                HttpResponse::InternalServerError().json(Response {
-                    status: "error",
-                    message: ErrorMessage::ServerError.into(),
-                })
+                (StatusCode::INTERNAL_SERVER_ERROR, "error", ErrorMessage::ServerError.into())
             }
+        };
+
+        let mut builder = HttpResponse::build(status);
+        if let Some(retry_after) = self.retry_after {
+            builder.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+
+        match error_format() {
+            ErrorFormat::Json => builder.json(ErrorResponse {
+                status: body_status.to_string(),
+                message,
+                request_id: request_id::current(),
+                errors: self.errors,
+            }),
+            ErrorFormat::Problem => builder
+                .content_type("application/problem+json")
+                .json(ProblemDetails {
+                    problem_type: "about:blank".to_string(),
+                    title: status.canonical_reason().unwrap_or("Error").to_string(),
+                    status: status.as_u16(),
+                    detail: message,
+                    request_id: request_id::current(),
+                    errors: self.errors,
+                }),
+        }
+    }
+
+}
+
+// Nested structs and lists are flattened into paths like `users[0].email`.
+fn collect_field_errors(errors: &ValidationErrors, prefix: &str, fields: &mut FieldErrors) {
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", prefix, field)
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(field_errors) => {
+                fields.entry(path).or_default().extend(field_errors.iter().map(|error| {
+                    error
+                        .message
+                        .as_ref()
+                        .map(|message| message.to_string())
+                        .unwrap_or_else(|| format!("Invalid value ({})", error.code))
+                }));
+            }
+            ValidationErrorsKind::Struct(nested) => collect_field_errors(nested, &path, fields),
+            ValidationErrorsKind::List(items) => {
+                for (index, nested) in items {
+                    collect_field_errors(nested, &format!("{}[{}]", path, index), fields);
+                }
+            }
+        }
+    }
+}
+
+/// Answers oversized JSON bodies with a 413 in the configured error format instead
+/// of actix's plain-text default. Other JSON errors keep the default handling.
+pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    match err {
+        JsonPayloadError::OverflowKnownLength { limit, .. } | JsonPayloadError::Overflow { limit } => {
+            HttpError::payload_too_large(ErrorMessage::RequestBodyTooLarge(limit)).into()
         }
+        err => err.into(),
     }
+}
 
+/// The form body counterpart of `json_error_handler`.
+pub fn form_error_handler(err: UrlencodedError, _req: &HttpRequest) -> actix_web::Error {
+    match err {
+        UrlencodedError::Overflow { limit, .. } => {
+            HttpError::payload_too_large(ErrorMessage::RequestBodyTooLarge(limit)).into()
+        }
+        err => err.into(),
+    }
+}
+
+/// Seconds clients are asked to wait after the connection pool ran dry.
+const POOL_EXHAUSTED_RETRY_AFTER: u64 = 5;
+
+// Database errors can carry SQL, constraint and connection details, so they are
+// logged here and only a generic message is sent to the client.
+impl From<sqlx::Error> for HttpError {
+    fn from(error: sqlx::Error) -> Self {
+        // Every connection was busy for the whole acquire timeout. The queries
+        // themselves are fine, so clients are told when to come back.
+        if let sqlx::Error::PoolTimedOut = error {
+            log::error!("Database pool exhausted: no connection became free in time");
+            return HttpError::service_unavailable(ErrorMessage::DatabaseBusy)
+                .with_retry_after(POOL_EXHAUSTED_RETRY_AFTER);
+        }
+
+        log::error!("Database error: {}", error);
+
+        match error {
+            sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+                HttpError::unique_constraint_voilation(ErrorMessage::DuplicateRecord)
+            }
+            sqlx::Error::RowNotFound => HttpError::not_found(ErrorMessage::RecordNotFound),
+            sqlx::Error::Io(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                HttpError::service_unavailable(ErrorMessage::DatabaseTimeout)
+            }
+            sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed => {
+                HttpError::service_unavailable(ErrorMessage::DatabaseUnavailable)
+            }
+            _ => HttpError::server_error(ErrorMessage::ServerError),
+        }
+    }
 }
 
 impl fmt::Display for HttpError {
@@ -156,4 +504,39 @@ impl ResponseError for HttpError {
         let cloned = self.clone();
         cloned.into_http_response()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::{web, App};
+
+    #[actix_web::test]
+    async fn oversized_json_body_gets_a_json_413() {
+        let app = init_service(
+            App::new()
+                .app_data(
+                    web::JsonConfig::default()
+                        .limit(16)
+                        .error_handler(json_error_handler),
+                )
+                .route(
+                    "/",
+                    web::post().to(|_: web::Json<serde_json::Value>| async { HttpResponse::Ok().finish() }),
+                ),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/")
+            .set_json(serde_json::json!({ "name": "a name well over sixteen bytes" }))
+            .to_request();
+        let res = call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = read_body_json(res).await;
+        assert_eq!(body["status"], "fail");
+        assert_eq!(body["message"], ErrorMessage::RequestBodyTooLarge(16).to_string());
+    }
+}
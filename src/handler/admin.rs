@@ -0,0 +1,576 @@
+use actix_web::{web, HttpRequest, HttpResponse, Scope};
+use serde_json::json;
+use validator::Validate;
+
+use crate::{
+    audit,
+    auth::{Authenticated, RequireAuth},
+    db::{AppSettingsExt, AuditLogExt, RoleUpdate, UserExt, UserFilter},
+    dtos::{
+        AuditLogDto, AuditLogListResponseDto, AuditLogQueryDto, BulkRoleUpdateDto,
+        BulkRoleUpdateResponseDto, CreateInvitationDto, FilterUserDto, InvitationCreatedResponseDto,
+        InvitationDto, LoginHistoryQueryDto, RegistrationSettingDto,
+        RegistrationSettingResponseDto, RoleCountDto, RoleStatsResponseDto, UserLoginResponseDto, UsersByRoleQueryDto, UsersByRoleResponseDto,
+    },
+    error::{ErrorMessage, HttpError},
+    handler::{auth::send_invitation, users::login_history_response},
+    models::{AuditAction, Email, Permission, UserRole},
+    webhook::{WebhookEvent, WebhookPayload},
+    AppState,
+};
+
+pub fn admin_handler() -> Scope {
+    web::scope("/api/admin")
+        .route(
+            "/audit-log",
+            web::get()
+                .to(get_audit_log)
+                .wrap(RequireAuth::permission(Permission::AuditLogRead)),
+        )
+        .route(
+            "/stats/roles",
+            web::get()
+                .to(get_role_stats)
+                .wrap(RequireAuth::permission(Permission::StatsRead)),
+        )
+        .route(
+            "/users/by-role/{role}",
+            web::get()
+                .to(get_users_by_role)
+                .wrap(RequireAuth::permission(Permission::UsersList)),
+        )
+        .route(
+            "/users/{id}/login-history",
+            web::get()
+                .to(get_user_login_history)
+                .wrap(RequireAuth::permission(Permission::AuditLogRead)),
+        )
+        .route(
+            "/users/roles",
+            web::put()
+                .to(update_user_roles)
+                .wrap(RequireAuth::permission(Permission::UsersUpdateRole).deny_impersonation()),
+        )
+        .route(
+            "/invitations",
+            web::post()
+                .to(create_invitation)
+                .wrap(RequireAuth::permission(Permission::UsersInvite).deny_impersonation()),
+        )
+        .route(
+            "/settings/registration",
+            web::get()
+                .to(get_registration_setting)
+                .wrap(RequireAuth::permission(Permission::SettingsManage)),
+        )
+        .route(
+            "/settings/registration",
+            web::put()
+                .to(update_registration_setting)
+                .wrap(RequireAuth::permission(Permission::SettingsManage).deny_impersonation()),
+        )
+        .route(
+            "/impersonate/{id}",
+            web::post()
+                .to(impersonate_user)
+                .wrap(RequireAuth::permission(Permission::UsersImpersonate).deny_impersonation()),
+        )
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/invitations",
+    tag = "Invitation Endpoint",
+    request_body(content = CreateInvitationDto, example = json!({"email": "johndoe@example.com"})),
+    responses(
+        (status = 201, description= "Invite created and emailed; the token is only returned here", body = InvitationCreatedResponseDto),
+        (status=400, description= "Validation Errors", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status=409, description= "User with email already exists", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn create_invitation(
+    admin: Authenticated,
+    body: web::Json<CreateInvitationDto>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    body.validate()
+        .map_err(HttpError::validation)?;
+    let email = Email::parse(&body.email).map_err(HttpError::bat_request)?;
+
+    let existing = app_state
+        .db_client
+        .get_user(None, None, Some(&email))
+        .await?;
+    if existing.is_some() {
+        return Err(HttpError::unique_constraint_voilation(ErrorMessage::EmailExist));
+    }
+
+    let (invitation, token) = send_invitation(&app_state, &admin, &email).await?;
+
+    Ok(HttpResponse::Created().json(InvitationCreatedResponseDto {
+        status: "success".to_string(),
+        invitation: InvitationDto::filter_invitation(&invitation),
+        token,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/settings/registration",
+    tag = "Settings Endpoint",
+    responses(
+        (status = 200, description= "Whether new accounts can register", body = RegistrationSettingResponseDto),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = []),
+       ("api_key" = [])
+   )
+)]
+pub async fn get_registration_setting(
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let settings = app_state.db_client.get_app_settings().await?;
+
+    Ok(HttpResponse::Ok().json(RegistrationSettingResponseDto {
+        status: "success".to_string(),
+        enabled: settings.registration_enabled,
+        updated_at: settings.updated_at,
+    }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/admin/settings/registration",
+    tag = "Settings Endpoint",
+    request_body(content = RegistrationSettingDto, example = json!({"enabled": false})),
+    responses(
+        (status = 200, description= "Registration opened or closed; applies to the next request", body = RegistrationSettingResponseDto),
+        (status=400, description= "Validation Errors", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = []),
+       ("api_key" = [])
+   )
+)]
+pub async fn update_registration_setting(
+    body: web::Json<RegistrationSettingDto>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let settings = app_state
+        .db_client
+        .set_registration_enabled(body.enabled)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(RegistrationSettingResponseDto {
+        status: "success".to_string(),
+        enabled: settings.registration_enabled,
+        updated_at: settings.updated_at,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/audit-log",
+    tag = "Audit Log Endpoint",
+    params(
+        AuditLogQueryDto
+    ),
+    responses(
+        (status = 200, description= "Audit log entries, newest first", body = AuditLogListResponseDto),
+        (status=400, description= "Invalid query parameters", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = []),
+       ("api_key" = [])
+   )
+)]
+pub async fn get_audit_log(
+    query: web::Query<AuditLogQueryDto>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let query_params: AuditLogQueryDto = query.into_inner();
+
+    query_params
+        .validate()
+        .map_err(HttpError::validation)?;
+
+    let page = query_params.page.unwrap_or(1);
+    let limit = query_params.limit.unwrap_or(20);
+    let user_id = query_params
+        .user_id
+        .as_deref()
+        .map(uuid::Uuid::parse_str)
+        .transpose()
+        .map_err(|_| HttpError::bat_request(ErrorMessage::InvalidUserId))?;
+
+    let entries = app_state
+        .db_client
+        .get_audit_log(user_id, query_params.action, page, limit)
+        .await?;
+
+    let total = app_state
+        .db_client
+        .count_audit_log(user_id, query_params.action)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(AuditLogListResponseDto {
+        status: "success".to_string(),
+        entries: AuditLogDto::filter_entries(&entries),
+        results: entries.len(),
+        page,
+        limit,
+        total_pages: (total as usize).div_ceil(limit),
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/stats/roles",
+    tag = "User Statistics Endpoint",
+    responses(
+        (status = 200, description= "Number of active users per role, including roles with none", body = RoleStatsResponseDto),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = []),
+       ("api_key" = [])
+   )
+)]
+pub async fn get_role_stats(app_state: web::Data<AppState>) -> Result<HttpResponse, HttpError> {
+    let counts = app_state.db_client.count_users_by_role().await?;
+
+    Ok(HttpResponse::Ok().json(RoleStatsResponseDto {
+        status: "success".to_string(),
+        total: counts.iter().map(|(_, count)| count).sum(),
+        roles: counts
+            .into_iter()
+            .map(|(role, count)| RoleCountDto { role, count })
+            .collect(),
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/users/by-role/{role}",
+    tag = "Users By Role Endpoint",
+    params(
+        ("role" = UserRole, Path, description = "Role to list: user, moderator or admin"),
+        UsersByRoleQueryDto
+    ),
+    responses(
+        (status = 200, description= "Active users holding the role, newest first, with the role's total", body = UsersByRoleResponseDto),
+        (status=400, description= "Invalid role or query parameters", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = []),
+       ("api_key" = [])
+   )
+)]
+pub async fn get_users_by_role(
+    path: web::Path<String>,
+    query: web::Query<UsersByRoleQueryDto>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let role: UserRole = path.parse().map_err(HttpError::bat_request)?;
+
+    query
+        .validate()
+        .map_err(HttpError::validation)?;
+
+    let page = query.page.unwrap_or(1);
+    let limit = query.limit.unwrap_or(10);
+
+    let users = app_state
+        .db_client
+        .get_users_by_role(role, page, limit)
+        .await?;
+
+    let total = app_state
+        .db_client
+        .count_users(UserFilter {
+            role: Some(role),
+            ..Default::default()
+        })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(UsersByRoleResponseDto {
+        status: "success".to_string(),
+        role,
+        users: FilterUserDto::filter_users(&users),
+        results: users.len(),
+        page,
+        limit,
+        total,
+        total_pages: (total as usize).div_ceil(limit),
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/users/{id}/login-history",
+    tag = "User Login History Endpoint",
+    params(
+        ("id" = String, Path, description = "User id"),
+        LoginHistoryQueryDto
+    ),
+    responses(
+        (status = 200, description= "The user's successful and failed login attempts, newest first", body = LoginHistoryResponseDto),
+        (status=400, description= "Invalid user id or query parameters", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status=404, description= "User not found", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = []),
+       ("api_key" = [])
+   )
+)]
+pub async fn get_user_login_history(
+    path: web::Path<String>,
+    query: web::Query<LoginHistoryQueryDto>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let user_id = uuid::Uuid::parse_str(&path.into_inner())
+        .map_err(|_| HttpError::bat_request(ErrorMessage::InvalidUserId))?;
+
+    app_state
+        .db_client
+        .get_user(Some(user_id), None, None)
+        .await?
+        .ok_or(HttpError::not_found(ErrorMessage::UserNotFound))?;
+
+    login_history_response(&app_state, user_id, &query).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/impersonate/{id}",
+    tag = "Impersonation Endpoint",
+    params(
+        ("id" = String, Path, description = "Id of the user to impersonate")
+    ),
+    responses(
+        (status = 200, description= "Short-lived access token for the user, carrying an impersonatedBy claim", body = UserLoginResponseDto),
+        (status=400, description= "Invalid user id, or the admin's own id", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status=404, description= "User not found", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn impersonate_user(
+    req: HttpRequest,
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+    admin: Authenticated,
+) -> Result<HttpResponse, HttpError> {
+    let user_id = uuid::Uuid::parse_str(&path)
+        .map_err(|_| HttpError::bat_request(ErrorMessage::InvalidUserId))?;
+
+    if user_id == admin.id {
+        return Err(HttpError::bat_request(ErrorMessage::CannotImpersonateSelf));
+    }
+
+    let user = app_state
+        .db_client
+        .get_user(Some(user_id), None, None)
+        .await?
+        .ok_or(HttpError::not_found(ErrorMessage::UserNotFound))?;
+
+    // Only an access token is issued and no cookies are set, so the admin's own
+    // session is untouched and the impersonation simply expires.
+    let token = app_state.token_service.create_impersonation_token(
+        &user.id.to_string(),
+        user.role,
+        &user.email,
+        &admin.id.to_string(),
+        app_state.env.impersonation_token_maxage_minutes,
+    )?;
+    app_state.metrics.token_issued("impersonation");
+
+    audit::record(
+        &app_state,
+        &req,
+        Some(user.id),
+        AuditAction::Impersonate,
+        Some(format!("Impersonated by {}", admin.id)),
+    );
+
+    Ok(HttpResponse::Ok().json(UserLoginResponseDto {
+        status: "success".to_string(),
+        token,
+        user: FilterUserDto::filter_user(&user),
+    }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/admin/users/roles",
+    tag = "Bulk Role Assignment Endpoint",
+    request_body(content = BulkRoleUpdateDto, description = "Roles to assign; applied all together or not at all", example = json!({"users": [{"id": "00000000-0000-0000-0000-000000000000", "role": "moderator"}]})),
+    responses(
+        (status = 200, description= "Roles updated", body = BulkRoleUpdateResponseDto),
+        (status=400, description= "Invalid or duplicate user ids, batch too large, or last admin demotion", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status=404, description= "User not found", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn update_user_roles(
+    req: HttpRequest,
+    actor: Authenticated,
+    app_state: web::Data<AppState>,
+    body: web::Json<BulkRoleUpdateDto>,
+) -> Result<HttpResponse, HttpError> {
+    body.validate()
+        .map_err(HttpError::validation)?;
+
+    let max_size = app_state.env.user_batch_max_size;
+    if body.users.len() > max_size {
+        return Err(HttpError::bat_request(ErrorMessage::BatchTooLarge(max_size)));
+    }
+
+    let mut roles = Vec::with_capacity(body.users.len());
+    for assignment in &body.users {
+        if !actor.role.can_assign(assignment.role) {
+            return Err(HttpError::forbidden(ErrorMessage::PermissionDenied));
+        }
+
+        let user_id = uuid::Uuid::parse_str(&assignment.id)
+            .map_err(|_| HttpError::bat_request(ErrorMessage::InvalidUserId))?;
+
+        if roles.iter().any(|(id, _)| *id == user_id) {
+            return Err(HttpError::bat_request(ErrorMessage::DuplicateUserId));
+        }
+        roles.push((user_id, assignment.role));
+    }
+
+    let ids: Vec<_> = roles.iter().map(|(id, _)| *id).collect();
+    let targets = app_state.db_client.get_users_by_ids(&ids).await?;
+    if targets.len() != ids.len() {
+        return Err(HttpError::not_found(ErrorMessage::UserNotFound));
+    }
+
+    let users = match app_state.db_client.update_user_roles(&roles).await? {
+        RoleUpdate::Updated(users) => users,
+        RoleUpdate::UserNotFound => return Err(HttpError::not_found(ErrorMessage::UserNotFound)),
+        RoleUpdate::LastAdmin => return Err(HttpError::bat_request(ErrorMessage::LastAdminDemotion)),
+    };
+
+    for user in &users {
+        let Some(target) = targets.iter().find(|target| target.id == user.id) else {
+            continue;
+        };
+        if target.role == user.role {
+            continue;
+        }
+
+        audit::record(
+            &app_state,
+            &req,
+            Some(user.id),
+            AuditAction::RoleChange,
+            Some(format!(
+                "Role changed from {} to {} by {}",
+                target.role.to_str(),
+                user.role.to_str(),
+                actor.id
+            )),
+        );
+        app_state.webhook_dispatcher.dispatch(WebhookPayload::new(
+            WebhookEvent::RoleChanged,
+            user.id,
+            json!({"from": target.role, "to": user.role, "changedBy": actor.id}),
+        ));
+    }
+
+    Ok(HttpResponse::Ok().json(BulkRoleUpdateResponseDto {
+        status: "success".to_string(),
+        results: users.len(),
+        users: users.iter().map(FilterUserDto::filter_user).collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test::{init_service, try_call_service, TestRequest};
+    use actix_web::App;
+    use sqlx::PgPool;
+
+    // Moderators don't hold `users:update_role`; the route is opened to them here
+    // to check the handler's own `can_assign` guard in case they ever do.
+    #[sqlx::test(migrations = "./migrations")]
+    async fn update_user_roles_refuses_roles_the_actor_cannot_assign(pool: PgPool) {
+        let app_state = AppState::for_tests(pool);
+        let moderator = app_state
+            .db_client
+            .save_user("Mod", &Email::parse("mod@example.com").unwrap(), "hashed")
+            .await
+            .unwrap();
+        app_state.db_client.verify_user(moderator.id).await.unwrap();
+        app_state.db_client.update_user_role(moderator.id, UserRole::Moderator).await.unwrap();
+        let user = app_state
+            .db_client
+            .save_user("John", &Email::parse("john@example.com").unwrap(), "hashed")
+            .await
+            .unwrap();
+        let token = app_state
+            .token_service
+            .create_token(&moderator.id.to_string(), UserRole::Moderator, &moderator.email, uuid::Uuid::new_v4(), 60)
+            .unwrap();
+        let app = init_service(
+            App::new().app_data(web::Data::new(app_state.clone())).route(
+                "/users/roles",
+                web::put()
+                    .to(update_user_roles)
+                    .wrap(RequireAuth::any_authenticated()),
+            ),
+        )
+        .await;
+
+        let req = TestRequest::put()
+            .uri("/users/roles")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .set_json(json!({ "users": [{ "id": user.id, "role": "admin" }] }))
+            .to_request();
+        let res = try_call_service(&app, req).await;
+        let status = match res {
+            Ok(res) => res.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let user = app_state.db_client.get_user(Some(user.id), None, None).await.unwrap().unwrap();
+        assert_eq!(user.role, UserRole::User);
+    }
+}
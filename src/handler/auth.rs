@@ -1,34 +1,84 @@
 use actix_web::{
-    cookie::time::Duration as ActixWebDuration, cookie::Cookie, web, HttpResponse, Responder, Scope,
+    cookie::time::Duration as ActixWebDuration, cookie::Cookie, web, HttpRequest, HttpResponse,
+    Scope,
 };
+use chrono::{DateTime, Duration, Utc};
 use serde_json::json;
-use validator::Validate;
+use tracing::{field, instrument, Span};
+use validator::{Validate, ValidateArgs};
 
 use crate::{
-    db::UserExt,
+    config::Config,
+    db::{
+        AppSettingsExt, IdempotencyKeyExt, InvitationExt, PasswordHistoryExt, PasswordResetTokenExt, RefreshTokenExt, RevokedTokenExt, UserExt,
+        VerificationTokenExt,
+    },
     dtos::{
-        FilterUserDto, LoginUserDto, RegisterUserDto, UserData, UserLoginResponseDto,
-        UserResponseDto,
+        EmailAvailabilityQueryDto, EmailAvailabilityResponseDto, FilterUserDto, IntrospectRequestDto,
+        IntrospectResponseDto, ForgotPasswordDto, LoginUserDto, RegisterUserDto, ResetPasswordDto,
+        Response, TokenClaimsResponseDto, TokenStatusResponseDto, TwoFactorChallengeResponseDto, UserData, UserLoginResponseDto, UserResponseDto,
+        VerifyEmailQueryDto,
     },
     error::{ErrorMessage, HttpError},
+    audit,
     auth::RequireAuth,
-    models::UserRole,
-    utils::{password, token},
+    models::{AuditAction, Email, Invitation, User},
+    rate_limit::RateLimit,
+    utils::{self, password, token::{self, TokenClaims}},
+    webhook::{WebhookEvent, WebhookPayload},
     AppState,
 };
 
+const TWO_FACTOR_CHALLENGE_MAXAGE: i64 = 5;
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
 pub fn auth_handler() -> Scope {
     web::scope("/api/auth")
-        .route("/register", web::post().to(register))
-        .route("/login", web::post().to(login))
+        .route(
+            "/register",
+            web::post()
+                .to(register)
+                .wrap(RateLimit::new(5, std::time::Duration::from_secs(60))),
+        )
+        .route(
+            "/email-available",
+            web::get()
+                .to(email_available)
+                .wrap(RateLimit::new(5, std::time::Duration::from_secs(60))),
+        )
+        .route(
+            "/login",
+            web::post()
+                .to(login)
+                .wrap(RateLimit::new(10, std::time::Duration::from_secs(60))),
+        )
+        .route("/refresh", web::post().to(refresh))
+        .route("/verify", web::get().to(verify_email))
+        .route(
+            "/forgot-password",
+            web::post()
+                .to(forgot_password)
+                .wrap(RateLimit::new(5, std::time::Duration::from_secs(60))),
+        )
+        .route(
+            "/reset-password",
+            web::post()
+                .to(reset_password)
+                .wrap(RateLimit::new(5, std::time::Duration::from_secs(60))),
+        )
+        .route(
+            "/me-from-token",
+            web::get().to(me_from_token).wrap(RequireAuth::any_authenticated()),
+        )
+        .route(
+            "/token/status",
+            web::get().to(token_status).wrap(RequireAuth::any_authenticated()),
+        )
         .route(
             "/logout",
-            web::post().to(logout).wrap(RequireAuth::allowed_roles(vec![
-                UserRole::User,
-                UserRole::Moderator,
-                UserRole::Admin,
-            ])) 
+            web::post().to(logout).wrap(RequireAuth::any_authenticated()) 
         )
+        .route("/introspect", web::post().to(introspect))
 }
 
 #[utoipa::path(
@@ -37,98 +87,714 @@ pub fn auth_handler() -> Scope {
     tag = "Register Account Endpoint",
     request_body(content = RegisterUserDto, description = "Credentials to create account", example = json!({"email": "johndoe@example.com","name": "John Doe","password": "password123","passwordConfirm": "password123"})),
     responses(
-        (status=201, description= "Account created successfully", body= UserResponseDto ),
-        (status=400, description= "Validation Errors", body= Response),
+        (status=201, description= "Account created successfully; with LOGIN_ON_REGISTER the body is a UserLoginResponseDto and the token cookies are set", body= UserResponseDto ),
+        (status=400, description= "Validation Errors, or an invite that is unknown, expired, used or for another email", body= Response),
+        (status=403, description= "New registrations are closed, or INVITE_ONLY is on and no invite token was sent", body= Response),
         (status=409, description= "User with email already exists", body= Response),
+        (status=422, description= "Idempotency-Key was already used for a different request", body= Response),
         (status=500, description= "Internal Server Error", body= Response ),
+    ),
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Repeating a registration with the same key returns the original response instead of creating another account"),
     )
 )]
 pub async fn register(
+    req: HttpRequest,
     app_state: web::Data<AppState>,
     body: web::Json<RegisterUserDto>,
 ) -> Result<HttpResponse, HttpError> {
-    body.validate()
-        .map_err(|e| HttpError::bat_request(e.to_string()))?;
+    body.validate_args((&app_state.env.name_policy, &app_state.env.password_policy))
+        .map_err(HttpError::validation)?;
+
+    let email = Email::parse(&body.email).map_err(HttpError::bat_request)?;
+
+    let idempotency_key = idempotency_key(&req)?;
+    let request_hash = token::hash_token(&format!("{}\0{}", body.name, email.as_str()));
+    if let Some(key) = &idempotency_key {
+        if let Some(response) = replay_registration(&req, &app_state, key, &request_hash, &body).await? {
+            return Ok(response);
+        }
+    }
+
+    // Checked per request so an admin can close signups without a restart.
+    if !app_state.db_client.get_app_settings().await?.registration_enabled {
+        return Err(HttpError::forbidden(ErrorMessage::RegistrationClosed));
+    }
+
+    // Checked before the password is hashed, so uninvited requests are cheap to refuse.
+    let invitation_hash = invitation_token_hash(&app_state, body.invite_token.as_deref())?;
+    if let Some(token_hash) = &invitation_hash {
+        if let Some(error) = invitation_error(&app_state, token_hash, &email).await? {
+            return Err(HttpError::bat_request(error));
+        }
+    }
+
+    reject_breached_password(&app_state, &body.password).await?;
 
     let hashed_password = 
-        password::hash(&body.password).map_err(|e| HttpError::server_error(e.to_string()))?;
+        password::hash(&body.password, &app_state.env.argon2_params).map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    // Claimed only now, so a failure above leaves the invite usable.
+    let invitation = match &invitation_hash {
+        Some(token_hash) => match app_state.db_client.claim_invitation(token_hash, &email).await? {
+            Some(invitation) => Some(invitation),
+            // Another registration used it in the meantime.
+            None => {
+                let error = invitation_error(&app_state, token_hash, &email)
+                    .await?
+                    .unwrap_or(ErrorMessage::InvitationAlreadyUsed);
+                return Err(HttpError::bat_request(error));
+            }
+        },
+        None => None,
+    };
 
     let result = app_state
             .db_client
-            .save_user(&body.name, &body.email, &hashed_password)
+            .save_user(&body.name, &email, &hashed_password)
             .await;
 
+    if let (Err(_), Some(invitation)) = (&result, &invitation) {
+        app_state.db_client.release_invitation(invitation.id).await?;
+    }
+
     match result {
-        Ok(user) => Ok(HttpResponse::Created().json(UserResponseDto {
-            status: "success".to_string(),
-            data: UserData {
-                user: FilterUserDto::filter_user(&user),
+        Ok(user) => {
+            if let Some(key) = &idempotency_key {
+                let expires_at = Utc::now() + Duration::minutes(app_state.env.idempotency_key_maxage_minutes);
+                app_state
+                    .db_client
+                    .save_idempotency_key(key, &request_hash, user.id, expires_at)
+                    .await?;
             }
-        })),
-        Err(sqlx::Error::Database(db_err)) => {
-            if db_err.is_unique_violation() {
-                Err(HttpError::unique_constraint_voilation(
-                    ErrorMessage::EmailExist,
-                ))
-            } else {
-                Err(HttpError::server_error(db_err.to_string()))
+
+            app_state.metrics.registrations.inc();
+            app_state.webhook_dispatcher.dispatch(WebhookPayload::new(
+                WebhookEvent::UserRegistered,
+                user.id,
+                json!({"name": user.name, "email": user.email}),
+            ));
+            send_verification_token(&app_state, &user).await?;
+
+            registration_response(&req, &app_state, &user).await
+        }
+        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            // A retry that raced the original request lost the insert; answer
+            // it the way the original was answered.
+            if let Some(key) = &idempotency_key {
+                if let Some(response) = replay_registration(&req, &app_state, key, &request_hash, &body).await? {
+                    return Ok(response);
+                }
             }
+
+            Err(HttpError::unique_constraint_voilation(
+                ErrorMessage::EmailExist,
+            ))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Hash of the invite token sent with a registration. Registering without one
+/// is refused while INVITE_ONLY is on.
+fn invitation_token_hash(app_state: &AppState, invite_token: Option<&str>) -> Result<Option<String>, HttpError> {
+    match invite_token {
+        Some(invite_token) => Ok(Some(token::hash_token(invite_token.trim()))),
+        None if app_state.env.features.invite_only => {
+            Err(HttpError::forbidden(ErrorMessage::InvitationRequired))
         }
-        Err(e) => Err(HttpError::server_error(e.to_string())),
+        None => Ok(None),
     }
 }
 
+/// Why the invite cannot be used to register `email`, or `None` if it can.
+async fn invitation_error(
+    app_state: &AppState,
+    token_hash: &str,
+    email: &Email,
+) -> Result<Option<ErrorMessage>, HttpError> {
+    let Some(invitation) = app_state.db_client.get_invitation(token_hash).await? else {
+        return Ok(Some(ErrorMessage::InvalidInvitation));
+    };
+
+    if invitation.used_at.is_some() {
+        Ok(Some(ErrorMessage::InvitationAlreadyUsed))
+    } else if invitation.expires_at <= Utc::now() {
+        Ok(Some(ErrorMessage::InvitationExpired))
+    } else if invitation.email != email.as_str() {
+        Ok(Some(ErrorMessage::InvitationEmailMismatch))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Reads the optional `Idempotency-Key` header.
+fn idempotency_key(req: &HttpRequest) -> Result<Option<String>, HttpError> {
+    let Some(value) = req.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+
+    match value.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= 255 && key.bytes().all(|b| b.is_ascii_graphic()) => {
+            Ok(Some(key.to_string()))
+        }
+        _ => Err(HttpError::bat_request(ErrorMessage::InvalidIdempotencyKey.to_string())),
+    }
+}
+
+/// Answers a repeated registration from the account it created, without
+/// re-sending the verification email or firing the webhook again. Returns
+/// `None` when the key is unknown or has expired.
+///
+/// Only the name and email are fingerprinted, since a fast hash of the
+/// password would sidestep Argon2; the password is checked against the
+/// account instead.
+async fn replay_registration(
+    req: &HttpRequest,
+    app_state: &web::Data<AppState>,
+    key: &str,
+    request_hash: &str,
+    body: &RegisterUserDto,
+) -> Result<Option<HttpResponse>, HttpError> {
+    let Some(stored) = app_state.db_client.get_idempotency_key(key).await? else {
+        return Ok(None);
+    };
+
+    let reused = || HttpError::unprocessable_entity(ErrorMessage::IdempotencyKeyReused.to_string());
+    if stored.request_hash != request_hash {
+        return Err(reused());
+    }
+
+    let Some(user) = app_state.db_client.get_user(Some(stored.user_id), None, None).await? else {
+        return Ok(None);
+    };
+
+    let password_matches = password::compare(&body.password, &user.password)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+    if !password_matches {
+        return Err(reused());
+    }
+
+    registration_response(req, app_state, &user).await.map(Some)
+}
+
+async fn registration_response(
+    req: &HttpRequest,
+    app_state: &web::Data<AppState>,
+    user: &User,
+) -> Result<HttpResponse, HttpError> {
+    if app_state.env.features.login_on_register && !app_state.env.features.require_verified_email {
+        let mut response = issue_tokens(req, app_state, user, None).await?;
+        *response.status_mut() = actix_web::http::StatusCode::CREATED;
+        return Ok(response);
+    }
+
+    Ok(HttpResponse::Created().json(UserResponseDto {
+        status: "success".to_string(),
+        data: UserData {
+            user: FilterUserDto::filter_user(user),
+        }
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/auth/email-available",
+    tag = "Email Availability Endpoint",
+    params(
+        EmailAvailabilityQueryDto
+    ),
+    responses(
+        (status=200, description= "Whether the email can be used to register", body= EmailAvailabilityResponseDto ),
+        (status=400, description= "Email is missing or malformed", body= Response),
+        (status=429, description= "Too many requests", body= Response),
+        (status=500, description= "Internal Server Error", body= Response ),
+    )
+)]
+pub async fn email_available(
+    query: web::Query<EmailAvailabilityQueryDto>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    query.validate()
+        .map_err(HttpError::validation)?;
+    let email = Email::parse(&query.email).map_err(HttpError::bat_request)?;
+
+    let user = app_state
+        .db_client
+        .get_user(None, None, Some(&email))
+        .await?;
+
+    Ok(HttpResponse::Ok().json(EmailAvailabilityResponseDto {
+        status: "success".to_string(),
+        available: user.is_none(),
+    }))
+}
+
 #[utoipa::path(
     post,
     path = "/api/auth/login",
     tag = "Login Endpoint",
-    request_body(content = LoginUserDto, description = "Credentials to log in to your account", example = json!({"email": "johndoe@example.com","password": "password123"})),
+    request_body(content = LoginUserDto, description = "Credentials to log in to your account; `identifier` is the email or the name", example = json!({"identifier": "johndoe@example.com","password": "password123"})),
     responses(
-        (status=200, description= "Login successfull", body= UserLoginResponseDto ),
+        (status=200, description= "Login successfull, or a 2FA challenge when two-factor authentication is enabled", body= UserLoginResponseDto ),
         (status=400, description= "Validation Errors", body= Response ),
         (status=500, description= "Internal Server Error", body= Response ),
     )
 )]
+#[instrument(skip_all, fields(user_id = field::Empty))]
 pub async fn login(
+    req: HttpRequest,
     app_state: web::Data<AppState>,
     body: web::Json<LoginUserDto>,
 ) -> Result<HttpResponse, HttpError> {
     body.validate()
-       .map_err(|e| HttpError::bat_request(e.to_string()))?;
+       .map_err(HttpError::validation)?;
 
-    let result = app_state
-                    .db_client
-                    .get_user(None, None, Some(&body.email))
-                    .await
-                    .map_err(|e| HttpError::server_error(e.to_string()))?;
-    let user = result.ok_or(HttpError::unauthorized(ErrorMessage::WrongCredentials))?;
+    let Some(user) = find_login_user(&app_state, &body.identifier).await? else {
+        password::dummy_compare(&body.password, &app_state.env.argon2_params);
+        return Err(wrong_credentials(
+            &req,
+            &app_state,
+            None,
+            Some(format!("Unknown email or name {}", body.identifier)),
+        ));
+    };
+    Span::current().record("user_id", field::display(user.id));
 
     let password_matches = password::compare(&body.password, &user.password)
-        .map_err(|_| HttpError::unauthorized(ErrorMessage::WrongCredentials))?;
+        .map_err(|_| wrong_credentials(&req, &app_state, Some(user.id), None))?;
 
     if password_matches {
-        let token = token::create_token(
-                &user.id.to_string(), 
-                &app_state.env.jwt_secret.as_bytes(), 
-                app_state.env.jwt_maxage
-            )
-            .map_err(|e| HttpError::server_error(e.to_string()))?;
+        if let Some(error) = user.status.lock_error() {
+            return Err(HttpError::forbidden(error));
+        }
 
-        let cookie = Cookie::build("token", token.to_owned())
-                    .path("/")
-                    .max_age(ActixWebDuration::new(60 * &app_state.env.jwt_maxage, 0))
-                    .http_only(true)
-                    .finish();
-        Ok(HttpResponse::Ok()
-            .cookie(cookie)
-            .json(UserLoginResponseDto {
-                status: "success".to_string(),
-                token,
-            })
-        )
+        if app_state.env.features.require_verified_email && !user.verified {
+            return Err(HttpError::forbidden(ErrorMessage::EmailNotVerified));
+        }
+
+        if user.totp_enabled {
+            let challenge_token = app_state
+                .token_service
+                .create_two_factor_challenge(&user.id.to_string(), TWO_FACTOR_CHALLENGE_MAXAGE)?;
+
+            return Ok(HttpResponse::Ok().json(TwoFactorChallengeResponseDto {
+                status: "2fa_required".to_string(),
+                challenge_token,
+            }));
+        }
+
+        issue_tokens(&req, &app_state, &user, body.remember_me).await
     } else {
-        Err(HttpError::unauthorized(ErrorMessage::WrongCredentials))
+        Err(wrong_credentials(&req, &app_state, Some(user.id), None))
+    }
+}
+
+/// Looks `identifier` up as an email when it parses as one, otherwise as a
+/// name. Names may contain `@`, so an email that matches nobody is retried as
+/// a name.
+#[instrument(skip_all)]
+async fn find_login_user(app_state: &AppState, identifier: &str) -> Result<Option<User>, HttpError> {
+    if let Ok(email) = Email::parse(identifier) {
+        let user = app_state
+            .db_client
+            .get_user(None, None, Some(&email))
+            .await?;
+        if user.is_some() {
+            return Ok(user);
+        }
+    }
+
+    Ok(app_state
+        .db_client
+        .get_user(None, Some(identifier), None)
+        .await?)
+}
+
+fn wrong_credentials(
+    req: &HttpRequest,
+    app_state: &AppState,
+    user_id: Option<uuid::Uuid>,
+    details: Option<String>,
+) -> HttpError {
+    app_state.metrics.login_failed();
+    audit::record(app_state, req, user_id, AuditAction::LoginFailed, details);
+    HttpError::unauthorized(ErrorMessage::WrongCredentials)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/auth/verify",
+    tag = "Verify Email Endpoint",
+    params(
+        VerifyEmailQueryDto
+    ),
+    responses(
+        (status=200, description= "Email verified successfully", body= Response ),
+        (status=400, description= "Verification token is invalid or expired", body= Response),
+        (status=500, description= "Internal Server Error", body= Response ),
+    )
+)]
+pub async fn verify_email(
+    query: web::Query<VerifyEmailQueryDto>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    query.validate()
+        .map_err(HttpError::validation)?;
+
+    let verification_token = app_state
+        .db_client
+        .consume_verification_token(&token::hash_token(&query.token))
+        .await?
+        .ok_or(HttpError::bat_request(ErrorMessage::InvalidVerificationToken))?;
+
+    if verification_token.expires_at <= Utc::now() {
+        return Err(HttpError::bat_request(ErrorMessage::InvalidVerificationToken));
+    }
+
+    let user = app_state
+        .db_client
+        .verify_user(verification_token.user_id)
+        .await?;
+
+    app_state.webhook_dispatcher.dispatch(WebhookPayload::new(
+        WebhookEvent::UserVerified,
+        user.id,
+        json!({"email": user.email}),
+    ));
+
+    Ok(HttpResponse::Ok().json(Response {
+        status: "success",
+        message: "Email verified successfully".to_string(),
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/forgot-password",
+    tag = "Forgot Password Endpoint",
+    request_body(content = ForgotPasswordDto, description = "Email of the account to reset", example = json!({"email": "johndoe@example.com"})),
+    responses(
+        (status=200, description= "Reset instructions sent if the account exists", body= Response ),
+        (status=400, description= "Validation Errors", body= Response ),
+        (status=500, description= "Internal Server Error", body= Response ),
+    )
+)]
+pub async fn forgot_password(
+    app_state: web::Data<AppState>,
+    body: web::Json<ForgotPasswordDto>,
+) -> Result<HttpResponse, HttpError> {
+    body.validate()
+        .map_err(HttpError::validation)?;
+    let email = Email::parse(&body.email).map_err(HttpError::bat_request)?;
+
+    let result = app_state
+        .db_client
+        .get_user(None, None, Some(&email))
+        .await?;
+
+    if let Some(user) = result {
+        let reset_token = token::create_password_reset_token();
+        let expires_at =
+            Utc::now() + Duration::minutes(app_state.env.password_reset_token_maxage_minutes);
+
+        app_state
+            .db_client
+            .save_password_reset_token(user.id, &token::hash_token(&reset_token), expires_at)
+            .await?;
+
+        send_email(
+            &app_state,
+            &user.email,
+            "Reset your password",
+            &format!(
+                "Use this token to reset your password: {}\n\nIt expires in {} minutes. If you did not request a reset, you can ignore this email.",
+                reset_token, app_state.env.password_reset_token_maxage_minutes
+            ),
+        )
+        .await;
+    }
+
+    Ok(HttpResponse::Ok().json(Response {
+        status: "success",
+        message: "If an account with that email exists, a password reset link has been sent".to_string(),
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/reset-password",
+    tag = "Reset Password Endpoint",
+    request_body(content = ResetPasswordDto, description = "Reset token and the new password", example = json!({"token": "reset-token","password": "newpassword123","passwordConfirm": "newpassword123"})),
+    responses(
+        (status=200, description= "Password reset successfully", body= Response ),
+        (status=400, description= "Validation Errors or invalid token", body= Response ),
+        (status=500, description= "Internal Server Error", body= Response ),
+    )
+)]
+pub async fn reset_password(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+    body: web::Json<ResetPasswordDto>,
+) -> Result<HttpResponse, HttpError> {
+    body.validate_args(&app_state.env.password_policy)
+        .map_err(HttpError::validation)?;
+
+    let token_hash = token::hash_token(&body.token);
+
+    // Look the token up without consuming it first, so a rejected password doesn't
+    // cost the user their reset link.
+    let reset_token = app_state
+        .db_client
+        .get_password_reset_token(&token_hash)
+        .await?
+        .filter(|reset_token| reset_token.expires_at > Utc::now())
+        .ok_or(HttpError::bat_request(ErrorMessage::InvalidPasswordResetToken))?;
+
+    let user = app_state
+        .db_client
+        .get_user(Some(reset_token.user_id), None, None)
+        .await?
+        .ok_or(HttpError::unauthorized(ErrorMessage::UserNoLongerExist))?;
+
+    // Only checked once the token is known good, so the endpoint can't be used to
+    // query the breach API without a reset link.
+    reject_breached_password(&app_state, &body.password).await?;
+    reject_reused_password(&app_state, &user, &body.password).await?;
+
+    // Consuming is what guards against the same link being used twice concurrently.
+    app_state
+        .db_client
+        .consume_password_reset_token(&token_hash)
+        .await?
+        .ok_or(HttpError::bat_request(ErrorMessage::InvalidPasswordResetToken))?;
+
+    let hashed_password =
+        password::hash(&body.password, &app_state.env.argon2_params).map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    record_password_history(&app_state, &user).await?;
+
+    app_state
+        .db_client
+        .update_user_password(reset_token.user_id, hashed_password)
+        .await?
+        .ok_or(HttpError::unauthorized(ErrorMessage::UserNoLongerExist))?;
+
+    app_state
+        .db_client
+        .revoke_user_refresh_tokens(reset_token.user_id)
+        .await?;
+
+    audit::record(&app_state, &req, Some(reset_token.user_id), AuditAction::PasswordReset, None);
+
+    Ok(HttpResponse::Ok().json(Response {
+        status: "success",
+        message: "Password reset successfully".to_string(),
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/refresh",
+    tag = "Refresh Token Endpoint",
+    responses(
+        (status=200, description= "Token refreshed successfully", body= UserLoginResponseDto ),
+        (status=401, description= "Refresh token missing, invalid, expired or reused", body= Response),
+        (status=500, description= "Internal Server Error", body= Response ),
+    )
+)]
+pub async fn refresh(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let refresh_token = req
+        .cookie("refresh_token")
+        .map(|c| c.value().to_string())
+        .ok_or(HttpError::unauthorized(ErrorMessage::TokenNotProvided))?;
+
+    let stored_token = app_state
+        .db_client
+        .get_refresh_token(&token::hash_token(&refresh_token))
+        .await?
+        .ok_or(HttpError::unauthorized(ErrorMessage::InvalidToken))?;
+
+    if stored_token.revoked_at.is_some() {
+        return Err(revoke_reused_family(&app_state, stored_token.family_id).await);
+    }
+
+    if stored_token.expires_at <= Utc::now() {
+        return Err(HttpError::unauthorized(ErrorMessage::InvalidToken));
     }
+
+    let user = app_state
+        .db_client
+        .get_user(Some(stored_token.user_id), None, None)
+        .await?
+        .ok_or(HttpError::unauthorized(ErrorMessage::UserNoLongerExist))?;
+
+    if let Some(error) = user.status.lock_error() {
+        return Err(HttpError::forbidden(error));
+    }
+
+    let new_refresh_token = token::create_refresh_token();
+    let refresh_token_maxage = refresh_token_maxage(&app_state.env, stored_token.remember_me);
+    let expires_at = Utc::now() + Duration::minutes(refresh_token_maxage);
+
+    let (user_agent, ip_address) = client_details(&req, &app_state);
+
+    let rotated = app_state
+        .db_client
+        .rotate_refresh_token(
+            stored_token.id,
+            &token::hash_token(&new_refresh_token),
+            expires_at,
+            user_agent.as_deref(),
+            ip_address.as_deref(),
+        )
+        .await?;
+
+    if rotated.is_none() {
+        return Err(revoke_reused_family(&app_state, stored_token.family_id).await);
+    }
+
+    let token = app_state.token_service.create_token(
+        &user.id.to_string(),
+        user.role,
+        &user.email,
+        stored_token.family_id,
+        app_state.env.jwt_maxage_minutes,
+    )?;
+
+    app_state.metrics.token_issued("access");
+    app_state.metrics.token_issued("refresh");
+
+    Ok(HttpResponse::Ok()
+        .cookie(token_cookie(
+            "token",
+            token.to_owned(),
+            cookie_max_age(app_state.env.jwt_maxage_minutes, stored_token.remember_me),
+            &app_state.env,
+        ))
+        .cookie(token_cookie(
+            "refresh_token",
+            new_refresh_token,
+            cookie_max_age(refresh_token_maxage, stored_token.remember_me),
+            &app_state.env,
+        ))
+        .json(UserLoginResponseDto {
+            status: "success".to_string(),
+            token,
+            user: FilterUserDto::filter_user(&user),
+        })
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/auth/me-from-token",
+    tag = "Decode Token Endpoint",
+    responses(
+        (status=200, description= "Claims carried by the presented access token", body= TokenClaimsResponseDto ),
+        (status=401, description= "Unauthorize Error", body= Response),
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn me_from_token(claims: web::ReqData<TokenClaims>) -> Result<HttpResponse, HttpError> {
+    Ok(HttpResponse::Ok().json(TokenClaimsResponseDto {
+        status: "success".to_string(),
+        claims: claims.into_inner(),
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/auth/token/status",
+    tag = "Token Status Endpoint",
+    responses(
+        (status=200, description= "Remaining lifetime of the presented access token", body= TokenStatusResponseDto ),
+        (status=401, description= "Unauthorize Error", body= Response),
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn token_status(
+    claims: web::ReqData<TokenClaims>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let expires_in_seconds = (claims.exp as i64 - Utc::now().timestamp()).max(0);
+
+    Ok(HttpResponse::Ok().json(TokenStatusResponseDto {
+        status: "success".to_string(),
+        expires_in_seconds,
+        should_refresh: expires_in_seconds <= app_state.env.token_refresh_threshold_seconds,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/introspect",
+    tag = "Token Introspection Endpoint",
+    request_body(content = IntrospectRequestDto, content_type = "application/x-www-form-urlencoded", description = "Access token to introspect"),
+    responses(
+        (status=200, description= "Token state per RFC 7662; inactive tokens return only `active: false`", body= IntrospectResponseDto ),
+        (status=401, description= "Missing or invalid introspection secret", body= Response),
+        (status=500, description= "Internal Server Error", body= Response ),
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn introspect(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+    body: web::Form<IntrospectRequestDto>,
+) -> Result<HttpResponse, HttpError> {
+    let provided = req
+        .headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+
+    // Comparing digests keeps the comparison time independent of the secret itself.
+    let authorized = match (&app_state.env.introspection_secret, provided) {
+        (Some(secret), Some(provided)) => token::hash_token(secret) == token::hash_token(provided),
+        _ => false,
+    };
+    if !authorized {
+        return Err(HttpError::unauthorized(ErrorMessage::InvalidIntrospectionCredentials));
+    }
+
+    let inactive = || HttpResponse::Ok().json(IntrospectResponseDto::default());
+
+    // Signature and expiry are checked while decoding.
+    let Ok(claims) = app_state.token_service.decode_token(&body.token) else {
+        return Ok(inactive());
+    };
+
+    if app_state.db_client.is_token_revoked(&claims.jti).await? {
+        return Ok(inactive());
+    }
+
+    let Ok(user_id) = uuid::Uuid::parse_str(&claims.sub) else {
+        return Ok(inactive());
+    };
+    let Some(user) = app_state.db_client.get_user(Some(user_id), None, None).await? else {
+        return Ok(inactive());
+    };
+    if user.status.lock_error().is_some() {
+        return Ok(inactive());
+    }
+
+    Ok(HttpResponse::Ok().json(IntrospectResponseDto {
+        active: true,
+        sub: Some(claims.sub),
+        role: Some(user.role),
+        exp: Some(claims.exp),
+        iat: Some(claims.iat),
+    }))
 }
 
 #[utoipa::path(
@@ -145,14 +811,470 @@ pub async fn login(
        ("token" = [])
    )
 )]
-pub async fn logout() -> impl Responder {
-    let cookie = Cookie::build("token", "")
-            .path("/")
-            .max_age(ActixWebDuration::new(-1, 0))
-            .http_only(true)
-            .finish();
-    
-    HttpResponse::Ok()
-        .cookie(cookie)
-        .json(json!({"status": "success"}))
-}
\ No newline at end of file
+pub async fn logout(
+    req: HttpRequest,
+    claims: web::ReqData<TokenClaims>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let expires_at = DateTime::<Utc>::from_timestamp(claims.exp as i64, 0).unwrap_or_else(Utc::now);
+
+    app_state
+        .db_client
+        .revoke_token(&claims.jti, expires_at)
+        .await?;
+
+    audit::record(
+        &app_state,
+        &req,
+        uuid::Uuid::parse_str(&claims.sub).ok(),
+        AuditAction::Logout,
+        None,
+    );
+
+    // An impersonation token has no session of its own; any refresh cookie sent
+    // along belongs to the admin and is left alone.
+    if claims.impersonated_by.is_some() {
+        return Ok(HttpResponse::Ok().json(json!({"status": "success"})));
+    }
+
+    if let Some(refresh_token) = req.cookie("refresh_token") {
+        let stored_token = app_state
+            .db_client
+            .get_refresh_token(&token::hash_token(refresh_token.value()))
+            .await?;
+
+        if let Some(stored_token) = stored_token {
+            app_state
+                .db_client
+                .revoke_refresh_token_family(stored_token.family_id)
+                .await?;
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .cookie(token_cookie("token", String::new(), Some(-1), &app_state.env))
+        .cookie(token_cookie("refresh_token", String::new(), Some(-1), &app_state.env))
+        .json(json!({"status": "success"})))
+}
+
+#[instrument(skip_all, fields(user_id = %user.id))]
+pub async fn issue_tokens(
+    req: &HttpRequest,
+    app_state: &AppState,
+    user: &User,
+    remember_me: Option<bool>,
+) -> Result<HttpResponse, HttpError> {
+    let session_id = uuid::Uuid::new_v4();
+    let token = app_state.token_service.create_token(
+        &user.id.to_string(),
+        user.role,
+        &user.email,
+        session_id,
+        app_state.env.jwt_maxage_minutes,
+    )?;
+
+    let refresh_token = token::create_refresh_token();
+    let refresh_token_maxage = refresh_token_maxage(&app_state.env, remember_me);
+    let expires_at = Utc::now() + Duration::minutes(refresh_token_maxage);
+    let (user_agent, ip_address) = client_details(req, app_state);
+
+    app_state
+        .db_client
+        .record_login(user.id, ip_address.as_deref())
+        .await?;
+
+    app_state
+        .db_client
+        .save_refresh_token(
+            user.id,
+            session_id,
+            &token::hash_token(&refresh_token),
+            expires_at,
+            user_agent.as_deref(),
+            ip_address.as_deref(),
+            remember_me,
+        )
+        .await?;
+
+    app_state.metrics.login_succeeded();
+    audit::record(app_state, req, Some(user.id), AuditAction::Login, None);
+    app_state.metrics.token_issued("access");
+    app_state.metrics.token_issued("refresh");
+
+    Ok(HttpResponse::Ok()
+        .cookie(token_cookie(
+            "token",
+            token.to_owned(),
+            cookie_max_age(app_state.env.jwt_maxage_minutes, remember_me),
+            &app_state.env,
+        ))
+        .cookie(token_cookie(
+            "refresh_token",
+            refresh_token,
+            cookie_max_age(refresh_token_maxage, remember_me),
+            &app_state.env,
+        ))
+        .json(UserLoginResponseDto {
+            status: "success".to_string(),
+            token,
+            user: FilterUserDto::filter_user(user),
+        })
+    )
+}
+
+fn client_details(req: &HttpRequest, app_state: &AppState) -> (Option<String>, Option<String>) {
+    let user_agent = req
+        .headers()
+        .get(actix_web::http::header::USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .map(|ua| ua.to_string());
+    let ip_address = utils::ip::client_ip(&req.connection_info(), app_state.env.trust_proxy);
+
+    (user_agent, ip_address)
+}
+
+// "Remember me" stretches the session; without it the default lifetime applies.
+fn refresh_token_maxage(config: &Config, remember_me: Option<bool>) -> i64 {
+    if remember_me == Some(true) {
+        config.remember_me_maxage_minutes
+    } else {
+        config.refresh_token_maxage_minutes
+    }
+}
+
+// An explicit `rememberMe: false` asks for browser-session cookies with no max age.
+fn cookie_max_age(max_age_minutes: i64, remember_me: Option<bool>) -> Option<i64> {
+    (remember_me != Some(false)).then_some(max_age_minutes)
+}
+
+// Clearing cookies must reuse the same attributes, otherwise browsers keep the original.
+// A `max_age_minutes` of None makes a session cookie.
+fn token_cookie<'a>(name: &'a str, value: String, max_age_minutes: Option<i64>, config: &Config) -> Cookie<'a> {
+    let mut builder = Cookie::build(name, value)
+        .path("/")
+        .http_only(true)
+        .secure(config.cookie_secure)
+        .same_site(config.cookie_same_site);
+
+    if let Some(max_age_minutes) = max_age_minutes {
+        // Built from minutes, like the token and refresh token expiries, so the
+        // cookie never outlives or undercuts what it carries. Negative expires it now.
+        let max_age = if max_age_minutes < 0 {
+            ActixWebDuration::seconds(-1)
+        } else {
+            ActixWebDuration::minutes(max_age_minutes)
+        };
+        builder = builder.max_age(max_age);
+    }
+
+    if let Some(domain) = &config.cookie_domain {
+        builder = builder.domain(domain.clone());
+    }
+
+    builder.finish()
+}
+
+pub async fn send_verification_token(app_state: &AppState, user: &User) -> Result<(), HttpError> {
+    let verification_token = token::create_verification_token();
+    let expires_at = Utc::now() + Duration::minutes(app_state.env.verification_token_maxage_minutes);
+
+    app_state
+        .db_client
+        .save_verification_token(user.id, &token::hash_token(&verification_token), expires_at)
+        .await?;
+
+    send_email(
+        app_state,
+        &user.email,
+        "Verify your email address",
+        &format!(
+            "Use this token to verify your email address: {}\n\nIt expires in {} minutes.",
+            verification_token, app_state.env.verification_token_maxage_minutes
+        ),
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Creates an invite for `email` and mails its token. The token is also
+/// returned so the inviter can pass it on another way.
+pub async fn send_invitation(
+    app_state: &AppState,
+    inviter: &User,
+    email: &Email,
+) -> Result<(Invitation, String), HttpError> {
+    let invitation_token = token::create_invitation_token();
+    let expires_at = Utc::now() + Duration::minutes(app_state.env.invitation_maxage_minutes);
+
+    let invitation = app_state
+        .db_client
+        .save_invitation(&token::hash_token(&invitation_token), email, inviter.id, expires_at)
+        .await?;
+
+    send_email(
+        app_state,
+        email.as_str(),
+        "You have been invited",
+        &format!(
+            "{} invited you to create an account. Register with this email address and this invite token: {}\n\nIt expires in {} minutes.",
+            inviter.name, invitation_token, app_state.env.invitation_maxage_minutes
+        ),
+    )
+    .await;
+
+    Ok((invitation, invitation_token))
+}
+
+// Delivery failures are logged rather than surfaced so that the account change
+// which triggered the email still goes through.
+async fn send_email(app_state: &AppState, to: &str, subject: &str, body: &str) {
+    if let Err(e) = app_state.email_sender.send(to, subject, body).await {
+        log::error!("Error sending email to {}: {}", to, e);
+    }
+}
+
+// A revoked refresh token being presented again means it was stolen or replayed,
+// so every token issued from the same login is revoked.
+async fn revoke_reused_family(app_state: &AppState, family_id: uuid::Uuid) -> HttpError {
+    match app_state.db_client.revoke_refresh_token_family(family_id).await {
+        Ok(_) => HttpError::unauthorized(ErrorMessage::RefreshTokenReused),
+        Err(e) => e.into(),
+    }
+}
+
+// Fail open so an unreachable breach API never blocks registration or resets.
+pub async fn reject_breached_password(app_state: &AppState, password: &str) -> Result<(), HttpError> {
+    if !app_state.env.features.check_breached_passwords {
+        return Ok(());
+    }
+
+    match app_state.breach_checker.is_breached(password).await {
+        Ok(true) => Err(HttpError::bat_request(ErrorMessage::BreachedPassword)),
+        Ok(false) => Ok(()),
+        Err(e) => {
+            log::warn!("Breached password check failed: {}", e);
+            Ok(())
+        }
+    }
+}
+
+/// Rejects a new password that matches the user's current one or any kept in their history.
+pub async fn reject_reused_password(
+    app_state: &AppState,
+    user: &User,
+    new_password: &str,
+) -> Result<(), HttpError> {
+    let depth = app_state.env.password_history_depth;
+    if depth == 0 {
+        return Ok(());
+    }
+
+    let previous = app_state
+        .db_client
+        .get_password_history(user.id, depth)
+        .await?;
+
+    for hashed_password in std::iter::once(&user.password).chain(previous.iter()) {
+        let reused = password::compare(new_password, hashed_password)
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+        if reused {
+            return Err(HttpError::bat_request(ErrorMessage::PasswordReused(depth)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Keeps the password being replaced so it can't be chosen again.
+pub async fn record_password_history(app_state: &AppState, user: &User) -> Result<(), HttpError> {
+    let depth = app_state.env.password_history_depth;
+    if depth == 0 {
+        return Ok(());
+    }
+
+    app_state
+        .db_client
+        .add_password_history(user.id, &user.password, depth)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::breach::FakeBreachChecker;
+    use crate::email::RecordingEmailSender;
+    use crate::models::UserRole;
+    use crate::utils::token::{JwtTokenService, TokenService};
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::App;
+    use sqlx::PgPool;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    #[test]
+    fn token_cookie_expires_with_the_token() {
+        let config = Config::for_tests();
+        let token_service = JwtTokenService::new(&config);
+        let token = token_service
+            .create_token(
+                &uuid::Uuid::new_v4().to_string(),
+                UserRole::User,
+                "john@example.com",
+                uuid::Uuid::new_v4(),
+                config.jwt_maxage_minutes,
+            )
+            .unwrap();
+        let claims = token_service.decode_token(&token).unwrap();
+
+        let cookie = token_cookie(
+            "token",
+            token,
+            cookie_max_age(config.jwt_maxage_minutes, None),
+            &config,
+        );
+
+        let token_lifetime = (claims.exp - claims.iat) as i64;
+        assert_eq!(cookie.max_age().map(|max_age| max_age.whole_seconds()), Some(token_lifetime));
+    }
+
+    fn with_breach_checker(pool: PgPool, breach_checker: FakeBreachChecker) -> AppState {
+        let mut app_state = AppState::for_tests(pool);
+        app_state.env.features.check_breached_passwords = true;
+        app_state.breach_checker = Arc::new(breach_checker);
+        app_state
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn reject_breached_password_rejects_known_breached_passwords(pool: PgPool) {
+        let app_state = with_breach_checker(
+            pool,
+            FakeBreachChecker {
+                breached: vec!["Passw0rd!breached".to_string()],
+                ..Default::default()
+            },
+        );
+
+        let err = reject_breached_password(&app_state, "Passw0rd!breached").await.unwrap_err();
+        assert_eq!(err.status, 400);
+        assert_eq!(err.message, ErrorMessage::BreachedPassword.to_string());
+
+        assert!(reject_breached_password(&app_state, "Passw0rd!fresh").await.is_ok());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn reject_breached_password_fails_open(pool: PgPool) {
+        let app_state = with_breach_checker(
+            pool,
+            FakeBreachChecker {
+                unavailable: true,
+                ..Default::default()
+            },
+        );
+
+        assert!(reject_breached_password(&app_state, "Passw0rd!breached").await.is_ok());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn reset_password_checks_the_token_before_the_breach_api(pool: PgPool) {
+        let breach_checker = Arc::new(FakeBreachChecker::default());
+        let mut app_state = AppState::for_tests(pool);
+        app_state.env.features.check_breached_passwords = true;
+        app_state.breach_checker = breach_checker.clone();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(app_state))
+                .service(auth_handler()),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/api/auth/reset-password")
+            .set_json(json!({
+                "token": "not-a-reset-token",
+                "password": "Passw0rd!new",
+                "passwordConfirm": "Passw0rd!new"
+            }))
+            .to_request();
+        let res = call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = read_body_json(res).await;
+        assert_eq!(body["message"], ErrorMessage::InvalidPasswordResetToken.to_string());
+        assert_eq!(breach_checker.checks.load(Ordering::SeqCst), 0);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn register_sends_the_verification_email(pool: PgPool) {
+        crate::run_on_actix(async move {
+            let email_sender = Arc::new(RecordingEmailSender::default());
+            let app_state = AppState {
+                email_sender: email_sender.clone(),
+                ..AppState::for_tests(pool)
+            };
+            let app = init_service(
+                App::new()
+                    .app_data(web::Data::new(app_state))
+                    .service(auth_handler()),
+            )
+            .await;
+
+            let req = TestRequest::post()
+                .uri("/api/auth/register")
+                .set_json(json!({
+                    "name": "John",
+                    "email": "john@example.com",
+                    "password": "Passw0rd!new",
+                    "passwordConfirm": "Passw0rd!new"
+                }))
+                .to_request();
+            let res = call_service(&app, req).await;
+
+            assert_eq!(res.status(), StatusCode::CREATED);
+            let sent = email_sender.sent.lock().unwrap();
+            assert_eq!(sent.len(), 1);
+            assert_eq!(sent[0].to, "john@example.com");
+            assert_eq!(sent[0].subject, "Verify your email address");
+            assert!(sent[0].body.contains("Use this token to verify your email address"));
+        });
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn login_matches_an_email_registered_in_mixed_case(pool: PgPool) {
+        crate::run_on_actix(async move {
+            let app = init_service(
+                App::new()
+                    .app_data(web::Data::new(AppState::for_tests(pool)))
+                    .service(auth_handler()),
+            )
+            .await;
+
+            let req = TestRequest::post()
+                .uri("/api/auth/register")
+                .set_json(json!({
+                    "name": "John",
+                    "email": "John@Example.com",
+                    "password": "Passw0rd!new",
+                    "passwordConfirm": "Passw0rd!new"
+                }))
+                .to_request();
+            assert_eq!(call_service(&app, req).await.status(), StatusCode::CREATED);
+
+            let req = TestRequest::post()
+                .uri("/api/auth/login")
+                .set_json(json!({
+                    "email": "john@example.com",
+                    "password": "Passw0rd!new"
+                }))
+                .to_request();
+            let res = call_service(&app, req).await;
+
+            assert_eq!(res.status(), StatusCode::OK);
+            let body: serde_json::Value = read_body_json(res).await;
+            assert_eq!(body["user"]["email"], "john@example.com");
+        });
+    }
+}
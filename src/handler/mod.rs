@@ -1,2 +1,4 @@
+pub mod admin;
 pub mod auth;
-pub mod users;
\ No newline at end of file
+pub mod two_factor;
+pub mod users;
@@ -0,0 +1,408 @@
+use actix_web::{web, HttpRequest, HttpResponse, Scope};
+use tracing::{field, instrument, Span};
+use validator::Validate;
+
+use crate::{
+    audit,
+    auth::{Authenticated, RequireAuth},
+    db::{TotpRecoveryCodeExt, UserExt},
+    dtos::{
+        RecoveryCodesRegenerateDto, RecoveryCodesStatusResponseDto, TotpCodeDto,
+        TotpRecoveryCodesResponseDto, TotpSetupResponseDto, TwoFactorLoginDto,
+    },
+    error::{ErrorMessage, HttpError},
+    handler::auth::issue_tokens,
+    models::AuditAction,
+    rate_limit::RateLimit,
+    utils::{password, token, totp},
+    AppState,
+};
+
+/// New enrolments stop when TWO_FACTOR_ENABLED is off. Accounts that already
+/// enrolled keep being challenged at login, so switching it off never strips
+/// a second factor from anyone.
+fn require_two_factor_feature(app_state: &AppState) -> Result<(), HttpError> {
+    if app_state.env.features.two_factor {
+        Ok(())
+    } else {
+        Err(HttpError::forbidden(ErrorMessage::TwoFactorUnavailable))
+    }
+}
+
+/// Checks a code from the authenticator app. Each code is accepted once: its
+/// time step is recorded and codes from that step or earlier are refused.
+async fn accept_totp_code(
+    app_state: &AppState,
+    user_id: uuid::Uuid,
+    secret: &str,
+    code: &str,
+) -> Result<bool, HttpError> {
+    let Some(step) = totp::verify_code(secret, code).map_err(HttpError::server_error)? else {
+        return Ok(false);
+    };
+
+    Ok(app_state.db_client.use_totp_step(user_id, step).await?)
+}
+
+pub fn two_factor_handler() -> Scope {
+    web::scope("/api/auth/2fa")
+        .route(
+            "/setup",
+            web::post()
+                .to(setup)
+                .wrap(RequireAuth::any_authenticated().deny_impersonation()),
+        )
+        .route(
+            "/verify",
+            web::post()
+                .to(verify)
+                .wrap(RequireAuth::any_authenticated().deny_impersonation()),
+        )
+        .route(
+            "/login",
+            web::post()
+                .to(login)
+                .wrap(RateLimit::new(10, std::time::Duration::from_secs(60))),
+        )
+        .route(
+            "/recovery-codes",
+            web::get()
+                .to(recovery_codes_status)
+                .wrap(RequireAuth::any_authenticated()),
+        )
+        .route(
+            "/recovery-codes/regenerate",
+            web::post()
+                .to(regenerate_recovery_codes)
+                .wrap(RateLimit::new(5, std::time::Duration::from_secs(300)).per_user())
+                .wrap(RequireAuth::any_authenticated().deny_impersonation()),
+        )
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/2fa/setup",
+    tag = "Two-Factor Authentication Endpoint",
+    responses(
+        (status=200, description= "TOTP secret generated", body= TotpSetupResponseDto ),
+        (status=400, description= "Two-factor authentication already enabled", body= Response ),
+        (status=401, description= "Unauthorize Error", body= Response),
+        (status=403, description= "Two-factor authentication is disabled on this server", body= Response),
+        (status=500, description= "Internal Server Error", body= Response ),
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn setup(
+    user: Authenticated,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    require_two_factor_feature(&app_state)?;
+
+    if user.totp_enabled {
+        return Err(HttpError::bat_request(ErrorMessage::TwoFactorAlreadyEnabled));
+    }
+
+    let secret = totp::generate_secret();
+    let otpauth_url = totp::otpauth_url(&secret, &app_state.env.totp_issuer, &user.email)
+        .map_err(HttpError::server_error)?;
+
+    app_state
+        .db_client
+        .update_user_totp_secret(user.id, Some(secret.clone()))
+        .await?;
+
+    Ok(HttpResponse::Ok().json(TotpSetupResponseDto {
+        status: "success".to_string(),
+        secret,
+        otpauth_url,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/2fa/verify",
+    tag = "Two-Factor Authentication Endpoint",
+    request_body(content = TotpCodeDto, description = "Code from the authenticator app", example = json!({"code": "123456"})),
+    responses(
+        (status=200, description= "Two-factor authentication enabled", body= TotpRecoveryCodesResponseDto ),
+        (status=400, description= "Invalid code or 2FA not set up", body= Response ),
+        (status=401, description= "Unauthorize Error", body= Response),
+        (status=403, description= "Two-factor authentication is disabled on this server", body= Response),
+        (status=500, description= "Internal Server Error", body= Response ),
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn verify(
+    user: Authenticated,
+    app_state: web::Data<AppState>,
+    body: web::Json<TotpCodeDto>,
+) -> Result<HttpResponse, HttpError> {
+    require_two_factor_feature(&app_state)?;
+
+    body.validate()
+        .map_err(HttpError::validation)?;
+
+    if user.totp_enabled {
+        return Err(HttpError::bat_request(ErrorMessage::TwoFactorAlreadyEnabled));
+    }
+
+    let secret = user
+        .totp_secret
+        .as_deref()
+        .ok_or(HttpError::bat_request(ErrorMessage::TwoFactorNotSetUp))?;
+
+    let code_matches = accept_totp_code(&app_state, user.id, secret, &body.code).await?;
+
+    if !code_matches {
+        return Err(HttpError::bat_request(ErrorMessage::InvalidTotpCode));
+    }
+
+    let recovery_codes = replace_recovery_codes(&app_state, user.id).await?;
+
+    app_state
+        .db_client
+        .enable_user_totp(user.id)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(TotpRecoveryCodesResponseDto {
+        status: "success".to_string(),
+        recovery_codes,
+    }))
+}
+
+/// Generates a fresh set of recovery codes, replacing any the user had. Only
+/// the hashes are stored, so the returned codes cannot be shown again.
+async fn replace_recovery_codes(
+    app_state: &AppState,
+    user_id: uuid::Uuid,
+) -> Result<Vec<String>, HttpError> {
+    let recovery_codes = totp::generate_recovery_codes();
+    let code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| token::hash_token(&totp::normalize_recovery_code(code)))
+        .collect();
+
+    app_state
+        .db_client
+        .replace_recovery_codes(user_id, &code_hashes)
+        .await?;
+
+    Ok(recovery_codes)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/auth/2fa/recovery-codes",
+    tag = "Two-Factor Authentication Endpoint",
+    responses(
+        (status=200, description= "Number of unused recovery codes", body= RecoveryCodesStatusResponseDto ),
+        (status=400, description= "Two-factor authentication is not enabled", body= Response ),
+        (status=401, description= "Unauthorize Error", body= Response),
+        (status=500, description= "Internal Server Error", body= Response ),
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn recovery_codes_status(
+    user: Authenticated,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    if !user.totp_enabled {
+        return Err(HttpError::bat_request(ErrorMessage::TwoFactorNotSetUp));
+    }
+
+    let remaining = app_state
+        .db_client
+        .count_unused_recovery_codes(user.id)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(RecoveryCodesStatusResponseDto {
+        status: "success".to_string(),
+        remaining,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/2fa/recovery-codes/regenerate",
+    tag = "Two-Factor Authentication Endpoint",
+    request_body(content = RecoveryCodesRegenerateDto, description = "Either a code from the authenticator app or the account password", example = json!({"code": "123456"})),
+    responses(
+        (status=200, description= "Old recovery codes invalidated; the new ones are only shown this once", body= TotpRecoveryCodesResponseDto ),
+        (status=400, description= "Validation Errors, wrong code or password, or 2FA not enabled", body= Response ),
+        (status=401, description= "Unauthorize Error", body= Response),
+        (status=403, description= "Not allowed while impersonating", body= Response),
+        (status=429, description= "Too many attempts", body= Response),
+        (status=500, description= "Internal Server Error", body= Response ),
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn regenerate_recovery_codes(
+    user: Authenticated,
+    app_state: web::Data<AppState>,
+    body: web::Json<RecoveryCodesRegenerateDto>,
+) -> Result<HttpResponse, HttpError> {
+    body.validate()
+        .map_err(HttpError::validation)?;
+
+    let secret = match (user.totp_enabled, user.totp_secret.as_deref()) {
+        (true, Some(secret)) => secret,
+        _ => return Err(HttpError::bat_request(ErrorMessage::TwoFactorNotSetUp)),
+    };
+
+    match (&body.code, &body.password) {
+        (Some(code), None) => {
+            let code_matches = accept_totp_code(&app_state, user.id, secret, code).await?;
+            if !code_matches {
+                return Err(HttpError::bat_request(ErrorMessage::InvalidTotpCode));
+            }
+        }
+        (None, Some(body_password)) => {
+            let password_matches = password::compare(body_password, &user.password)
+                .map_err(|_| HttpError::bat_request(ErrorMessage::IncorrectPassword))?;
+            if !password_matches {
+                return Err(HttpError::bat_request(ErrorMessage::IncorrectPassword));
+            }
+        }
+        _ => return Err(HttpError::bat_request(ErrorMessage::ReauthenticationRequired)),
+    }
+
+    let recovery_codes = replace_recovery_codes(&app_state, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(TotpRecoveryCodesResponseDto {
+        status: "success".to_string(),
+        recovery_codes,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/auth/2fa/login",
+    tag = "Two-Factor Authentication Endpoint",
+    request_body(content = TwoFactorLoginDto, description = "Challenge token from login plus a TOTP or recovery code", example = json!({"challengeToken": "challenge-token","code": "123456"})),
+    responses(
+        (status=200, description= "Login successfull", body= UserLoginResponseDto ),
+        (status=400, description= "Validation Errors", body= Response ),
+        (status=401, description= "Invalid challenge or code", body= Response),
+        (status=500, description= "Internal Server Error", body= Response ),
+    )
+)]
+#[instrument(skip_all, fields(user_id = field::Empty))]
+pub async fn login(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+    body: web::Json<TwoFactorLoginDto>,
+) -> Result<HttpResponse, HttpError> {
+    body.validate()
+        .map_err(HttpError::validation)?;
+
+    let user_id = app_state
+        .token_service
+        .decode_two_factor_challenge(&body.challenge_token)?;
+    let user_id = uuid::Uuid::parse_str(&user_id)
+        .map_err(|_| HttpError::unauthorized(ErrorMessage::InvalidToken))?;
+    Span::current().record("user_id", field::display(user_id));
+
+    let user = app_state
+        .db_client
+        .get_user(Some(user_id), None, None)
+        .await?
+        .ok_or(HttpError::unauthorized(ErrorMessage::UserNoLongerExist))?;
+
+    if let Some(error) = user.status.lock_error() {
+        return Err(HttpError::forbidden(error));
+    }
+
+    let secret = match (user.totp_enabled, user.totp_secret.as_deref()) {
+        (true, Some(secret)) => secret,
+        _ => return Err(HttpError::unauthorized(ErrorMessage::TwoFactorNotSetUp)),
+    };
+
+    let code_matches = accept_totp_code(&app_state, user.id, secret, &body.code).await?;
+
+    if !code_matches {
+        let recovery_code_hash = token::hash_token(&totp::normalize_recovery_code(&body.code));
+        let recovery_code_used = app_state
+            .db_client
+            .use_recovery_code(user.id, &recovery_code_hash)
+            .await?;
+
+        if !recovery_code_used {
+            app_state.metrics.login_failed();
+            audit::record(
+                &app_state,
+                &req,
+                Some(user.id),
+                AuditAction::LoginFailed,
+                Some("Invalid two-factor code".to_string()),
+            );
+            return Err(HttpError::unauthorized(ErrorMessage::InvalidTotpCode));
+        }
+    }
+
+    issue_tokens(&req, &app_state, &user, body.remember_me).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Email;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+    use serde_json::json;
+    use sqlx::PgPool;
+    use totp_rs::{Algorithm, Secret, TOTP};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn login_refuses_a_totp_code_that_was_already_used(pool: PgPool) {
+        crate::run_on_actix(async move {
+            let app_state = AppState::for_tests(pool);
+            let user = app_state
+                .db_client
+                .save_user("John", &Email::parse("john@example.com").unwrap(), "hashed")
+                .await
+                .unwrap();
+            app_state.db_client.verify_user(user.id).await.unwrap();
+            let secret = totp::generate_secret();
+            app_state
+                .db_client
+                .update_user_totp_secret(user.id, Some(secret.clone()))
+                .await
+                .unwrap();
+            app_state.db_client.enable_user_totp(user.id).await.unwrap();
+            let secret = Secret::Encoded(secret).to_bytes().unwrap();
+            let code = TOTP::new(Algorithm::SHA1, 6, 0, 30, secret, None, String::new())
+                .unwrap()
+                .generate_current()
+                .unwrap();
+            let challenge_token = app_state
+                .token_service
+                .create_two_factor_challenge(&user.id.to_string(), 5)
+                .unwrap();
+            let app = init_service(
+                App::new()
+                    .app_data(web::Data::new(app_state))
+                    .service(two_factor_handler()),
+            )
+            .await;
+
+            let login = || {
+                TestRequest::post()
+                    .uri("/api/auth/2fa/login")
+                    .set_json(json!({ "challengeToken": challenge_token, "code": code }))
+                    .to_request()
+            };
+
+            assert_eq!(call_service(&app, login()).await.status(), StatusCode::OK);
+            assert_eq!(call_service(&app, login()).await.status(), StatusCode::UNAUTHORIZED);
+        });
+    }
+}
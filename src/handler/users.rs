@@ -1,13 +1,25 @@
-use actix_web::{web, HttpResponse, Scope};
-use validator::Validate;
+use actix_multipart::Multipart;
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Scope};
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use serde_json::json;
+use validator::{Validate, ValidateArgs};
 
 use crate::{
     auth::{Authenticated, RequireAuth}, 
-    db::UserExt, 
-    dtos::{FilterUserDto, RequestQueryDto, UserData, UserListResponseDto, UserResponseDto}, 
-    error::HttpError, 
+    db::{ApiKeyExt, AuditLogExt, DBClient, PhoneVerificationCodeExt, RefreshTokenExt, RevokedTokenExt, RoleUpdate, UserExt, UserFilter}, 
+    dtos::{AdminPasswordUpdateDto, ApiKeyCreatedResponseDto, ApiKeyDto, ApiKeyListResponseDto, CreateApiKeyDto, AuditLogDto, CancelDeletionDto, DeletionRequestResponseDto, DirectoryQueryDto, DirectoryResponseDto, DirectoryUserDto, EmailUpdateDto, FilterUserDto, LoginAttemptDto, LoginHistoryQueryDto, LoginHistoryResponseDto, PermissionsResponseDto, PhoneUpdateDto, ProfileUpdateDto, PhoneVerifyDto, RequestQueryDto, Response, RoleUpdateDto, SessionDto, SessionListResponseDto, StatusUpdateDto, UserBatchResponseDto, UserData, UserListResponseDto, UserDataExportDto, UserExportQueryDto, UserPasswordUpdateDto, UserResponseDto, VerifyPasswordDto, VerifyPasswordResponseDto}, 
+    error::{ErrorMessage, HttpError}, 
+    handler::auth::{
+        record_password_history, reject_breached_password, reject_reused_password,
+        send_verification_token,
+    },
+    rate_limit::RateLimit,
     AppState,
-    models::UserRole,
+    audit,
+    models::{AuditAction, Email, Permission, PhoneNumber, SortOrder, User, UserSortField},
+    utils::{password, token::{self, TokenClaims}},
+    webhook::{WebhookEvent, WebhookPayload},
 };
 
 pub fn users_handler() -> Scope {
@@ -16,15 +28,169 @@ pub fn users_handler() -> Scope {
             "", 
             web::get()
             .to(get_users)
-            .wrap(RequireAuth::allowed_roles(vec![UserRole::Admin])),
+            .wrap(RequireAuth::permission(Permission::UsersList)),
         )
         .route(
             "/me", 
-            web::get().to(get_me).wrap(RequireAuth::allowed_roles(vec![
-                UserRole::User,
-                UserRole::Moderator,
-                UserRole::Admin,
-            ])),
+            web::get().to(get_me).wrap(RequireAuth::permission(Permission::ProfileRead)),
+        )
+        .route(
+            "/me",
+            web::patch()
+                .to(update_me)
+                .wrap(RequireAuth::any_authenticated().deny_impersonation()),
+        )
+        .route(
+            "/me",
+            web::delete()
+                .to(delete_me)
+                .wrap(RequireAuth::any_authenticated().deny_impersonation()),
+        )
+        .route(
+            "/me/delete-request",
+            web::post()
+                .to(request_deletion)
+                .wrap(RequireAuth::any_authenticated().deny_impersonation()),
+        )
+        .route(
+            "/me/cancel-delete",
+            web::post()
+                .to(cancel_deletion)
+                .wrap(RateLimit::new(10, std::time::Duration::from_secs(60))),
+        )
+        .route(
+            "/me/export",
+            web::get()
+                .to(export_me)
+                .wrap(RequireAuth::any_authenticated().deny_impersonation()),
+        )
+        .route(
+            "/me/permissions",
+            web::get()
+                .to(get_my_permissions)
+                .wrap(RequireAuth::permission(Permission::ProfileRead)),
+        )
+        .route(
+            "/me/login-history",
+            web::get().to(get_login_history).wrap(RequireAuth::any_authenticated()),
+        )
+        .route(
+            "/me/sessions",
+            web::get().to(get_sessions).wrap(RequireAuth::any_authenticated()),
+        )
+        .route(
+            "/me/sessions/{id}",
+            web::delete().to(revoke_session).wrap(RequireAuth::any_authenticated()),
+        )
+        .route(
+            "/me/api-keys",
+            web::post()
+                .to(create_api_key)
+                .wrap(RequireAuth::any_authenticated().deny_impersonation()),
+        )
+        .route(
+            "/me/api-keys",
+            web::get()
+                .to(get_api_keys)
+                .wrap(RequireAuth::any_authenticated().deny_impersonation()),
+        )
+        .route(
+            "/me/api-keys/{id}",
+            web::delete()
+                .to(revoke_api_key)
+                .wrap(RequireAuth::any_authenticated().deny_impersonation()),
+        )
+        .route(
+            "/me/password",
+            web::put()
+                .to(update_user_password)
+                .wrap(RequireAuth::any_authenticated().deny_impersonation()),
+        )
+        .route(
+            "/me/verify-password",
+            web::post()
+                .to(verify_password)
+                .wrap(RateLimit::new(5, std::time::Duration::from_secs(300)).per_user())
+                .wrap(RequireAuth::any_authenticated().deny_impersonation()),
+        )
+        .route(
+            "/me/email",
+            web::put()
+                .to(update_email)
+                .wrap(RequireAuth::any_authenticated().deny_impersonation()),
+        )
+        .route(
+            "/me/phone",
+            web::put()
+                .to(update_phone)
+                // Every request sends a text, which costs money.
+                .wrap(RateLimit::new(3, std::time::Duration::from_secs(600)).per_user())
+                .wrap(RequireAuth::any_authenticated().deny_impersonation()),
+        )
+        .route(
+            "/me/phone/verify",
+            web::post()
+                .to(verify_phone)
+                .wrap(RateLimit::new(5, std::time::Duration::from_secs(300)).per_user())
+                .wrap(RequireAuth::any_authenticated().deny_impersonation()),
+        )
+        .route(
+            "/me/photo",
+            web::put().to(update_photo).wrap(RequireAuth::any_authenticated()),
+        )
+        .route(
+            "/batch",
+            web::post()
+            .to(get_users_batch)
+            .wrap(RequireAuth::permission(Permission::UsersRead)),
+        )
+        .route(
+            "/directory",
+            web::get()
+            .to(get_directory)
+            .wrap(RequireAuth::permission(Permission::UsersDirectory)),
+        )
+        .route(
+            "/export.csv",
+            web::get()
+            .to(export_users_csv)
+            .wrap(RequireAuth::permission(Permission::UsersExport)),
+        )
+        .route(
+            "/{id}",
+            web::get()
+            .to(get_user)
+            .wrap(RequireAuth::permission(Permission::UsersRead)),
+        )
+        .route(
+            "/{id}",
+            web::delete()
+            .to(delete_user)
+            .wrap(RequireAuth::permission(Permission::UsersDelete)),
+        )
+        .route(
+            "/{id}/role",
+            web::put()
+            .to(update_user_role)
+            .wrap(RequireAuth::permission(Permission::UsersUpdateRole).deny_impersonation()),
+        )
+        .route(
+            "/{id}/status",
+            web::put()
+            .to(update_user_status)
+            .wrap(RequireAuth::permission(Permission::UsersUpdateStatus).deny_impersonation()),
+        )
+        .route(
+            "/{id}/password",
+            web::put()
+            .to(set_user_password)
+            .wrap(RequireAuth::permission(Permission::UsersSetPassword).deny_impersonation()),
+        )
+        .route(
+            "/{id}/restore",
+            web::post()
+            .to(restore_user)
+            .wrap(RequireAuth::permission(Permission::UsersRestore)),
         )
 }
 
@@ -38,7 +204,8 @@ pub fn users_handler() -> Scope {
        
     ),
     security(
-       ("token" = [])
+       ("token" = []),
+       ("api_key" = [])
    )
 )]
 pub async fn get_me(user: Authenticated) -> Result<HttpResponse, HttpError> {
@@ -54,6 +221,519 @@ pub async fn get_me(user: Authenticated) -> Result<HttpResponse, HttpError> {
     Ok(HttpResponse::Ok().json(response_data))
 }
 
+#[utoipa::path(
+    patch,
+    path = "/api/users/me",
+    tag = "Update Profile Endpoint",
+    request_body(content = ProfileUpdateDto, description = "Fields to change; absent fields are left as they are", example = json!({"name": "John Doe", "email": "johndoe@example.com", "photo": "default.png"})),
+    responses(
+        (status = 200, description= "Profile updated; a changed email awaits verification", body = UserResponseDto),
+        (status=400, description= "Validation Errors", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=409, description= "User with email already exists", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn update_me(
+    user: Authenticated,
+    body: web::Json<ProfileUpdateDto>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    body.validate_args(&app_state.env.name_policy)
+        .map_err(HttpError::validation)?;
+
+    // Resubmitting the current email or photo doesn't count as a change, so it
+    // neither triggers re-verification nor deletes the photo.
+    let email = body
+        .email
+        .as_deref()
+        .map(Email::parse)
+        .transpose()
+        .map_err(HttpError::bat_request)?
+        .filter(|email| !email.eq_ignore_ascii_case(&user.email));
+    let photo = body.photo.as_deref().filter(|photo| *photo != user.photo);
+
+    if body.name.is_none() && email.is_none() && photo.is_none() {
+        return Ok(HttpResponse::Ok().json(UserResponseDto {
+            status: "success".to_string(),
+            data: UserData {
+                user: FilterUserDto::filter_user(&user),
+            },
+        }));
+    }
+
+    let result = app_state
+        .db_client
+        .update_user_profile(user.id, body.name.as_deref(), email.as_ref(), photo)
+        .await;
+
+    match result {
+        Ok(None) => Err(HttpError::unauthorized(ErrorMessage::UserNoLongerExist)),
+        Ok(Some(updated_user)) => {
+            if email.is_some() {
+                send_verification_token(&app_state, &updated_user).await?;
+            }
+            if photo.is_some() {
+                remove_uploaded_photo(&app_state, &user.photo).await;
+            }
+
+            Ok(HttpResponse::Ok().json(UserResponseDto {
+                status: "success".to_string(),
+                data: UserData {
+                    user: FilterUserDto::filter_user(&updated_user),
+                },
+            }))
+        }
+        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            Err(HttpError::unique_constraint_voilation(
+                ErrorMessage::EmailExist,
+            ))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users/me/permissions",
+    tag = "Get Authenticated User Permissions Endpoint",
+    responses(
+        (status = 200, description= "Capabilities granted by the authenticated user's role", body = PermissionsResponseDto),
+        (status=401, description= "Authentication Error", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = []),
+       ("api_key" = [])
+   )
+)]
+pub async fn get_my_permissions(user: Authenticated) -> Result<HttpResponse, HttpError> {
+    Ok(HttpResponse::Ok().json(PermissionsResponseDto {
+        status: "success".to_string(),
+        role: user.role,
+        permissions: user.role.permissions().to_vec(),
+    }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/users/me/password",
+    tag = "Update Password Endpoint",
+    request_body(content = UserPasswordUpdateDto, example = json!({"oldPassword": "Passw0rd!old","newPassword": "Passw0rd!new","newPasswordConfirm": "Passw0rd!new"})),
+    responses(
+        (status = 200, description= "Password updated", body = Response),
+        (status=400, description= "Validation Errors or incorrect old password", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn update_user_password(
+    req: HttpRequest,
+    user: Authenticated,
+    body: web::Json<UserPasswordUpdateDto>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    body.validate_args(&app_state.env.password_policy)
+        .map_err(HttpError::validation)?;
+
+    let password_matches = password::compare(&body.old_password, &user.password)
+        .map_err(|_| HttpError::bat_request(ErrorMessage::IncorrectOldPassword))?;
+
+    if !password_matches {
+        return Err(HttpError::bat_request(ErrorMessage::IncorrectOldPassword));
+    }
+
+    reject_breached_password(&app_state, &body.new_password).await?;
+    reject_reused_password(&app_state, &user, &body.new_password).await?;
+
+    let hashed_password = password::hash(&body.new_password, &app_state.env.argon2_params)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    record_password_history(&app_state, &user).await?;
+
+    app_state
+        .db_client
+        .update_user_password(user.id, hashed_password)
+        .await?
+        .ok_or(HttpError::unauthorized(ErrorMessage::UserNoLongerExist))?;
+
+    audit::record(&app_state, &req, Some(user.id), AuditAction::PasswordChange, None);
+
+    Ok(HttpResponse::Ok().json(Response {
+        status: "success",
+        message: "Password updated successfully".to_string(),
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/users/me/verify-password",
+    tag = "Verify Password Endpoint",
+    request_body(content = VerifyPasswordDto, example = json!({"password": "password123"})),
+    responses(
+        (status = 200, description= "Whether the password is the authenticated user's current one", body = VerifyPasswordResponseDto),
+        (status=400, description= "Validation Errors", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Not allowed while impersonating", body= Response),
+        (status=429, description= "Too many attempts", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn verify_password(
+    user: Authenticated,
+    body: web::Json<VerifyPasswordDto>,
+) -> Result<HttpResponse, HttpError> {
+    body.validate()
+        .map_err(HttpError::validation)?;
+
+    let valid = password::compare(&body.password, &user.password)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(HttpResponse::Ok().json(VerifyPasswordResponseDto {
+        status: "success".to_string(),
+        valid,
+    }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/users/me/email",
+    tag = "Update Email Endpoint",
+    request_body(content = EmailUpdateDto, example = json!({"email": "johndoe@example.com"})),
+    responses(
+        (status = 200, description= "Email updated and awaiting verification", body = UserResponseDto),
+        (status=400, description= "Validation Errors", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=409, description= "User with email already exists", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn update_email(
+    user: Authenticated,
+    body: web::Json<EmailUpdateDto>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    body.validate()
+        .map_err(HttpError::validation)?;
+    let email = Email::parse(&body.email).map_err(HttpError::bat_request)?;
+
+    // Re-submitting the current address is a no-op rather than a uniqueness error.
+    if email.eq_ignore_ascii_case(&user.email) {
+        return Ok(HttpResponse::Ok().json(UserResponseDto {
+            status: "success".to_string(),
+            data: UserData {
+                user: FilterUserDto::filter_user(&user),
+            },
+        }));
+    }
+
+    let result = app_state
+        .db_client
+        .update_user_email(user.id, &email)
+        .await;
+
+    match result {
+        Ok(None) => Err(HttpError::unauthorized(ErrorMessage::UserNoLongerExist)),
+        Ok(Some(updated_user)) => {
+            send_verification_token(&app_state, &updated_user).await?;
+
+            Ok(HttpResponse::Ok().json(UserResponseDto {
+                status: "success".to_string(),
+                data: UserData {
+                    user: FilterUserDto::filter_user(&updated_user),
+                },
+            }))
+        }
+        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            Err(HttpError::unique_constraint_voilation(
+                ErrorMessage::EmailExist,
+            ))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/users/me/phone",
+    tag = "Update Phone Endpoint",
+    request_body(content = PhoneUpdateDto, example = json!({"phone": "+14155550123"})),
+    responses(
+        (status = 200, description= "Phone number set and a verification code texted to it", body = UserResponseDto),
+        (status=400, description= "Validation Errors", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Phone verification is disabled on this server", body= Response),
+        (status=429, description= "Too many requests", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn update_phone(
+    user: Authenticated,
+    body: web::Json<PhoneUpdateDto>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    require_phone_verification_feature(&app_state)?;
+
+    body.validate()
+        .map_err(HttpError::validation)?;
+    let phone = PhoneNumber::parse(&body.phone).map_err(HttpError::bat_request)?;
+
+    // Re-submitting an already verified number doesn't send another code.
+    if user.phone_verified && user.phone.as_deref() == Some(phone.as_str()) {
+        return Ok(HttpResponse::Ok().json(UserResponseDto {
+            status: "success".to_string(),
+            data: UserData {
+                user: FilterUserDto::filter_user(&user),
+            },
+        }));
+    }
+
+    let updated_user = app_state
+        .db_client
+        .update_user_phone(user.id, &phone)
+        .await?
+        .ok_or(HttpError::unauthorized(ErrorMessage::UserNoLongerExist))?;
+
+    send_phone_verification_code(&app_state, updated_user.id, &phone).await?;
+
+    Ok(HttpResponse::Ok().json(UserResponseDto {
+        status: "success".to_string(),
+        data: UserData {
+            user: FilterUserDto::filter_user(&updated_user),
+        },
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/users/me/phone/verify",
+    tag = "Verify Phone Endpoint",
+    request_body(content = PhoneVerifyDto, description = "Code texted to the phone number", example = json!({"code": "123456"})),
+    responses(
+        (status = 200, description= "Phone number verified", body = UserResponseDto),
+        (status=400, description= "No phone number set, or an invalid or expired code", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Phone verification is disabled on this server", body= Response),
+        (status=429, description= "Too many attempts", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn verify_phone(
+    user: Authenticated,
+    body: web::Json<PhoneVerifyDto>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    require_phone_verification_feature(&app_state)?;
+
+    body.validate()
+        .map_err(HttpError::validation)?;
+
+    let phone = user
+        .phone
+        .as_deref()
+        .ok_or(HttpError::bat_request(ErrorMessage::PhoneNotSet))?;
+    let invalid_code = || HttpError::bat_request(ErrorMessage::InvalidPhoneVerificationCode);
+
+    let code = app_state
+        .db_client
+        .get_phone_verification_code(user.id)
+        .await?
+        .ok_or_else(invalid_code)?;
+
+    // A code only vouches for the number it was sent to.
+    if code.phone != phone
+        || code.expires_at <= Utc::now()
+        || code.attempts >= MAX_PHONE_VERIFICATION_ATTEMPTS
+    {
+        return Err(invalid_code());
+    }
+
+    if code.code_hash != token::hash_token(&body.code) {
+        let attempts = app_state
+            .db_client
+            .record_phone_verification_attempt(user.id)
+            .await?;
+        // Six digits are guessable, so the code is burned after a few misses.
+        if attempts >= MAX_PHONE_VERIFICATION_ATTEMPTS {
+            app_state
+                .db_client
+                .delete_phone_verification_code(user.id)
+                .await?;
+        }
+        return Err(invalid_code());
+    }
+
+    app_state
+        .db_client
+        .delete_phone_verification_code(user.id)
+        .await?;
+
+    let updated_user = app_state
+        .db_client
+        .verify_user_phone(user.id, phone)
+        .await?
+        .ok_or_else(invalid_code)?;
+
+    Ok(HttpResponse::Ok().json(UserResponseDto {
+        status: "success".to_string(),
+        data: UserData {
+            user: FilterUserDto::filter_user(&updated_user),
+        },
+    }))
+}
+
+const MAX_PHONE_VERIFICATION_ATTEMPTS: i32 = 5;
+
+fn require_phone_verification_feature(app_state: &AppState) -> Result<(), HttpError> {
+    if app_state.env.features.phone_verification {
+        Ok(())
+    } else {
+        Err(HttpError::forbidden(ErrorMessage::PhoneVerificationUnavailable))
+    }
+}
+
+// Like email, a failed delivery is logged rather than surfaced; the user can
+// ask for another code by submitting the number again.
+async fn send_phone_verification_code(
+    app_state: &AppState,
+    user_id: uuid::Uuid,
+    phone: &PhoneNumber,
+) -> Result<(), HttpError> {
+    let code = token::create_phone_verification_code();
+    let expires_at =
+        Utc::now() + chrono::Duration::minutes(app_state.env.phone_verification_code_maxage_minutes);
+
+    app_state
+        .db_client
+        .save_phone_verification_code(user_id, phone.as_str(), &token::hash_token(&code), expires_at)
+        .await?;
+
+    let body = format!(
+        "Your verification code is {}. It expires in {} minutes.",
+        code, app_state.env.phone_verification_code_maxage_minutes
+    );
+    if let Err(e) = app_state.sms_sender.send(phone.as_str(), &body).await {
+        log::error!("Error sending SMS to {}: {}", phone.as_str(), e);
+    }
+
+    Ok(())
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/users/me/photo",
+    tag = "Update Profile Photo Endpoint",
+    request_body(content = PhotoUploadDto, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description= "Photo updated", body = UserResponseDto),
+        (status=400, description= "Missing photo or unsupported file type", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=413, description= "Photo too large", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn update_photo(
+    user: Authenticated,
+    mut payload: Multipart,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let max_size = app_state.env.photo_max_size;
+    let mut upload: Option<(&'static str, Vec<u8>)> = None;
+
+    while let Some(mut field) = payload
+        .try_next()
+        .await
+        .map_err(|e| HttpError::bat_request(e.to_string()))?
+    {
+        if field.name() != "photo" {
+            continue;
+        }
+
+        let extension = match field.content_type().map(|mime| mime.essence_str()) {
+            Some("image/jpeg") => "jpg",
+            Some("image/png") => "png",
+            _ => return Err(HttpError::bat_request(ErrorMessage::UnsupportedPhotoType)),
+        };
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field
+            .try_next()
+            .await
+            .map_err(|e| HttpError::bat_request(e.to_string()))?
+        {
+            if bytes.len() + chunk.len() > max_size {
+                return Err(HttpError::payload_too_large(ErrorMessage::PhotoTooLarge(max_size)));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        upload = Some((extension, bytes));
+        break;
+    }
+
+    let (extension, bytes) = upload.ok_or(HttpError::bat_request(ErrorMessage::PhotoRequired))?;
+
+    // The declared content type is client-controlled, so check the magic bytes too.
+    let signature_matches = match extension {
+        "png" => bytes.starts_with(b"\x89PNG\r\n\x1a\n"),
+        _ => bytes.starts_with(&[0xFF, 0xD8, 0xFF]),
+    };
+    if !signature_matches {
+        return Err(HttpError::bat_request(ErrorMessage::UnsupportedPhotoType));
+    }
+
+    let file_name = format!("{}-{}.{}", user.id, uuid::Uuid::new_v4(), extension);
+    let file_path = std::path::Path::new(&app_state.env.photo_upload_dir).join(&file_name);
+
+    web::block(move || std::fs::write(file_path, bytes))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let updated_user = app_state
+        .db_client
+        .update_user_photo(user.id, format!("/uploads/{}", file_name))
+        .await?
+        .ok_or(HttpError::unauthorized(ErrorMessage::UserNoLongerExist))?;
+
+    remove_uploaded_photo(&app_state, &user.photo).await;
+
+    Ok(HttpResponse::Ok().json(UserResponseDto {
+        status: "success".to_string(),
+        data: UserData {
+            user: FilterUserDto::filter_user(&updated_user),
+        },
+    }))
+}
+
+// Best effort: a leftover previous upload is harmless.
+async fn remove_uploaded_photo(app_state: &AppState, photo: &str) {
+    if let Some(previous) = photo.strip_prefix("/uploads/") {
+        let previous_path = std::path::Path::new(&app_state.env.photo_upload_dir).join(previous);
+        let _ = web::block(move || std::fs::remove_file(previous_path)).await;
+    }
+}
 
 #[utoipa::path(
     get,
@@ -63,17 +743,21 @@ pub async fn get_me(user: Authenticated) -> Result<HttpResponse, HttpError> {
         RequestQueryDto
     ),
     responses(
-        (status = 200, description= "All Users", body = [UserResponseDto]),
+        (status = 200, description= "All Users", body = UserListResponseDto,
+            headers(("Link" = String, description = "RFC 8288 links with rel next, prev and last; only next when paging by cursor"))),
+        (status=400, description= "Invalid pagination cursor or date range", body= Response),
         (status=401, description= "Authentication Error", body= Response),
         (status=403, description= "Permission Denied Error", body= Response),
         (status= 500, description= "Internal Server Error", body = Response )
        
     ),
     security(
-       ("token" = [])
+       ("token" = []),
+       ("api_key" = [])
    )
 )]
 pub async fn get_users(
+    req: HttpRequest,
     query: web::Query<RequestQueryDto>,
     app_state: web::Data<AppState>,
 ) -> Result<HttpResponse, HttpError> {
@@ -81,20 +765,1256 @@ pub async fn get_users(
 
     query_params
         .validate()
-        .map_err(|e| HttpError::bat_request(e.to_string()))?;
+        .map_err(HttpError::validation)?;
+
+    if let (Some(created_after), Some(created_before)) =
+        (query_params.created_after, query_params.created_before)
+    {
+        if created_after > created_before {
+            return Err(HttpError::bat_request(ErrorMessage::InvalidDateRange));
+        }
+    }
 
     let page = query_params.page.unwrap_or(1);
     let limit = query_params.limit.unwrap_or(10);
+    let filter = UserFilter {
+        search: query_params.search.as_deref(),
+        role: query_params.role,
+        verified: query_params.verified,
+        created_after: query_params.created_after,
+        created_before: query_params.created_before,
+    };
 
-    let users = app_state
+    let users = match query_params.cursor.as_deref() {
+        Some(cursor) => {
+            let cursor = uuid::Uuid::parse_str(cursor)
+                .map_err(|_| HttpError::bat_request(ErrorMessage::InvalidCursor))?;
+
+            app_state
+                .db_client
+                .get_users_after(Some(cursor), filter, limit)
+                .await?
+        }
+        None if filter.search.is_some()
+            || filter.role.is_some()
+            || filter.verified.is_some()
+            || filter.created_after.is_some()
+            || filter.created_before.is_some()
+            || query_params.sort_by.is_some()
+            || query_params.order.is_some() =>
+        {
+            app_state
+                .db_client
+                .search_users(
+                    filter,
+                    query_params.sort_by.unwrap_or(UserSortField::CreatedAt),
+                    query_params.order.unwrap_or(SortOrder::Desc),
+                    page,
+                    limit,
+                )
+                .await?
+        }
+        None => app_state
             .db_client
-            .get_users(page as u32, limit)
-            .await
-            .map_err(|e| HttpError::server_error(e.to_string()))?;
+            .get_users(page, limit)
+            .await?,
+    };
 
-    Ok(HttpResponse::Ok().json(UserListResponseDto {
-        status: "success".to_string(),
-        users: FilterUserDto::filter_users(&users),
-        results: users.len(),
-    }))
-}
\ No newline at end of file
+    let total = app_state
+        .db_client
+        .count_users(filter)
+        .await?;
+
+    let total_pages = (total as usize).div_ceil(limit);
+
+    let next_cursor = if users.len() == limit {
+        users.last().map(|user| user.id.to_string())
+    } else {
+        None
+    };
+
+    // Page numbers mean nothing when walking by cursor, so fall back to the cursor.
+    let has_next = if query_params.cursor.is_some() {
+        next_cursor.is_some()
+    } else {
+        (page as usize) < total_pages
+    };
+
+    let mut links = Vec::new();
+    if query_params.cursor.is_some() {
+        if let Some(cursor) = &next_cursor {
+            links.push(pagination_link(&req, "cursor", cursor, "next"));
+        }
+    } else {
+        let last_page = total_pages.max(1);
+        if has_next {
+            links.push(pagination_link(&req, "page", &(page + 1).to_string(), "next"));
+        }
+        if page > 1 {
+            let prev_page = (page as usize - 1).min(last_page);
+            links.push(pagination_link(&req, "page", &prev_page.to_string(), "prev"));
+        }
+        links.push(pagination_link(&req, "page", &last_page.to_string(), "last"));
+    }
+
+    let mut response = HttpResponse::Ok();
+    if !links.is_empty() {
+        response.insert_header((header::LINK, links.join(", ")));
+    }
+
+    Ok(response.json(UserListResponseDto {
+        status: "success".to_string(),
+        users: FilterUserDto::filter_users(&users),
+        results: users.len(),
+        page,
+        limit,
+        total,
+        total_pages,
+        has_next,
+        has_prev: page > 1,
+        next_cursor,
+    }))
+}
+
+/// Builds one RFC 8288 link to the current request with `param` set to `value`.
+/// Page and cursor are mutually exclusive, so both are dropped first. The target
+/// is relative to the request, which keeps it right behind a proxy.
+fn pagination_link(req: &HttpRequest, param: &str, value: &str, rel: &str) -> String {
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    for (key, existing) in form_urlencoded::parse(req.query_string().as_bytes()) {
+        if key != "page" && key != "cursor" {
+            query.append_pair(&key, &existing);
+        }
+    }
+    query.append_pair(param, value);
+
+    format!("<{}?{}>; rel=\"{}\"", req.path(), query.finish(), rel)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users/directory",
+    tag = "User Directory Endpoint",
+    params(
+        DirectoryQueryDto
+    ),
+    responses(
+        (status = 200, description= "Users with only id, name and role", body = DirectoryResponseDto),
+        (status=400, description= "Invalid pagination parameters", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = []),
+       ("api_key" = [])
+   )
+)]
+pub async fn get_directory(
+    query: web::Query<DirectoryQueryDto>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    query.validate()
+        .map_err(HttpError::validation)?;
+
+    let page = query.page.unwrap_or(1);
+    let limit = query.limit.unwrap_or(10);
+
+    let users = app_state
+        .db_client
+        .get_users(page, limit)
+        .await?;
+
+    let total = app_state
+        .db_client
+        .count_users(UserFilter::default())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(DirectoryResponseDto {
+        status: "success".to_string(),
+        users: DirectoryUserDto::filter_users(&users),
+        results: users.len(),
+        page,
+        limit,
+        total_pages: (total as usize).div_ceil(limit),
+    }))
+}
+
+const CSV_EXPORT_BATCH_SIZE: usize = 500;
+const CSV_HEADER: &str = "id,name,email,role,verified,created_at\r\n";
+
+#[utoipa::path(
+    get,
+    path = "/api/users/export.csv",
+    tag = "Export Users Endpoint",
+    params(
+        UserExportQueryDto
+    ),
+    responses(
+        (status = 200, description= "Every matching user as CSV", content_type = "text/csv", body = String),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+    ),
+    security(
+       ("token" = []),
+       ("api_key" = [])
+   )
+)]
+pub async fn export_users_csv(
+    query: web::Query<UserExportQueryDto>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let UserExportQueryDto { role, verified } = query.into_inner();
+    let db_client = app_state.db_client.clone();
+
+    // Pages through the table by cursor, so only one batch is held in memory at a time.
+    let rows = stream::try_unfold(Some(None), move |cursor: Option<Option<uuid::Uuid>>| {
+        let db_client = db_client.clone();
+        async move {
+            let Some(cursor) = cursor else {
+                return Ok(None);
+            };
+            let filter = UserFilter {
+                role,
+                verified,
+                ..UserFilter::default()
+            };
+
+            let batch = csv_export_batch(&db_client, cursor, filter).await?;
+            let next = match batch.last() {
+                Some(user) if batch.len() == CSV_EXPORT_BATCH_SIZE => Some(Some(user.id)),
+                _ => None,
+            };
+
+            let chunk = batch
+                .iter()
+                .map(|user| csv_row(&FilterUserDto::filter_user(user)))
+                .collect::<String>();
+
+            Ok(Some((web::Bytes::from(chunk), next)))
+        }
+    });
+    let header_row = web::Bytes::from_static(CSV_HEADER.as_bytes());
+    let body = stream::once(async { Ok::<_, actix_web::Error>(header_row) }).chain(rows);
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((header::CONTENT_DISPOSITION, "attachment; filename=\"users.csv\""))
+        .streaming(body))
+}
+
+async fn csv_export_batch(
+    db_client: &DBClient,
+    cursor: Option<uuid::Uuid>,
+    filter: UserFilter<'_>,
+) -> Result<Vec<User>, actix_web::Error> {
+    db_client
+        .get_users_after(cursor, filter, CSV_EXPORT_BATCH_SIZE)
+        .await
+        // Headers are already sent, so the client sees a truncated download.
+        .map_err(|e| HttpError::from(e).into())
+}
+
+fn csv_row(user: &FilterUserDto) -> String {
+    format!(
+        "{},{},{},{},{},{}\r\n",
+        user.id,
+        csv_field(&user.name),
+        csv_field(&user.email),
+        user.role,
+        user.verified,
+        user.created_at.to_rfc3339(),
+    )
+}
+
+/// Quotes a field when needed, and defuses values a spreadsheet would run as a formula.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users/{id}",
+    tag = "Get User By Id Endpoint",
+    params(
+        ("id" = String, Path, description = "User id")
+    ),
+    responses(
+        (status = 200, description= "User", body = UserResponseDto),
+        (status=400, description= "Invalid user id", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status=404, description= "User not found", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = []),
+       ("api_key" = [])
+   )
+)]
+pub async fn get_user(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let user_id = parse_user_id(&path)?;
+
+    let user = app_state
+        .db_client
+        .get_user(Some(user_id), None, None)
+        .await?
+        .ok_or(HttpError::not_found(ErrorMessage::UserNotFound))?;
+
+    Ok(HttpResponse::Ok().json(UserResponseDto {
+        status: "success".to_string(),
+        data: UserData {
+            user: FilterUserDto::filter_user(&user),
+        },
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/users/batch",
+    tag = "Get Users By Ids Endpoint",
+    request_body(content = Vec<String>, description = "User ids to look up", example = json!(["0b9a4c1e-8f2d-4c55-9a3e-2d1f6b7c8e90"])),
+    responses(
+        (status = 200, description= "Users in request order, null where not found", body = UserBatchResponseDto),
+        (status=400, description= "Invalid user id or too many ids", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = []),
+       ("api_key" = [])
+   )
+)]
+pub async fn get_users_batch(
+    body: web::Json<Vec<String>>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let max_size = app_state.env.user_batch_max_size;
+    if body.len() > max_size {
+        return Err(HttpError::bat_request(ErrorMessage::BatchTooLarge(max_size)));
+    }
+
+    let ids = body
+        .iter()
+        .map(|id| parse_user_id(id))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let users = app_state.db_client.get_users_by_ids(&ids).await?;
+
+    let users = ids
+        .iter()
+        .map(|id| {
+            users
+                .iter()
+                .find(|user| user.id == *id)
+                .map(FilterUserDto::filter_user)
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(UserBatchResponseDto {
+        status: "success".to_string(),
+        users,
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/users/me",
+    tag = "Delete Authenticated User Endpoint",
+    responses(
+        (status = 200, description= "Account deleted", body = Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn delete_me(
+    user: Authenticated,
+    claims: web::ReqData<TokenClaims>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    app_state
+        .db_client
+        .soft_delete_user(user.id)
+        .await?;
+
+    app_state
+        .db_client
+        .revoke_user_refresh_tokens(user.id)
+        .await?;
+
+    let expires_at = DateTime::<Utc>::from_timestamp(claims.exp as i64, 0).unwrap_or_else(Utc::now);
+
+    app_state
+        .db_client
+        .revoke_token(&claims.jti, expires_at)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(Response {
+        status: "success",
+        message: "Account deleted successfully".to_string(),
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/users/me/delete-request",
+    tag = "Request Account Deletion Endpoint",
+    responses(
+        (status = 200, description= "Account deactivated and scheduled for permanent deletion", body = DeletionRequestResponseDto),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Not allowed while impersonating", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn request_deletion(
+    req: HttpRequest,
+    user: Authenticated,
+    claims: web::ReqData<TokenClaims>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let purge_after = Utc::now() + chrono::Duration::days(app_state.env.account_deletion_grace_days);
+
+    app_state
+        .db_client
+        .schedule_user_deletion(user.id, purge_after)
+        .await?
+        .ok_or(HttpError::unauthorized(ErrorMessage::UserNoLongerExist))?;
+
+    app_state
+        .db_client
+        .revoke_user_refresh_tokens(user.id)
+        .await?;
+
+    let expires_at = DateTime::<Utc>::from_timestamp(claims.exp as i64, 0).unwrap_or_else(Utc::now);
+
+    app_state
+        .db_client
+        .revoke_token(&claims.jti, expires_at)
+        .await?;
+
+    audit::record(
+        &app_state,
+        &req,
+        Some(user.id),
+        AuditAction::DeletionRequest,
+        Some(format!("Permanent deletion scheduled for {}", purge_after.to_rfc3339())),
+    );
+
+    Ok(HttpResponse::Ok().json(DeletionRequestResponseDto {
+        status: "success".to_string(),
+        purge_after,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/users/me/cancel-delete",
+    tag = "Cancel Account Deletion Endpoint",
+    request_body(content = CancelDeletionDto, description = "Credentials of the account awaiting deletion", example = json!({"email": "johndoe@example.com","password": "password123"})),
+    responses(
+        (status = 200, description= "Deletion cancelled and account restored", body = Response),
+        (status=400, description= "Validation Errors", body= Response),
+        (status=401, description= "Wrong credentials or no pending deletion", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    )
+)]
+pub async fn cancel_deletion(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+    body: web::Json<CancelDeletionDto>,
+) -> Result<HttpResponse, HttpError> {
+    body.validate()
+        .map_err(HttpError::validation)?;
+    let email = Email::parse(&body.email).map_err(HttpError::bat_request)?;
+
+    // The account is soft-deleted and can't log in, so the password proves ownership.
+    let Some(user) = app_state.db_client.get_user_pending_deletion(&email).await? else {
+        password::dummy_compare(&body.password, &app_state.env.argon2_params);
+        return Err(HttpError::unauthorized(ErrorMessage::WrongCredentials));
+    };
+
+    let password_matches = password::compare(&body.password, &user.password)
+        .map_err(|_| HttpError::unauthorized(ErrorMessage::WrongCredentials))?;
+    if !password_matches {
+        return Err(HttpError::unauthorized(ErrorMessage::WrongCredentials));
+    }
+
+    app_state
+        .db_client
+        .cancel_user_deletion(user.id)
+        .await?
+        .ok_or(HttpError::unauthorized(ErrorMessage::WrongCredentials))?;
+
+    audit::record(&app_state, &req, Some(user.id), AuditAction::DeletionCancel, None);
+
+    Ok(HttpResponse::Ok().json(Response {
+        status: "success",
+        message: "Account deletion cancelled, you can log in again".to_string(),
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users/me/export",
+    tag = "Export Authenticated User Data Endpoint",
+    responses(
+        (status = 200, description= "Profile, sessions and audit history of the authenticated user", body = UserDataExportDto),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Not allowed while impersonating", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn export_me(
+    user: Authenticated,
+    claims: web::ReqData<TokenClaims>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let sessions = app_state
+        .db_client
+        .get_user_sessions(user.id)
+        .await?;
+
+    let total = app_state
+        .db_client
+        .count_audit_log(Some(user.id), None)
+        .await?;
+    let audit_log = app_state
+        .db_client
+        .get_audit_log(Some(user.id), None, 1, total.max(1) as usize)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(UserDataExportDto {
+        status: "success".to_string(),
+        exported_at: Utc::now(),
+        user: FilterUserDto::filter_user(&user),
+        two_factor_enabled: user.totp_enabled,
+        sessions: SessionDto::filter_sessions(&sessions, claims.sid.as_deref()),
+        audit_log: AuditLogDto::filter_entries(&audit_log),
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users/me/login-history",
+    tag = "Login History Endpoint",
+    params(
+        LoginHistoryQueryDto
+    ),
+    responses(
+        (status = 200, description= "Successful and failed login attempts, newest first", body = LoginHistoryResponseDto),
+        (status=400, description= "Invalid query parameters", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn get_login_history(
+    user: Authenticated,
+    query: web::Query<LoginHistoryQueryDto>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    login_history_response(&app_state, user.id, &query).await
+}
+
+/// Shared by the user's own login history and the admin view of any user's.
+pub(crate) async fn login_history_response(
+    app_state: &AppState,
+    user_id: uuid::Uuid,
+    query: &LoginHistoryQueryDto,
+) -> Result<HttpResponse, HttpError> {
+    query.validate()
+        .map_err(HttpError::validation)?;
+
+    let page = query.page.unwrap_or(1);
+    let limit = query.limit.unwrap_or(20);
+
+    let attempts = app_state
+        .db_client
+        .get_login_history(user_id, page, limit)
+        .await?;
+
+    let total = app_state
+        .db_client
+        .count_login_history(user_id)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(LoginHistoryResponseDto {
+        status: "success".to_string(),
+        attempts: LoginAttemptDto::filter_attempts(&attempts),
+        results: attempts.len(),
+        page,
+        limit,
+        total_pages: (total as usize).div_ceil(limit),
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users/me/sessions",
+    tag = "List Sessions Endpoint",
+    responses(
+        (status = 200, description= "Active sessions; the one the request was made from is marked current", body = SessionListResponseDto),
+        (status=401, description= "Authentication Error", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn get_sessions(
+    user: Authenticated,
+    claims: web::ReqData<TokenClaims>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let sessions = app_state
+        .db_client
+        .get_user_sessions(user.id)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(SessionListResponseDto {
+        status: "success".to_string(),
+        sessions: SessionDto::filter_sessions(&sessions, claims.sid.as_deref()),
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/users/me/sessions/{id}",
+    tag = "Revoke Session Endpoint",
+    params(
+        ("id" = String, Path, description = "Session id")
+    ),
+    responses(
+        (status = 200, description= "Session revoked", body = Response),
+        (status=400, description= "Invalid session id", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=404, description= "Session not found", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn revoke_session(
+    user: Authenticated,
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let session_id = uuid::Uuid::parse_str(&path.into_inner())
+        .map_err(|_| HttpError::bat_request(ErrorMessage::InvalidSessionId))?;
+
+    let revoked = app_state
+        .db_client
+        .revoke_user_session(user.id, session_id)
+        .await?;
+
+    if !revoked {
+        return Err(HttpError::not_found(ErrorMessage::SessionNotFound));
+    }
+
+    Ok(HttpResponse::Ok().json(Response {
+        status: "success",
+        message: "Session revoked successfully".to_string(),
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/users/me/api-keys",
+    tag = "Create API Key Endpoint",
+    request_body(content = CreateApiKeyDto, example = json!({"name": "CI export", "scopes": ["users:list", "users:read"], "expiresAt": "2025-01-01T00:00:00Z"})),
+    responses(
+        (status = 201, description= "API key created; the full key is only returned here", body = ApiKeyCreatedResponseDto),
+        (status=400, description= "Validation Errors", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Scope not granted by the user's role", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn create_api_key(
+    user: Authenticated,
+    body: web::Json<CreateApiKeyDto>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    body.validate()
+        .map_err(HttpError::validation)?;
+
+    if body.scopes.iter().any(|scope| !user.role.has_permission(*scope)) {
+        return Err(HttpError::forbidden(ErrorMessage::ApiKeyScopeNotGranted));
+    }
+    if body.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        return Err(HttpError::bat_request(ErrorMessage::ApiKeyExpiryInPast));
+    }
+
+    // Stored in `Permission::ALL` order so duplicates collapse.
+    let scopes: Vec<String> = Permission::ALL
+        .into_iter()
+        .filter(|permission| body.scopes.contains(permission))
+        .map(|permission| permission.to_str().to_string())
+        .collect();
+
+    let key = token::create_api_key();
+    let api_key = app_state
+        .db_client
+        .save_api_key(
+            user.id,
+            body.name.trim(),
+            &key[..12],
+            &token::hash_token(&key),
+            &scopes,
+            body.expires_at,
+        )
+        .await?;
+
+    Ok(HttpResponse::Created().json(ApiKeyCreatedResponseDto {
+        status: "success".to_string(),
+        api_key: ApiKeyDto::filter_api_key(&api_key),
+        key,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users/me/api-keys",
+    tag = "List API Keys Endpoint",
+    responses(
+        (status = 200, description= "API keys that have not been revoked, without the secret part", body = ApiKeyListResponseDto),
+        (status=401, description= "Authentication Error", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn get_api_keys(
+    user: Authenticated,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let api_keys = app_state
+        .db_client
+        .get_user_api_keys(user.id)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(ApiKeyListResponseDto {
+        status: "success".to_string(),
+        api_keys: ApiKeyDto::filter_api_keys(&api_keys),
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/users/me/api-keys/{id}",
+    tag = "Revoke API Key Endpoint",
+    params(
+        ("id" = String, Path, description = "API key id")
+    ),
+    responses(
+        (status = 200, description= "API key revoked", body = Response),
+        (status=400, description= "Invalid API key id", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=404, description= "API key not found", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn revoke_api_key(
+    user: Authenticated,
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let key_id = uuid::Uuid::parse_str(&path.into_inner())
+        .map_err(|_| HttpError::bat_request(ErrorMessage::InvalidApiKeyId))?;
+
+    let revoked = app_state
+        .db_client
+        .revoke_api_key(user.id, key_id)
+        .await?;
+
+    if !revoked {
+        return Err(HttpError::not_found(ErrorMessage::ApiKeyNotFound));
+    }
+
+    Ok(HttpResponse::Ok().json(Response {
+        status: "success",
+        message: "API key revoked successfully".to_string(),
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/users/{id}",
+    tag = "Delete User Endpoint",
+    params(
+        ("id" = String, Path, description = "User id")
+    ),
+    responses(
+        (status = 200, description= "User deleted", body = UserResponseDto),
+        (status=400, description= "Invalid user id", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status=404, description= "User not found", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = []),
+       ("api_key" = [])
+   )
+)]
+pub async fn delete_user(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let user_id = parse_user_id(&path)?;
+
+    let user = app_state
+        .db_client
+        .soft_delete_user(user_id)
+        .await?
+        .ok_or(HttpError::not_found(ErrorMessage::UserNotFound))?;
+
+    app_state
+        .db_client
+        .revoke_user_refresh_tokens(user.id)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(UserResponseDto {
+        status: "success".to_string(),
+        data: UserData {
+            user: FilterUserDto::filter_user(&user),
+        },
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/users/{id}/restore",
+    tag = "Restore User Endpoint",
+    params(
+        ("id" = String, Path, description = "User id")
+    ),
+    responses(
+        (status = 200, description= "User restored", body = UserResponseDto),
+        (status=400, description= "Invalid user id", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status=404, description= "No deleted user with this id", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = []),
+       ("api_key" = [])
+   )
+)]
+pub async fn restore_user(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, HttpError> {
+    let user_id = parse_user_id(&path)?;
+
+    let user = app_state
+        .db_client
+        .restore_user(user_id)
+        .await?
+        .ok_or(HttpError::not_found(ErrorMessage::UserNotFound))?;
+
+    Ok(HttpResponse::Ok().json(UserResponseDto {
+        status: "success".to_string(),
+        data: UserData {
+            user: FilterUserDto::filter_user(&user),
+        },
+    }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/users/{id}/role",
+    tag = "Update User Role Endpoint",
+    params(
+        ("id" = String, Path, description = "User id")
+    ),
+    request_body(content = RoleUpdateDto, description = "New role for the user", example = json!({"role": "moderator"})),
+    responses(
+        (status = 200, description= "Role updated", body = UserResponseDto),
+        (status=400, description= "Invalid user id or last admin demotion", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status=404, description= "User not found", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn update_user_role(
+    req: HttpRequest,
+    actor: Authenticated,
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+    body: web::Json<RoleUpdateDto>,
+) -> Result<HttpResponse, HttpError> {
+    body.validate()
+        .map_err(HttpError::validation)?;
+
+    if !actor.role.can_assign(body.role) {
+        return Err(HttpError::forbidden(ErrorMessage::PermissionDenied));
+    }
+
+    let user_id = parse_user_id(&path)?;
+
+    let target = app_state
+        .db_client
+        .get_user(Some(user_id), None, None)
+        .await?
+        .ok_or(HttpError::not_found(ErrorMessage::UserNotFound))?;
+
+    let user = match app_state.db_client.update_user_role(user_id, body.role).await? {
+        RoleUpdate::Updated(user) => user,
+        RoleUpdate::UserNotFound => return Err(HttpError::not_found(ErrorMessage::UserNotFound)),
+        RoleUpdate::LastAdmin => return Err(HttpError::bat_request(ErrorMessage::LastAdminDemotion)),
+    };
+
+    audit::record(
+        &app_state,
+        &req,
+        Some(user.id),
+        AuditAction::RoleChange,
+        Some(format!(
+            "Role changed from {} to {} by {}",
+            target.role.to_str(),
+            user.role.to_str(),
+            actor.id
+        )),
+    );
+    app_state.webhook_dispatcher.dispatch(WebhookPayload::new(
+        WebhookEvent::RoleChanged,
+        user.id,
+        json!({"from": target.role, "to": user.role, "changedBy": actor.id}),
+    ));
+
+    Ok(HttpResponse::Ok().json(UserResponseDto {
+        status: "success".to_string(),
+        data: UserData {
+            user: FilterUserDto::filter_user(&user),
+        },
+    }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/users/{id}/status",
+    tag = "Update User Status Endpoint",
+    params(
+        ("id" = String, Path, description = "User id")
+    ),
+    request_body(content = StatusUpdateDto, description = "New status for the user", example = json!({"status": "suspended"})),
+    responses(
+        (status = 200, description= "Status updated", body = UserResponseDto),
+        (status=400, description= "Invalid user id or own account", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status=404, description= "User not found", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn update_user_status(
+    req: HttpRequest,
+    actor: Authenticated,
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+    body: web::Json<StatusUpdateDto>,
+) -> Result<HttpResponse, HttpError> {
+    let user_id = parse_user_id(&path)?;
+
+    if user_id == actor.id {
+        return Err(HttpError::bat_request(ErrorMessage::CannotChangeOwnStatus));
+    }
+
+    let target = app_state
+        .db_client
+        .get_user(Some(user_id), None, None)
+        .await?
+        .ok_or(HttpError::not_found(ErrorMessage::UserNotFound))?;
+
+    let user = app_state
+        .db_client
+        .update_user_status(user_id, body.status)
+        .await?
+        .ok_or(HttpError::not_found(ErrorMessage::UserNotFound))?;
+
+    // Access tokens are rejected by RequireAuth once the status changes; this stops refreshes.
+    if user.status.lock_error().is_some() {
+        app_state
+            .db_client
+            .revoke_user_refresh_tokens(user.id)
+            .await?;
+    }
+
+    audit::record(
+        &app_state,
+        &req,
+        Some(user.id),
+        AuditAction::StatusChange,
+        Some(format!(
+            "Status changed from {} to {} by {}",
+            target.status.to_str(),
+            user.status.to_str(),
+            actor.id
+        )),
+    );
+
+    Ok(HttpResponse::Ok().json(UserResponseDto {
+        status: "success".to_string(),
+        data: UserData {
+            user: FilterUserDto::filter_user(&user),
+        },
+    }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/users/{id}/password",
+    tag = "Set User Password Endpoint",
+    params(
+        ("id" = String, Path, description = "User id")
+    ),
+    request_body(content = AdminPasswordUpdateDto, description = "New password for the user", example = json!({"newPassword": "password123","newPasswordConfirm": "password123","revokeSessions": true})),
+    responses(
+        (status = 200, description= "Password set", body = Response),
+        (status=400, description= "Validation Errors or invalid user id", body= Response),
+        (status=401, description= "Authentication Error", body= Response),
+        (status=403, description= "Permission Denied Error", body= Response),
+        (status=404, description= "User not found", body= Response),
+        (status= 500, description= "Internal Server Error", body = Response )
+    ),
+    security(
+       ("token" = [])
+   )
+)]
+pub async fn set_user_password(
+    req: HttpRequest,
+    actor: Authenticated,
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+    body: web::Json<AdminPasswordUpdateDto>,
+) -> Result<HttpResponse, HttpError> {
+    body.validate_args(&app_state.env.password_policy)
+        .map_err(HttpError::validation)?;
+
+    let user_id = parse_user_id(&path)?;
+
+    let target = app_state
+        .db_client
+        .get_user(Some(user_id), None, None)
+        .await?
+        .ok_or(HttpError::not_found(ErrorMessage::UserNotFound))?;
+
+    reject_breached_password(&app_state, &body.new_password).await?;
+    reject_reused_password(&app_state, &target, &body.new_password).await?;
+
+    let hashed_password = password::hash(&body.new_password, &app_state.env.argon2_params)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    record_password_history(&app_state, &target).await?;
+
+    app_state
+        .db_client
+        .update_user_password(target.id, hashed_password)
+        .await?
+        .ok_or(HttpError::not_found(ErrorMessage::UserNotFound))?;
+
+    // Access tokens already issued stay valid until they expire, as with a password reset.
+    if body.revoke_sessions.unwrap_or(true) {
+        app_state
+            .db_client
+            .revoke_user_refresh_tokens(target.id)
+            .await?;
+    }
+
+    audit::record(
+        &app_state,
+        &req,
+        Some(target.id),
+        AuditAction::PasswordChange,
+        Some(format!("Password set by {}", actor.id)),
+    );
+
+    Ok(HttpResponse::Ok().json(Response {
+        status: "success",
+        message: "Password updated successfully".to_string(),
+    }))
+}
+
+fn parse_user_id(id: &str) -> Result<uuid::Uuid, HttpError> {
+    uuid::Uuid::parse_str(id).map_err(|_| HttpError::bat_request(ErrorMessage::InvalidUserId))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::App;
+    use sqlx::PgPool;
+    use std::sync::Arc;
+
+    use crate::models::UserRole;
+    use crate::webhook::RecordingWebhookDispatcher;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn update_user_password_rejects_wrong_old_password(pool: PgPool) {
+        let app_state = AppState::for_tests(pool);
+        let hashed_password = password::hash("Passw0rd!old", &app_state.env.argon2_params).unwrap();
+        let user = app_state
+            .db_client
+            .save_user("John", &Email::parse("john@example.com").unwrap(), &hashed_password)
+            .await
+            .unwrap();
+        app_state.db_client.verify_user(user.id).await.unwrap();
+        let token = app_state
+            .token_service
+            .create_token(&user.id.to_string(), user.role, &user.email, uuid::Uuid::new_v4(), 60)
+            .unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(app_state))
+                .service(users_handler()),
+        )
+        .await;
+
+        let req = TestRequest::put()
+            .uri("/api/users/me/password")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .set_json(json!({
+                "oldPassword": "Wr0ng!password",
+                "newPassword": "Passw0rd!new",
+                "newPasswordConfirm": "Passw0rd!new"
+            }))
+            .to_request();
+        let res = call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = read_body_json(res).await;
+        assert_eq!(body["message"], ErrorMessage::IncorrectOldPassword.to_string());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn update_user_role_dispatches_role_changed_webhook(pool: PgPool) {
+        crate::run_on_actix(async move {
+            let webhook_dispatcher = Arc::new(RecordingWebhookDispatcher::default());
+            let app_state = AppState {
+                webhook_dispatcher: webhook_dispatcher.clone(),
+                ..AppState::for_tests(pool)
+            };
+            let admin = app_state
+                .db_client
+                .save_user("Admin", &Email::parse("admin@example.com").unwrap(), "hashed")
+                .await
+                .unwrap();
+            app_state.db_client.verify_user(admin.id).await.unwrap();
+            app_state.db_client.update_user_role(admin.id, UserRole::Admin).await.unwrap();
+            let user = app_state
+                .db_client
+                .save_user("John", &Email::parse("john@example.com").unwrap(), "hashed")
+                .await
+                .unwrap();
+            let token = app_state
+                .token_service
+                .create_token(&admin.id.to_string(), UserRole::Admin, &admin.email, uuid::Uuid::new_v4(), 60)
+                .unwrap();
+            let app = init_service(
+                App::new()
+                    .app_data(web::Data::new(app_state))
+                    .service(users_handler()),
+            )
+            .await;
+
+            let req = TestRequest::put()
+                .uri(&format!("/api/users/{}/role", user.id))
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+                .set_json(json!({ "role": "moderator" }))
+                .to_request();
+            let res = call_service(&app, req).await;
+
+            assert_eq!(res.status(), StatusCode::OK);
+            let payloads = webhook_dispatcher.payloads.lock().unwrap();
+            assert_eq!(payloads.len(), 1);
+            assert_eq!(payloads[0].event, WebhookEvent::RoleChanged);
+            assert_eq!(payloads[0].user_id, user.id);
+            assert_eq!(payloads[0].data, json!({"from": "user", "to": "moderator", "changedBy": admin.id}));
+        })
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn get_users_reports_the_filtered_total(pool: PgPool) {
+        let app_state = AppState::for_tests(pool);
+        let admin = app_state
+            .db_client
+            .save_user("Admin", &Email::parse("admin@example.com").unwrap(), "hashed")
+            .await
+            .unwrap();
+        app_state.db_client.verify_user(admin.id).await.unwrap();
+        app_state.db_client.update_user_role(admin.id, UserRole::Admin).await.unwrap();
+        for email in ["john@example.com", "jane@example.com"] {
+            app_state
+                .db_client
+                .save_user("User", &Email::parse(email).unwrap(), "hashed")
+                .await
+                .unwrap();
+        }
+        let token = app_state
+            .token_service
+            .create_token(&admin.id.to_string(), UserRole::Admin, &admin.email, uuid::Uuid::new_v4(), 60)
+            .unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(app_state))
+                .service(users_handler()),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/api/users?role=user&limit=1")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        let res = call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(res).await;
+        assert_eq!(body["results"], 1);
+        assert_eq!(body["total"], 2);
+        assert_eq!(body["totalPages"], 2);
+    }
+}
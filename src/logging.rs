@@ -0,0 +1,172 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use chrono::{SecondsFormat, Utc};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use futures_util::FutureExt;
+use serde_json::json;
+use std::io::Write;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::request_id;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("Unsupported log format: {}", other)),
+        }
+    }
+}
+
+/// Installs the global logger. `RUST_LOG` still controls filtering in both formats.
+pub fn init(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+
+    if format == LogFormat::Text {
+        // env_logger's own layout, plus the id of the request being handled so
+        // errors logged from inside a handler can be matched to the access log.
+        builder.format(|buf, record| {
+            write!(
+                buf,
+                "[{} {:<5} {}] {}",
+                Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+                record.level(),
+                record.target(),
+                record.args()
+            )?;
+            match request_id::current() {
+                Some(id) => writeln!(buf, " request_id={}", id),
+                None => writeln!(buf),
+            }
+        });
+    }
+
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let message = record.args().to_string();
+            let (message, request_id) = match request_id::current() {
+                Some(id) => (message.as_str(), Some(id)),
+                None => split_request_id(&message),
+            };
+
+            writeln!(
+                buf,
+                "{}",
+                json!({
+                    "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": message,
+                    "request_id": request_id,
+                })
+            )
+        });
+    }
+
+    builder.init();
+}
+
+// Access log lines are written once the response body is done, after the request's
+// task-local id is gone, so the id is read back from the Logger format's suffix.
+fn split_request_id(message: &str) -> (&str, Option<String>) {
+    match message.rsplit_once(" request_id=") {
+        Some((rest, id)) if !id.is_empty() && id != "-" && !id.contains(' ') => {
+            (rest, Some(id.to_string()))
+        }
+        _ => (message, None),
+    }
+}
+
+/// Logs a warning for every request that takes longer than `threshold` to
+/// produce its response, with the route pattern rather than the raw path.
+pub struct SlowRequestMiddleware {
+    threshold: Duration,
+}
+
+impl SlowRequestMiddleware {
+    pub fn new(threshold: Duration) -> Self {
+        SlowRequestMiddleware { threshold }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SlowRequestMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = SlowRequestService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SlowRequestService {
+            service: Rc::new(service),
+            threshold: self.threshold,
+        }))
+    }
+}
+
+pub struct SlowRequestService<S> {
+    service: Rc<S>,
+    threshold: Duration,
+}
+
+impl<S, B> Service<ServiceRequest> for SlowRequestService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, actix_web::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let method = req.method().to_string();
+        let route = req
+            .match_pattern()
+            .unwrap_or_else(|| req.path().to_string());
+        let threshold = self.threshold;
+        let started = Instant::now();
+
+        let srv = Rc::clone(&self.service);
+
+        async move {
+            let result = srv.call(req).await;
+
+            let elapsed = started.elapsed();
+            if elapsed >= threshold {
+                let status = match &result {
+                    Ok(res) => res.status(),
+                    Err(e) => e.as_response_error().status_code(),
+                };
+                log::warn!(
+                    "Slow request: {} {} answered {} in {} ms",
+                    method,
+                    route,
+                    status.as_u16(),
+                    elapsed.as_millis()
+                );
+            }
+
+            result
+        }
+        .boxed_local()
+    }
+}
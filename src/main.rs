@@ -6,46 +6,116 @@ mod utils;
 mod db;
 mod auth;
 mod handler;
+mod rate_limit;
+mod session_store;
+mod breach;
+mod email;
+mod request_id;
+mod metrics;
+mod cli;
+mod audit;
+mod logging;
+mod webhook;
+mod sms;
+mod tls;
+mod security_headers;
+mod telemetry;
 
 use actix_cors::Cors;
+use actix_files::Files;
 use actix_web::{
-    get, http::header, middleware::Logger, web, App, HttpResponse, HttpServer, Responder,
+    get,
+    http::header,
+    middleware::{Compress, Condition, Logger},
+    web, App, HttpResponse, HttpServer, Responder,
 };
 use config::Config;
-use db::DBClient;
+use db::{DBClient, IdempotencyKeyExt, RevokedTokenExt, UserExt};
 use dotenv::dotenv;
+use error::HttpError;
+use email::{EmailSender, LogEmailSender, SmtpEmailSender};
+use breach::{BreachChecker, HibpBreachChecker};
+use session_store::{InMemorySessionStore, RedisSessionStore, SessionStore};
+use logging::SlowRequestMiddleware;
+use request_id::RequestIdMiddleware;
+use security_headers::SecurityHeadersMiddleware;
+use telemetry::TracingMiddleware;
+use metrics::{Metrics, MetricsMiddleware};
+use sms::{LogSmsSender, SmsSender, TwilioSmsSender};
+use webhook::{HttpWebhookDispatcher, NoopWebhookDispatcher, WebhookDispatcher};
 use dtos::{
-    FilterUserDto, LoginUserDto, RegisterUserDto, Response, UserData, UserListResponseDto,
-    UserLoginResponseDto, UserResponseDto,
+    AdminPasswordUpdateDto, LoginAttemptDto, LoginHistoryResponseDto, ApiKeyCreatedResponseDto, ApiKeyDto, ApiKeyListResponseDto, CreateApiKeyDto, AuditLogDto, CancelDeletionDto, DeletionRequestResponseDto, UserDataExportDto, IntrospectRequestDto, IntrospectResponseDto, AuditLogListResponseDto, DirectoryResponseDto, DirectoryUserDto, RoleCountDto, RoleStatsResponseDto, UsersByRoleResponseDto, RegistrationSettingDto, RegistrationSettingResponseDto, CreateInvitationDto, InvitationDto, InvitationCreatedResponseDto, EmailAvailabilityResponseDto, EmailUpdateDto, ProfileUpdateDto, PhoneUpdateDto, PhoneVerifyDto, FilterUserDto, ForgotPasswordDto, LoginUserDto, PhotoUploadDto, RegisterUserDto, ResetPasswordDto, Response,
+    RoleUpdateDto, PermissionsResponseDto, RoleAssignmentDto, BulkRoleUpdateDto, BulkRoleUpdateResponseDto, SessionDto, SessionListResponseDto, StatusUpdateDto, TokenClaimsResponseDto, TokenStatusResponseDto, TotpCodeDto, TotpRecoveryCodesResponseDto, RecoveryCodesRegenerateDto, RecoveryCodesStatusResponseDto, TotpSetupResponseDto, TwoFactorChallengeResponseDto,
+    TwoFactorLoginDto, UserBatchResponseDto, VerifyPasswordDto, VerifyPasswordResponseDto, UserData, UserListResponseDto, UserLoginResponseDto, UserPasswordUpdateDto,
+    UserResponseDto,
 };
 use sqlx::postgres::PgPoolOptions;
 use utoipa::{
-    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
     Modify, OpenApi,
 };
 use utoipa_rapidoc::RapiDoc;
 use utoipa_redoc::{Redoc, Servable};
 use utoipa_swagger_ui::SwaggerUi;
 
-use handler::{auth as authHandler, users};
+use handler::{admin, auth as authHandler, two_factor, users};
+use models::{AuditAction, Permission, SortOrder, UserRole, UserSortField, UserStatus};
+use utils::token::{JwtTokenService, TokenClaims, TokenService};
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub env: Config,
     pub db_client: DBClient,
+    pub session_store: Arc<dyn SessionStore>,
+    pub breach_checker: Arc<dyn BreachChecker>,
+    pub email_sender: Arc<dyn EmailSender>,
+    pub sms_sender: Arc<dyn SmsSender>,
+    pub webhook_dispatcher: Arc<dyn WebhookDispatcher>,
+    pub metrics: Arc<Metrics>,
+    pub token_service: Arc<dyn TokenService>,
+}
+
+#[cfg(test)]
+impl AppState {
+    /// State for handler tests: the `.env` configuration over `pool`, with
+    /// emails, texts and webhooks going nowhere.
+    pub fn for_tests(pool: sqlx::PgPool) -> Self {
+        let config = Config::for_tests();
+
+        AppState {
+            token_service: Arc::new(JwtTokenService::new(&config)),
+            env: config,
+            db_client: DBClient::new(pool),
+            session_store: Arc::new(InMemorySessionStore::new()),
+            breach_checker: Arc::new(HibpBreachChecker::new()),
+            email_sender: Arc::new(LogEmailSender::default()),
+            sms_sender: Arc::new(LogSmsSender),
+            webhook_dispatcher: Arc::new(NoopWebhookDispatcher),
+            metrics: Arc::new(Metrics::new()),
+        }
+    }
+}
+
+/// Runs `test` on an actix system. `sqlx::test` only starts the runtime sqlx
+/// needs, and handlers that spawn background work (audit logs, webhooks) need this.
+#[cfg(test)]
+pub fn run_on_actix<F: std::future::Future>(test: F) -> F::Output {
+    actix_web::rt::System::new().block_on(test)
 }
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
-        authHandler::login,authHandler::logout,authHandler::register, users::get_me, users::get_users, heath_checker_handler
+        authHandler::login,authHandler::logout,authHandler::introspect,authHandler::me_from_token,authHandler::token_status,authHandler::register,authHandler::email_available,authHandler::refresh,authHandler::verify_email,authHandler::forgot_password,authHandler::reset_password,two_factor::setup,two_factor::verify,two_factor::login,two_factor::recovery_codes_status,two_factor::regenerate_recovery_codes, users::get_me, users::update_me, users::get_my_permissions, users::update_user_password, users::verify_password, users::get_login_history, users::get_sessions, users::revoke_session, users::create_api_key, users::get_api_keys, users::revoke_api_key, users::update_email, users::update_phone, users::verify_phone, users::update_photo, users::get_users, users::get_directory, users::export_users_csv, users::get_users_batch, users::get_user, users::delete_me, users::request_deletion, users::cancel_deletion, users::export_me, users::delete_user, users::update_user_role, users::update_user_status, users::set_user_password, users::restore_user, admin::get_audit_log, admin::get_role_stats, admin::get_users_by_role, admin::create_invitation, admin::get_registration_setting, admin::update_registration_setting, admin::get_user_login_history, admin::update_user_roles, admin::impersonate_user, heath_checker_handler, readiness_handler
     ),
     components(
-        schemas(UserData,FilterUserDto,LoginUserDto,RegisterUserDto,UserResponseDto,UserLoginResponseDto,Response,UserListResponseDto)
+        schemas(UserData,FilterUserDto,LoginUserDto,RegisterUserDto,CancelDeletionDto,DeletionRequestResponseDto,UserDataExportDto,ForgotPasswordDto,ResetPasswordDto,TotpCodeDto,TotpSetupResponseDto,TotpRecoveryCodesResponseDto,RecoveryCodesRegenerateDto,RecoveryCodesStatusResponseDto,TwoFactorChallengeResponseDto,TwoFactorLoginDto,RoleUpdateDto,RoleAssignmentDto,BulkRoleUpdateDto,BulkRoleUpdateResponseDto,StatusUpdateDto,UserPasswordUpdateDto,VerifyPasswordDto,VerifyPasswordResponseDto,AdminPasswordUpdateDto,EmailUpdateDto,ProfileUpdateDto,PhoneUpdateDto,PhoneVerifyDto,SessionDto,SessionListResponseDto,CreateApiKeyDto,ApiKeyDto,ApiKeyCreatedResponseDto,ApiKeyListResponseDto,TokenClaims,TokenClaimsResponseDto,TokenStatusResponseDto,PhotoUploadDto,UserRole,Permission,PermissionsResponseDto,UserStatus,UserSortField,SortOrder,UserResponseDto,UserLoginResponseDto,Response,UserListResponseDto,UserBatchResponseDto,EmailAvailabilityResponseDto,IntrospectRequestDto,IntrospectResponseDto,AuditLogDto,AuditLogListResponseDto,LoginAttemptDto,LoginHistoryResponseDto,AuditAction,DirectoryUserDto,DirectoryResponseDto,RoleCountDto,RoleStatsResponseDto,UsersByRoleResponseDto,RegistrationSettingDto,RegistrationSettingResponseDto,CreateInvitationDto,InvitationDto,InvitationCreatedResponseDto)
     ),
     tags(
         (name = "Rust Authentication Api", description = "Authentication in Rust API")
-    )
+    ),
+    modifiers(&SecurityAddon),
 )]
 struct ApiDoc;
 
@@ -53,7 +123,8 @@ struct SecurityAddon;
 
 impl Modify for SecurityAddon {
     fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
-        let components = openapi.components.as_mut().unwrap();
+        // Components are only generated when a schema is registered, so create them if missing.
+        let components = openapi.components.get_or_insert_with(Default::default);
         components.add_security_scheme(
             "token", 
             SecurityScheme::Http(
@@ -62,6 +133,10 @@ impl Modify for SecurityAddon {
                 .bearer_format("JWT")
                 .build(),
             )
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(auth::API_KEY_HEADER))),
         )
     }
 }
@@ -70,63 +145,295 @@ impl Modify for SecurityAddon {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     openssl_probe::init_ssl_cert_env_vars();
     if std::env::var_os("RUST_LOG").is_none() {
-        std::env::set_var("RUST_LOG", "actix_web=info");
+        std::env::set_var("RUST_LOG", "actix_web=info,rust_auth=info");
     }
 
     dotenv().ok();
-    env_logger::init();
 
-    let config = Config::init();
+    let config = Config::init().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    logging::init(config.log_format);
+    let tracer_provider = if config.features.tracing {
+        let provider = telemetry::init(&config.tracing).unwrap_or_else(|e| {
+            eprintln!("Failed to set up tracing: {}", e);
+            std::process::exit(1);
+        });
+        Some(provider)
+    } else {
+        None
+    };
+    error::set_error_format(config.error_format);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let create_admin_args = cli::parse_create_admin(&args)?;
 
-    let pool = PgPoolOptions::new()
-        .max_connections(10)
-        .connect(&config.database_url)
-        .await?;
+    let pool_options = PgPoolOptions::new()
+        .max_connections(config.db_max_connections)
+        .min_connections(config.db_min_connections)
+        .acquire_timeout(std::time::Duration::from_secs(config.db_acquire_timeout))
+        .idle_timeout(std::time::Duration::from_secs(config.db_idle_timeout));
+    let pool = pool_options.clone().connect(&config.database_url).await?;
+    let replica_pool = match &config.replica_database_url {
+        Some(url) => Some(pool_options.connect(url).await?),
+        None => None,
+    };
 
-    match sqlx::migrate!("./migrations").run(&pool).await {
-        Ok(_) => println!("Migrations executed successfully."),
-        Err(e) => eprintln!("Error running migrations: {}", e),
+    if config.run_migrations {
+        // A schema the code doesn't expect fails in confusing ways later, so refuse to start.
+        if let Err(e) = sqlx::migrate!("./migrations").run(&pool).await {
+            eprintln!("Error running migrations: {}", e);
+            std::process::exit(1);
+        }
+        println!("Migrations executed successfully.");
     }
 
-    let db_client = DBClient::new(pool);
+    let mut db_client = DBClient::new(pool.clone());
+    if let Some(replica_pool) = &replica_pool {
+        db_client = db_client.with_replica(replica_pool.clone());
+    }
+    if config.db_query_timeout > 0 {
+        db_client = db_client.with_query_timeout(std::time::Duration::from_secs(config.db_query_timeout));
+    }
+    // Builds the throwaway hash now so the first login for an unknown email isn't slower.
+    utils::password::dummy_compare("warm-up", &config.argon2_params);
+
+    if let Some(create_admin_args) = create_admin_args {
+        let user_id = cli::create_admin(&db_client, &config, create_admin_args).await?;
+        println!("Admin user created with id {}", user_id);
+        pool.close().await;
+        if let Some(replica_pool) = &replica_pool {
+            replica_pool.close().await;
+        }
+        return Ok(());
+    }
+
+    let cleanup_client = db_client.clone();
+    let photo_upload_dir = config.photo_upload_dir.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(std::time::Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = cleanup_client.delete_expired_revoked_tokens().await {
+                log::error!("Error purging expired revoked tokens: {}", e);
+            }
+            if let Err(e) = cleanup_client.delete_expired_idempotency_keys().await {
+                log::error!("Error purging expired idempotency keys: {}", e);
+            }
+
+            // Accounts whose deletion grace period is over are removed for good, photos included.
+            match cleanup_client.purge_deleted_users().await {
+                Ok(photos) => {
+                    for photo in photos {
+                        if let Some(file_name) = photo.strip_prefix("/uploads/") {
+                            let _ = std::fs::remove_file(std::path::Path::new(&photo_upload_dir).join(file_name));
+                        }
+                    }
+                }
+                Err(e) => log::error!("Error purging deleted users: {}", e),
+            }
+        }
+    });
+    std::fs::create_dir_all(&config.photo_upload_dir)?;
+
+    let email_sender: Arc<dyn EmailSender> = match &config.smtp {
+        Some(smtp) => Arc::new(
+            SmtpEmailSender::new(smtp).unwrap_or_else(|e| {
+                eprintln!("Invalid SMTP configuration: {}", e);
+                std::process::exit(1);
+            }),
+        ),
+        None => {
+            log::warn!("SMTP_HOST is not set; emails are logged instead of sent");
+            Arc::new(LogEmailSender::new(config.log_email_bodies))
+        }
+    };
+
+    let sms_sender: Arc<dyn SmsSender> = match &config.twilio {
+        Some(twilio) => Arc::new(
+            TwilioSmsSender::new(twilio).unwrap_or_else(|e| {
+                eprintln!("Invalid Twilio configuration: {}", e);
+                std::process::exit(1);
+            }),
+        ),
+        None => Arc::new(LogSmsSender),
+    };
+
+    let webhook_dispatcher: Arc<dyn WebhookDispatcher> = match &config.webhook {
+        Some(webhook) => Arc::new(
+            HttpWebhookDispatcher::new(webhook).unwrap_or_else(|e| {
+                eprintln!("Invalid webhook configuration: {}", e);
+                std::process::exit(1);
+            }),
+        ),
+        None => Arc::new(NoopWebhookDispatcher),
+    };
+
+    let tls_config = config.tls.as_ref().map(|tls| {
+        tls::load_server_config(tls).unwrap_or_else(|e| {
+            eprintln!("Invalid TLS configuration: {}", e);
+            std::process::exit(1);
+        })
+    });
+
+    let session_store: Arc<dyn SessionStore> = match &config.redis_url {
+        Some(redis_url) => Arc::new(
+            RedisSessionStore::connect(redis_url).await.unwrap_or_else(|e| {
+                eprintln!("Failed to connect to Redis: {}", e);
+                std::process::exit(1);
+            }),
+        ),
+        None => Arc::new(InMemorySessionStore::new()),
+    };
+
     let app_state = AppState { 
         env: config.clone(), 
-        db_client 
+        db_client,
+        session_store,
+        breach_checker: Arc::new(HibpBreachChecker::new()),
+        email_sender,
+        sms_sender,
+        webhook_dispatcher,
+        metrics: Arc::new(Metrics::new()),
+        token_service: Arc::new(JwtTokenService::new(&config)),
     };
+    let metrics_state = app_state.clone();
 
-    println!(
-        "{}",
-        format!("Server is running on http://localhost:{}", config.port)
-    );
+    let host = config.host.clone();
+    let metrics_port = config.metrics_port;
+    let compression_enabled = config.features.compression;
+    let slow_request_threshold_ms = config.slow_request_threshold_ms;
+    let security_headers_enabled = config.features.security_headers;
+    let tracing_enabled = config.features.tracing;
+    let security_headers = config.security_headers.clone();
+    let tls_enabled = tls_config.is_some();
+    let max_body_size = config.max_body_size;
 
     let openapi = ApiDoc::openapi();
 
-    HttpServer::new(move || {
-        let cors = Cors::default()
-                    .allowed_origin("http://localhost:3000")
-                    .allowed_origin("http://localhost:8000")
-                    .allowed_methods(vec!["GET", "POST"])
-                    .allowed_headers(vec![
-                        header::CONTENT_TYPE,
-                        header::AUTHORIZATION,
-                        header::ACCEPT,
-                    ])
-                    .supports_credentials();
+    let server = HttpServer::new(move || {
+        let cors = if config.cors_permissive {
+            // Development only: reflects any origin, method and header.
+            Cors::permissive()
+        } else {
+            config
+                .cors_allowed_origins
+                .iter()
+                .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+                .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
+                .allowed_headers(vec![
+                    header::CONTENT_TYPE,
+                    header::AUTHORIZATION,
+                    header::ACCEPT,
+                    header::HeaderName::from_static(request_id::REQUEST_ID_HEADER),
+                    header::HeaderName::from_static("x-api-key"),
+                    header::HeaderName::from_static(authHandler::IDEMPOTENCY_KEY_HEADER),
+                ])
+                .expose_headers(vec![
+                    header::HeaderName::from_static(request_id::REQUEST_ID_HEADER),
+                    header::LINK,
+                ])
+                .supports_credentials()
+        };
 
         App::new()
             .app_data(web::Data::new(app_state.clone()))
+            .app_data(
+                web::JsonConfig::default()
+                    .limit(max_body_size)
+                    .error_handler(error::json_error_handler),
+            )
+            .app_data(
+                web::FormConfig::default()
+                    .limit(max_body_size)
+                    .error_handler(error::form_error_handler),
+            )
+            .app_data(web::PayloadConfig::new(max_body_size))
             .wrap(cors)
-            .wrap(Logger::default())
+            .wrap(MetricsMiddleware)
+            // Negotiates gzip, brotli or zstd from Accept-Encoding; off when a proxy already compresses.
+            .wrap(Condition::new(compression_enabled, Compress::default()))
+            .wrap(Condition::new(
+                security_headers_enabled,
+                SecurityHeadersMiddleware::new(&security_headers, tls_enabled),
+            ))
+            // Inside RequestIdMiddleware so the warning carries the request id.
+            .wrap(Condition::new(
+                slow_request_threshold_ms > 0,
+                SlowRequestMiddleware::new(std::time::Duration::from_millis(slow_request_threshold_ms)),
+            ))
+            // Also inside RequestIdMiddleware, so spans carry the request id.
+            .wrap(Condition::new(tracing_enabled, TracingMiddleware))
+            .wrap(RequestIdMiddleware)
+            .wrap(Logger::new(r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T request_id=%{x-request-id}o"#))
+            // Registered before the /api/auth scope, which would otherwise claim these paths.
+            .service(handler::two_factor::two_factor_handler())
             .service(handler::auth::auth_handler())
             .service(handler::users::users_handler())
+            .service(handler::admin::admin_handler())
             .service(heath_checker_handler)
+            .service(readiness_handler)
+            .configure(|cfg| {
+                // With a dedicated metrics port, /metrics is only served there.
+                if metrics_port.is_none() {
+                    cfg.route("/metrics", web::get().to(metrics::metrics_handler));
+                }
+            })
+            .service(Files::new("/uploads", &app_state.env.photo_upload_dir))
             .service(Redoc::with_url("/redoc", openapi.clone()))
             .service(RapiDoc::new("/api-docs/openapi.json").path("/rapidoc"))
             .service(SwaggerUi::new("/{_:.*}").url("/api-docs/openapi.json", openapi.clone()))
-    })
-    .bind(("0.0.0.0", config.port))?
-    .run()
-    .await?;
+    });
+
+    let scheme = if tls_config.is_some() { "https" } else { "http" };
+    let server = match tls_config {
+        Some(tls_config) => server.bind_rustls_021((host.as_str(), config.port), tls_config)?,
+        None => server.bind((host.as_str(), config.port))?,
+    };
+
+    println!("rust_auth {} features: {}", env!("CARGO_PKG_VERSION"), config.features);
+    for addr in server.addrs() {
+        println!("Server is running on {}://{}", scheme, addr);
+    }
+
+    let server = server
+        // On SIGTERM/SIGINT, stop accepting connections and give in-flight requests this long to finish.
+        .shutdown_timeout(config.shutdown_timeout)
+        .run();
+
+    match metrics_port {
+        Some(port) => {
+            let metrics_server = HttpServer::new(move || {
+                App::new()
+                    .app_data(web::Data::new(metrics_state.clone()))
+                    .route("/metrics", web::get().to(metrics::metrics_handler))
+            })
+            .bind((host.as_str(), port))?;
+
+            for addr in metrics_server.addrs() {
+                println!("Metrics are served on http://{}/metrics", addr);
+            }
+
+            let metrics_server = metrics_server
+                .workers(1)
+                .shutdown_timeout(config.shutdown_timeout)
+                .run();
+
+            futures_util::future::try_join(server, metrics_server).await?;
+        }
+        None => server.await?,
+    }
+
+    println!("Server stopped, closing database connections");
+    pool.close().await;
+    if let Some(replica_pool) = replica_pool {
+        replica_pool.close().await;
+    }
+    if let Some(tracer_provider) = tracer_provider {
+        if let Err(e) = tracer_provider.shutdown() {
+            log::error!("Failed to flush traces: {}", e);
+        }
+    }
 
     Ok(())
 }
@@ -144,4 +451,24 @@ async fn heath_checker_handler() -> impl Responder {
     const MESSAGE: &str = "Complete Rust API";
 
     HttpResponse::Ok().json(serde_json::json!({"status":"success", "message": MESSAGE}))
-}
\ No newline at end of file
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/healthchecker/ready",
+    tag = "Health Checker Endpoint",
+    responses(
+        (status = 200, description= "Service and database are reachable", body = Response),
+        (status = 503, description= "Database is unreachable", body = Response),
+    )
+)]
+#[get("/api/healthchecker/ready")]
+async fn readiness_handler(app_state: web::Data<AppState>) -> Result<HttpResponse, HttpError> {
+    // Bounded so a hung database fails the probe instead of stalling it.
+    actix_web::rt::time::timeout(std::time::Duration::from_secs(3), app_state.db_client.ping())
+        .await
+        .map_err(|_| HttpError::service_unavailable("Database unavailable: timed out"))?
+        .map_err(|e| HttpError::service_unavailable(format!("Database unavailable: {}", e)))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"status":"success", "message": "Ready"})))
+}
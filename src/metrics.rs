@@ -0,0 +1,167 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, HttpResponse};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use futures_util::FutureExt;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry, TextEncoder};
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use crate::AppState;
+
+pub struct Metrics {
+    registry: Registry,
+    pub logins: IntCounterVec,
+    pub registrations: IntCounter,
+    pub tokens_issued: IntCounterVec,
+    pub request_duration: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let logins = IntCounterVec::new(
+            Opts::new("auth_logins_total", "Login attempts by outcome"),
+            &["result"],
+        )
+        .unwrap();
+        let registrations =
+            IntCounter::new("auth_registrations_total", "Accounts registered").unwrap();
+        let tokens_issued = IntCounterVec::new(
+            Opts::new("auth_tokens_issued_total", "Tokens issued by kind"),
+            &["kind"],
+        )
+        .unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(logins.clone())).unwrap();
+        registry.register(Box::new(registrations.clone())).unwrap();
+        registry.register(Box::new(tokens_issued.clone())).unwrap();
+        registry.register(Box::new(request_duration.clone())).unwrap();
+
+        Metrics {
+            registry,
+            logins,
+            registrations,
+            tokens_issued,
+            request_duration,
+        }
+    }
+
+    pub fn login_succeeded(&self) {
+        self.logins.with_label_values(&["success"]).inc();
+    }
+
+    pub fn login_failed(&self) {
+        self.logins.with_label_values(&["failure"]).inc();
+    }
+
+    pub fn token_issued(&self, kind: &str) {
+        self.tokens_issued.with_label_values(&[kind]).inc();
+    }
+
+    /// Renders every registered metric in the Prometheus text format.
+    pub fn render(&self) -> Result<String, String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|e| e.to_string())?;
+
+        String::from_utf8(buffer).map_err(|e| e.to_string())
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+pub async fn metrics_handler(app_state: web::Data<AppState>) -> HttpResponse {
+    match app_state.metrics.render() {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(TextEncoder::new().format_type())
+            .body(body),
+        Err(e) => HttpResponse::InternalServerError().body(e),
+    }
+}
+
+/// Records the latency of every request, labelled by method, route pattern and status.
+pub struct MetricsMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for MetricsMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = MetricsService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(MetricsService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct MetricsService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for MetricsService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, actix_web::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let app_state = req.app_data::<web::Data<AppState>>().unwrap().clone();
+        let method = req.method().to_string();
+        // Unmatched paths share one label so arbitrary URLs can't blow up the series count.
+        let route = req
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".to_string());
+        let started = Instant::now();
+
+        let srv = Rc::clone(&self.service);
+
+        async move {
+            let result = srv.call(req).await;
+
+            let status = match &result {
+                Ok(res) => res.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+            app_state
+                .metrics
+                .request_duration
+                .with_label_values(&[&method, &route, status.as_str()])
+                .observe(started.elapsed().as_secs_f64());
+
+            result
+        }
+        .boxed_local()
+    }
+}
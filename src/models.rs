@@ -1,8 +1,92 @@
 use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::validate_email;
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq)]
+use crate::error::ErrorMessage;
+
+/// A well-formed email address, lowercased since addresses are matched case-insensitively.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Email(String);
+
+impl Email {
+    pub fn parse(value: impl AsRef<str>) -> Result<Self, ErrorMessage> {
+        let value = value.as_ref().trim().to_lowercase();
+
+        if validate_email(&value) {
+            Ok(Email(value))
+        } else {
+            Err(ErrorMessage::InvalidEmail)
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for Email {
+    type Error = ErrorMessage;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Email::parse(value)
+    }
+}
+
+impl From<Email> for String {
+    fn from(value: Email) -> Self {
+        value.0
+    }
+}
+
+impl std::fmt::Display for Email {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::ops::Deref for Email {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// A phone number in E.164 form: `+`, then up to 15 digits without a leading zero.
+/// Spaces, dashes, dots and parentheses are dropped so `+1 (415) 555-0123` is accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PhoneNumber(String);
+
+impl PhoneNumber {
+    pub fn parse(value: impl AsRef<str>) -> Result<Self, ErrorMessage> {
+        let value: String = value
+            .as_ref()
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '.' | '(' | ')'))
+            .collect();
+
+        let digits = value.strip_prefix('+').unwrap_or_default();
+        let valid = (8..=15).contains(&digits.len())
+            && !digits.starts_with('0')
+            && digits.bytes().all(|b| b.is_ascii_digit());
+
+        if valid {
+            Ok(PhoneNumber(value))
+        } else {
+            Err(ErrorMessage::InvalidPhoneNumber)
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq, Eq, ToSchema)]
 #[sqlx(type_name = "user_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
 pub enum UserRole {
     Admin,
     Moderator,
@@ -10,26 +94,442 @@ pub enum UserRole {
 }
 
 impl UserRole {
-    pub fn to_str(&self) -> &str {
+    pub const ALL: [UserRole; 3] = [UserRole::User, UserRole::Moderator, UserRole::Admin];
+
+    pub fn to_str(self) -> &'static str {
         match self {
             UserRole::Admin => "admin",
             UserRole::Moderator => "moderator",
             UserRole::User => "user"
         }
     }
+
+    fn rank(self) -> u8 {
+        match self {
+            UserRole::Admin => 2,
+            UserRole::Moderator => 1,
+            UserRole::User => 0,
+        }
+    }
+
+    // Admins may assign any role; everyone else only roles at or below their own.
+    pub fn can_assign(self, role: UserRole) -> bool {
+        self == UserRole::Admin || role <= self
+    }
+
+    /// Everything the role is allowed to do. Route guards and
+    /// `GET /api/users/me/permissions` both read from this mapping.
+    pub fn permissions(self) -> &'static [Permission] {
+        const USER: &[Permission] = &[
+            Permission::ProfileRead,
+            Permission::ProfileUpdate,
+            Permission::SessionsManage,
+        ];
+        const MODERATOR: &[Permission] = &[
+            Permission::ProfileRead,
+            Permission::ProfileUpdate,
+            Permission::SessionsManage,
+            Permission::UsersDirectory,
+        ];
+
+        match self {
+            UserRole::Admin => &Permission::ALL,
+            UserRole::Moderator => MODERATOR,
+            UserRole::User => USER,
+        }
+    }
+
+    pub fn has_permission(self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+}
+
+impl std::str::FromStr for UserRole {
+    type Err = ErrorMessage;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        UserRole::ALL
+            .into_iter()
+            .find(|role| role.to_str() == s)
+            .ok_or(ErrorMessage::InvalidRole)
+    }
+}
+
+/// A capability granted by a role, serialized as `resource:action`.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+pub enum Permission {
+    #[serde(rename = "profile:read")]
+    ProfileRead,
+    #[serde(rename = "profile:update")]
+    ProfileUpdate,
+    #[serde(rename = "sessions:manage")]
+    SessionsManage,
+    #[serde(rename = "users:directory")]
+    UsersDirectory,
+    #[serde(rename = "users:list")]
+    UsersList,
+    #[serde(rename = "users:export")]
+    UsersExport,
+    #[serde(rename = "users:read")]
+    UsersRead,
+    #[serde(rename = "users:delete")]
+    UsersDelete,
+    #[serde(rename = "users:restore")]
+    UsersRestore,
+    #[serde(rename = "users:update_role")]
+    UsersUpdateRole,
+    #[serde(rename = "users:update_status")]
+    UsersUpdateStatus,
+    #[serde(rename = "users:set_password")]
+    UsersSetPassword,
+    #[serde(rename = "users:impersonate")]
+    UsersImpersonate,
+    #[serde(rename = "audit_log:read")]
+    AuditLogRead,
+    #[serde(rename = "stats:read")]
+    StatsRead,
+    #[serde(rename = "settings:manage")]
+    SettingsManage,
+    #[serde(rename = "users:invite")]
+    UsersInvite,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 17] = [
+        Permission::ProfileRead,
+        Permission::ProfileUpdate,
+        Permission::SessionsManage,
+        Permission::UsersDirectory,
+        Permission::UsersList,
+        Permission::UsersExport,
+        Permission::UsersRead,
+        Permission::UsersDelete,
+        Permission::UsersRestore,
+        Permission::UsersUpdateRole,
+        Permission::UsersUpdateStatus,
+        Permission::UsersSetPassword,
+        Permission::UsersImpersonate,
+        Permission::AuditLogRead,
+        Permission::StatsRead,
+        Permission::SettingsManage,
+        Permission::UsersInvite,
+    ];
+
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Permission::ProfileRead => "profile:read",
+            Permission::ProfileUpdate => "profile:update",
+            Permission::SessionsManage => "sessions:manage",
+            Permission::UsersDirectory => "users:directory",
+            Permission::UsersList => "users:list",
+            Permission::UsersExport => "users:export",
+            Permission::UsersRead => "users:read",
+            Permission::UsersDelete => "users:delete",
+            Permission::UsersRestore => "users:restore",
+            Permission::UsersUpdateRole => "users:update_role",
+            Permission::UsersUpdateStatus => "users:update_status",
+            Permission::UsersSetPassword => "users:set_password",
+            Permission::UsersImpersonate => "users:impersonate",
+            Permission::AuditLogRead => "audit_log:read",
+            Permission::StatsRead => "stats:read",
+            Permission::SettingsManage => "settings:manage",
+            Permission::UsersInvite => "users:invite",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Permission> {
+        Permission::ALL.into_iter().find(|p| p.to_str() == value)
+    }
+}
+
+// Roles are ordered by privilege: User < Moderator < Admin.
+impl PartialOrd for UserRole {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, sqlx::Type, Clone)]
+impl Ord for UserRole {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.rank().cmp(&other.rank())
+    }
+}
+
+/// Account state. `Pending` accounts have not verified their email yet.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq, Eq, ToSchema)]
+#[sqlx(type_name = "user_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum UserStatus {
+    Active,
+    Pending,
+    Suspended,
+    Banned,
+}
+
+impl UserStatus {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            UserStatus::Active => "active",
+            UserStatus::Pending => "pending",
+            UserStatus::Suspended => "suspended",
+            UserStatus::Banned => "banned",
+        }
+    }
+
+    /// The error to turn the user away with, if the account is locked.
+    pub fn lock_error(self) -> Option<ErrorMessage> {
+        match self {
+            UserStatus::Suspended => Some(ErrorMessage::AccountSuspended),
+            UserStatus::Banned => Some(ErrorMessage::AccountBanned),
+            UserStatus::Active | UserStatus::Pending => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UserSortField {
+    CreatedAt,
+    Name,
+    Email,
+}
+
+impl UserSortField {
+    pub fn to_column(self) -> &'static str {
+        match self {
+            UserSortField::CreatedAt => "created_at",
+            UserSortField::Name => "name",
+            UserSortField::Email => "email",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+/// Photo of accounts that haven't uploaded one.
+pub const DEFAULT_PHOTO: &str = "default.png";
+
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
 pub struct User {
     pub id: uuid::Uuid,
     pub name: String,
     pub email: String,
     pub password: String,
     pub role: UserRole,
+    pub status: UserStatus,
     pub photo: String,
     pub verified: bool,
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
     #[serde(rename = "createdAt")]
     pub created_at: Option<DateTime<Utc>>,
     #[serde(rename = "updatedAt")]
     pub updated_at: Option<DateTime<Utc>>,
-}
\ No newline at end of file
+    #[serde(rename = "deletedAt")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    #[serde(rename = "lastLoginAt")]
+    pub last_login_at: Option<DateTime<Utc>>,
+    #[serde(rename = "lastLoginIp")]
+    pub last_login_ip: Option<String>,
+    pub phone: Option<String>,
+    #[serde(rename = "phoneVerified")]
+    pub phone_verified: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
+pub struct RefreshToken {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub family_id: uuid::Uuid,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub last_used_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    // None for a regular login, Some(true) for "remember me", Some(false) for a browser-session login.
+    pub remember_me: Option<bool>,
+}
+
+// A login session is a refresh token family; `id` is the family id, which stays
+// stable across rotations.
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
+pub struct Session {
+    pub id: uuid::Uuid,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+}
+
+/// A registration that can be replayed by resending its `Idempotency-Key`.
+/// A single-use invite to register with `email`. Only the SHA-256 of the token
+/// is stored; `used_at` is set once an account has been created with it.
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
+pub struct Invitation {
+    pub id: uuid::Uuid,
+    pub token_hash: String,
+    pub email: String,
+    pub invited_by: Option<uuid::Uuid>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Settings admins change at runtime, kept in a single `app_settings` row.
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
+pub struct AppSettings {
+    pub registration_enabled: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// `request_hash` covers the name and email only; replays must also present a
+/// password that matches the account.
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
+pub struct IdempotencyKey {
+    pub idempotency_key: String,
+    pub request_hash: String,
+    pub user_id: uuid::Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+// Only the SHA-256 of the key is stored; `key_prefix` is kept in clear so users can
+// tell their keys apart. `scopes` holds `Permission` strings.
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
+pub struct ApiKey {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub name: String,
+    pub key_prefix: String,
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ApiKey {
+    /// The scopes that name a known permission; unknown strings are ignored.
+    pub fn permissions(&self) -> Vec<Permission> {
+        self.scopes.iter().filter_map(|s| Permission::parse(s)).collect()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq, ToSchema)]
+#[sqlx(type_name = "audit_action", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Login,
+    LoginFailed,
+    Logout,
+    PasswordChange,
+    PasswordReset,
+    RoleChange,
+    Impersonate,
+    StatusChange,
+    DeletionRequest,
+    DeletionCancel,
+}
+
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
+pub struct AuditLog {
+    pub id: uuid::Uuid,
+    pub user_id: Option<uuid::Uuid>,
+    pub action: AuditAction,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub details: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
+pub struct VerificationToken {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The code most recently texted to a user, for the number it was sent to.
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
+pub struct PhoneVerificationCode {
+    pub user_id: uuid::Uuid,
+    pub phone: String,
+    pub code_hash: String,
+    pub attempts: i32,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
+pub struct PasswordResetToken {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn users_cannot_assign_admin() {
+        assert!(!UserRole::User.can_assign(UserRole::Admin));
+    }
+
+    #[test]
+    fn moderators_cannot_assign_admin() {
+        assert!(!UserRole::Moderator.can_assign(UserRole::Admin));
+    }
+
+    #[test]
+    fn moderators_can_assign_user() {
+        assert!(UserRole::Moderator.can_assign(UserRole::User));
+    }
+
+    #[test]
+    fn admins_can_assign_admin() {
+        assert!(UserRole::Admin.can_assign(UserRole::Admin));
+    }
+
+    #[test]
+    fn roles_are_ordered_by_privilege() {
+        assert!(UserRole::User < UserRole::Moderator);
+        assert!(UserRole::Moderator < UserRole::Admin);
+    }
+
+    #[test]
+    fn moderator_meets_user_minimum_but_not_admin() {
+        assert!(UserRole::Moderator >= UserRole::User);
+        assert!(UserRole::Moderator < UserRole::Admin);
+    }
+
+    #[test]
+    fn email_parse_trims_and_lowercases() {
+        assert_eq!(
+            Email::parse(" John@Example.COM "),
+            Email::parse("john@example.com")
+        );
+        assert_eq!(Email::parse(" John@Example.COM ").unwrap().as_str(), "john@example.com");
+    }
+}
@@ -0,0 +1,145 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{http, web, HttpMessage, HttpResponse};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use futures_util::FutureExt;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::error::{ErrorMessage, ErrorResponse};
+use crate::models::User;
+use crate::{request_id, utils, AppState};
+
+pub struct RateLimit {
+    max_requests: u32,
+    window: Duration,
+    per_user: bool,
+}
+
+impl RateLimit {
+    pub fn new(max_requests: u32, window: Duration) -> Self {
+        RateLimit {
+            max_requests,
+            window,
+            per_user: false,
+        }
+    }
+
+    /// Counts requests per authenticated user instead of per client IP, so the
+    /// limit holds when the caller switches addresses. Needs `RequireAuth` to be
+    /// wrapped outside this middleware; unauthenticated requests fall back to the IP.
+    pub fn per_user(mut self) -> Self {
+        self.per_user = true;
+        self
+    }
+}
+
+impl<S> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<
+            ServiceRequest,
+            Response = ServiceResponse<actix_web::body::BoxBody>,
+            Error = actix_web::Error,
+        > + 'static,
+{
+    type Response = ServiceResponse<actix_web::body::BoxBody>;
+    type Error = actix_web::Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            max_requests: self.max_requests,
+            window: self.window,
+            per_user: self.per_user,
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    max_requests: u32,
+    window: Duration,
+    per_user: bool,
+}
+
+impl<S> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<
+            ServiceRequest,
+            Response = ServiceResponse<actix_web::body::BoxBody>,
+            Error = actix_web::Error,
+        > + 'static,
+{
+    type Response = ServiceResponse<actix_web::body::BoxBody>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, actix_web::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let app_state = req.app_data::<web::Data<AppState>>().unwrap().clone();
+        let srv = Rc::clone(&self.service);
+        let client_ip = utils::ip::client_ip(&req.connection_info(), app_state.env.trust_proxy);
+
+        // Allowlisted addresses skip the limit altogether, even on per-user routes.
+        if client_ip
+            .as_deref()
+            .is_some_and(|ip| utils::ip::is_in_networks(ip, &app_state.env.rate_limit_allowlist))
+        {
+            return srv.call(req).boxed_local();
+        }
+
+        let user_id = self
+            .per_user
+            .then(|| req.extensions().get::<User>().map(|user| user.id))
+            .flatten();
+        let client = match user_id {
+            Some(user_id) => format!("user:{}", user_id),
+            None => client_ip.unwrap_or_else(|| "unknown".to_string()),
+        };
+        let route = req.match_pattern().unwrap_or_else(|| req.path().to_string());
+        let key = format!("{}:{}", route, client);
+
+        let max_requests = self.max_requests;
+        let window = self.window;
+
+        async move {
+            let limited = match app_state
+                .session_store
+                .hit(&key, max_requests, window)
+                .await
+            {
+                Ok(limited) => limited,
+                Err(e) => {
+                    // Fail open so an unavailable store never locks everyone out.
+                    log::error!("Rate limit store error: {}", e);
+                    None
+                }
+            };
+
+            if let Some(retry_after) = limited {
+                let response = HttpResponse::TooManyRequests()
+                    .insert_header((
+                        http::header::RETRY_AFTER,
+                        retry_after.as_secs_f64().ceil().to_string(),
+                    ))
+                    .json(ErrorResponse {
+                        status: "fail".to_string(),
+                        message: ErrorMessage::TooManyRequests.to_string(),
+                        request_id: request_id::current(),
+                        errors: None,
+                    });
+
+                return Ok(req.into_response(response));
+            }
+
+            srv.call(req).await
+        }
+        .boxed_local()
+    }
+}
@@ -0,0 +1,134 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::InternalError;
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use futures_util::FutureExt;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use crate::config::SecurityHeadersConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameOptions {
+    Deny,
+    SameOrigin,
+}
+
+impl FrameOptions {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            FrameOptions::Deny => "DENY",
+            FrameOptions::SameOrigin => "SAMEORIGIN",
+        }
+    }
+}
+
+impl std::str::FromStr for FrameOptions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "deny" => Ok(FrameOptions::Deny),
+            "sameorigin" => Ok(FrameOptions::SameOrigin),
+            other => Err(format!("Unsupported frame options: {}", other)),
+        }
+    }
+}
+
+/// Adds hardening headers to every response, including errors raised by other
+/// middleware. Headers a handler already set are left alone.
+pub struct SecurityHeadersMiddleware {
+    headers: Rc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl SecurityHeadersMiddleware {
+    /// HSTS is only sent when `tls_enabled`, since browsers would otherwise
+    /// refuse to reach a plain-HTTP development server on the same host.
+    pub fn new(config: &SecurityHeadersConfig, tls_enabled: bool) -> Self {
+        let mut headers = vec![
+            (header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+            (header::X_FRAME_OPTIONS, HeaderValue::from_static(config.frame_options.to_str())),
+        ];
+
+        if let Some(policy) = &config.content_security_policy {
+            if let Ok(value) = HeaderValue::from_str(policy) {
+                headers.push((header::CONTENT_SECURITY_POLICY, value));
+            }
+        }
+
+        if tls_enabled && config.hsts_max_age > 0 {
+            let value = format!("max-age={}; includeSubDomains", config.hsts_max_age);
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.push((header::STRICT_TRANSPORT_SECURITY, value));
+            }
+        }
+
+        SecurityHeadersMiddleware {
+            headers: Rc::new(headers),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SecurityHeadersMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Transform = SecurityHeadersService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SecurityHeadersService {
+            service: Rc::new(service),
+            headers: Rc::clone(&self.headers),
+        }))
+    }
+}
+
+pub struct SecurityHeadersService<S> {
+    service: Rc<S>,
+    headers: Rc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl<S, B> Service<ServiceRequest> for SecurityHeadersService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, actix_web::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let srv = Rc::clone(&self.service);
+        let headers = Rc::clone(&self.headers);
+
+        async move {
+            let mut res = srv.call(req).await.map_err(|e| {
+                let mut response = e.error_response();
+                insert_headers(response.headers_mut(), &headers);
+                actix_web::Error::from(InternalError::from_response(e.to_string(), response))
+            })?;
+            insert_headers(res.headers_mut(), &headers);
+
+            Ok(res.map_into_boxed_body())
+        }
+        .boxed_local()
+    }
+}
+
+fn insert_headers(target: &mut HeaderMap, headers: &[(HeaderName, HeaderValue)]) {
+    for (name, value) in headers {
+        if !target.contains_key(name) {
+            target.insert(name.clone(), value.clone());
+        }
+    }
+}
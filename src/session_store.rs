@@ -0,0 +1,162 @@
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::Script;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+/// Short-lived state that has to be shared by every replica of the server.
+///
+/// Revoked tokens and refresh tokens are not kept here: they live in Postgres,
+/// which every replica already shares and which survives restarts.
+#[async_trait]
+pub trait SessionStore: Send + Sync + std::fmt::Debug {
+    /// Records a rate limit hit for `key` and returns how long the caller must
+    /// wait when the limit has been exceeded.
+    async fn hit(
+        &self,
+        key: &str,
+        max_requests: u32,
+        window: Duration,
+    ) -> Result<Option<Duration>, String>;
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Keeps state in process memory; only suitable for a single replica.
+#[derive(Debug, Default)]
+pub struct InMemorySessionStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl InMemorySessionStore {
+    pub fn new() -> Self {
+        InMemorySessionStore::default()
+    }
+}
+
+#[async_trait]
+impl SessionStore for InMemorySessionStore {
+    async fn hit(
+        &self,
+        key: &str,
+        max_requests: u32,
+        window: Duration,
+    ) -> Result<Option<Duration>, String> {
+        let capacity = max_requests as f64;
+        let refill_per_second = capacity / window.as_secs_f64();
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().map_err(|e| e.to_string())?;
+
+        if buckets.len() >= MAX_TRACKED_KEYS {
+            buckets.retain(|_, bucket| {
+                let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                bucket.tokens + elapsed * refill_per_second < capacity
+            });
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * refill_per_second).min(capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(None)
+        } else {
+            let wait = (1.0 - bucket.tokens) / refill_per_second;
+            Ok(Some(Duration::from_secs_f64(wait)))
+        }
+    }
+}
+
+// The same token bucket as the in-memory store, run inside Redis so concurrent
+// replicas can't race each other. Uses the Redis clock so replicas with skewed
+// clocks still agree. Returns the milliseconds to wait, or 0 when allowed.
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local window_ms = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(bucket[1]) or capacity
+local last_refill = tonumber(bucket[2]) or now
+local refill_per_ms = capacity / window_ms
+
+tokens = math.min(capacity, tokens + math.max(0, now - last_refill) * refill_per_ms)
+
+local wait = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    wait = math.ceil((1 - tokens) / refill_per_ms)
+end
+
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', now)
+redis.call('PEXPIRE', KEYS[1], window_ms)
+return wait
+"#;
+
+/// Keeps state in Redis so it is shared by every replica.
+#[derive(Clone)]
+pub struct RedisSessionStore {
+    connection: ConnectionManager,
+    token_bucket: Script,
+}
+
+impl RedisSessionStore {
+    /// Connects to `redis_url`, failing if the server can't be reached.
+    pub async fn connect(redis_url: &str) -> Result<Self, redis::RedisError> {
+        let client = redis::Client::open(redis_url)?;
+        let connection = ConnectionManager::new(client).await?;
+
+        Ok(RedisSessionStore {
+            connection,
+            token_bucket: Script::new(TOKEN_BUCKET_SCRIPT),
+        })
+    }
+}
+
+impl std::fmt::Debug for RedisSessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisSessionStore").finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn hit(
+        &self,
+        key: &str,
+        max_requests: u32,
+        window: Duration,
+    ) -> Result<Option<Duration>, String> {
+        let mut connection = self.connection.clone();
+        let wait_ms: u64 = self
+            .token_bucket
+            .key(format!("rate_limit:{}", key))
+            .arg(max_requests)
+            .arg(window.as_millis() as u64)
+            .invoke_async(&mut connection)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if wait_ms == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(Duration::from_millis(wait_ms)))
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use async_trait::async_trait;
+use std::time::Duration;
+
+use crate::config::TwilioConfig;
+
+const TWILIO_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[async_trait]
+pub trait SmsSender: Send + Sync + std::fmt::Debug {
+    /// Sends `body` to `to`, an E.164 number such as `+14155550123`.
+    async fn send(&self, to: &str, body: &str) -> Result<(), String>;
+}
+
+/// Prints messages to stdout instead of delivering them. Used when Twilio is
+/// not configured, e.g. during local development.
+#[derive(Debug, Default)]
+pub struct LogSmsSender;
+
+#[async_trait]
+impl SmsSender for LogSmsSender {
+    async fn send(&self, to: &str, body: &str) -> Result<(), String> {
+        println!("SMS to {}: {}", to, body);
+        Ok(())
+    }
+}
+
+/// Delivers messages through the Twilio Messages API.
+#[derive(Debug, Clone)]
+pub struct TwilioSmsSender {
+    client: reqwest::Client,
+    account_sid: String,
+    auth_token: String,
+    from: String,
+}
+
+impl TwilioSmsSender {
+    pub fn new(config: &TwilioConfig) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(TWILIO_TIMEOUT)
+            .user_agent("rust_auth")
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        Ok(TwilioSmsSender {
+            client,
+            account_sid: config.account_sid.clone(),
+            auth_token: config.auth_token.clone(),
+            from: config.from.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl SmsSender for TwilioSmsSender {
+    async fn send(&self, to: &str, body: &str) -> Result<(), String> {
+        let url = format!(
+            "https://api.twilio.com/2010-04-01/Accounts/{}/Messages.json",
+            self.account_sid
+        );
+
+        let response = self
+            .client
+            .post(url)
+            .basic_auth(&self.account_sid, Some(&self.auth_token))
+            .form(&[("To", to), ("From", self.from.as_str()), ("Body", body)])
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Twilio answered {}", response.status()))
+        }
+    }
+}
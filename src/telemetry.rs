@@ -0,0 +1,149 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::HeaderMap;
+use actix_web::HttpMessage;
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use futures_util::FutureExt;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use tracing::{field, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::config::TracingConfig;
+use crate::models::User;
+use crate::request_id;
+
+/// Exports this crate's `tracing` spans over OTLP/HTTP. Logging still goes
+/// through `log`, so nothing changes for the log output. The returned provider
+/// must be shut down on exit to flush the spans still buffered.
+pub fn init(config: &TracingConfig) -> Result<SdkTracerProvider, String> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(config.otlp_endpoint.clone())
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(config.service_name.clone()).build())
+        .build();
+
+    // Continues traces started by callers that send a W3C `traceparent` header.
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("rust_auth")))
+        .with(Targets::new().with_target("rust_auth", tracing::Level::INFO))
+        .try_init()
+        .map_err(|e| e.to_string())?;
+
+    Ok(provider)
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Opens a span for every request, named after the route pattern, that the
+/// handler and database spans nest under. Records the status and, once
+/// `RequireAuth` has run, the user id.
+pub struct TracingMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for TracingMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = TracingService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TracingService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct TracingService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for TracingService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, actix_web::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let method = req.method().to_string();
+        let route = req
+            .match_pattern()
+            .unwrap_or_else(|| req.path().to_string());
+
+        let span = tracing::info_span!(
+            "request",
+            otel.name = %format!("{} {}", method, route),
+            otel.kind = "server",
+            otel.status_code = field::Empty,
+            http.request.method = %method,
+            http.route = %route,
+            http.response.status_code = field::Empty,
+            request_id = request_id::current().as_deref(),
+            user_id = field::Empty,
+        );
+        let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(req.headers()))
+        });
+        let _ = span.set_parent(parent);
+
+        let srv = Rc::clone(&self.service);
+
+        async move {
+            let result = srv.call(req).instrument(span.clone()).await;
+
+            let status = match &result {
+                Ok(res) => {
+                    if let Some(user) = res.request().extensions().get::<User>() {
+                        span.record("user_id", field::display(user.id));
+                    }
+                    res.status()
+                }
+                Err(e) => e.as_response_error().status_code(),
+            };
+            span.record("http.response.status_code", status.as_u16());
+            if status.is_server_error() {
+                span.record("otel.status_code", "ERROR");
+            }
+
+            result
+        }
+        .boxed_local()
+    }
+}
@@ -0,0 +1,41 @@
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use rustls_pemfile::Item;
+use std::fs::File;
+use std::io::BufReader;
+
+use crate::config::TlsConfig;
+
+/// Builds the rustls configuration from the PEM certificate chain and private key.
+pub fn load_server_config(config: &TlsConfig) -> Result<ServerConfig, String> {
+    let cert_chain: Vec<Certificate> = read_pem(&config.cert_path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(cert) => Some(Certificate(cert)),
+            _ => None,
+        })
+        .collect();
+    if cert_chain.is_empty() {
+        return Err(format!("no certificate found in {}", config.cert_path));
+    }
+
+    // PKCS#8, PKCS#1 (RSA) and SEC1 (EC) keys are all accepted; the first one wins.
+    let key = read_pem(&config.key_path)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("no private key found in {}", config.key_path))?;
+
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(cert_chain, key)
+        .map_err(|e| format!("certificate and key do not form a valid pair: {}", e))
+}
+
+fn read_pem(path: &str) -> Result<Vec<Item>, String> {
+    let file = File::open(path).map_err(|e| format!("failed to open {}: {}", path, e))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| format!("failed to parse {}: {}", path, e))
+}
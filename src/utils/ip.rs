@@ -0,0 +1,39 @@
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::dev::ConnectionInfo;
+use ipnet::IpNet;
+
+pub fn client_ip(conn: &ConnectionInfo, trust_proxy: bool) -> Option<String> {
+    let addr = if trust_proxy {
+        conn.realip_remote_addr()
+    } else {
+        conn.peer_addr()
+    };
+
+    addr.map(|addr| addr.to_string())
+}
+
+/// Parses a CIDR block such as `10.0.0.0/8`; a bare address matches just itself.
+pub fn parse_network(value: &str) -> Result<IpNet, String> {
+    if value.contains('/') {
+        value.parse::<IpNet>().map_err(|e| e.to_string())
+    } else {
+        value
+            .parse::<IpAddr>()
+            .map(IpNet::from)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Whether the address returned by `client_ip` falls in any of `networks`.
+pub fn is_in_networks(ip: &str, networks: &[IpNet]) -> bool {
+    // Forwarded headers may carry a port along with the address.
+    let addr = ip
+        .parse::<IpAddr>()
+        .or_else(|_| ip.parse::<SocketAddr>().map(|addr| addr.ip()));
+
+    match addr {
+        Ok(addr) => networks.iter().any(|network| network.contains(&addr)),
+        Err(_) => false,
+    }
+}
@@ -1,2 +1,5 @@
+pub mod ip;
+pub mod name;
 pub mod password;
-pub mod token;
\ No newline at end of file
+pub mod token;
+pub mod totp;
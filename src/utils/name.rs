@@ -0,0 +1,66 @@
+use std::borrow::Cow;
+
+use validator::ValidationError;
+
+/// Longest name the `users.name` column holds.
+pub const MAX_NAME_LENGTH: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct NamePolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+}
+
+/// Checks a display name that has already been trimmed, e.g. by `trim`.
+pub fn validate(name: &str, policy: &NamePolicy) -> Result<(), ValidationError> {
+    if name.is_empty() {
+        return Err(name_error("name_blank", "Name cannot be blank".to_string()));
+    }
+
+    // Covers newlines and tabs as well as other invisible characters.
+    if name.chars().any(char::is_control) {
+        return Err(name_error(
+            "name_control_characters",
+            "Name must not contain line breaks or control characters".to_string(),
+        ));
+    }
+
+    let mut previous_was_space = false;
+    for c in name.chars() {
+        let is_space = c.is_whitespace();
+        if is_space && previous_was_space {
+            return Err(name_error(
+                "name_repeated_whitespace",
+                "Name must not contain repeated spaces".to_string(),
+            ));
+        }
+        previous_was_space = is_space;
+    }
+
+    let length = name.chars().count();
+    if length < policy.min_length || length > policy.max_length {
+        let mut error = name_error(
+            "name_length",
+            format!(
+                "Name must be between {} and {} characters",
+                policy.min_length, policy.max_length
+            ),
+        );
+        error.add_param(Cow::from("min"), &policy.min_length);
+        error.add_param(Cow::from("max"), &policy.max_length);
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+/// Strips leading and trailing whitespace, so that alone never fails validation.
+pub fn trim(name: &str) -> String {
+    name.trim().to_string()
+}
+
+fn name_error(code: &'static str, message: String) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(Cow::from(message));
+    error
+}
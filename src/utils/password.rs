@@ -1,15 +1,135 @@
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
-    Argon2,
+    Algorithm, Argon2, Params, Version,
 };
 
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize, Serializer};
+use validator::{HasLen, ValidationError};
+use zeroize::Zeroize;
+
 use crate::error::ErrorMessage;
 
 const MAX_PASSWORD_LENGTH: usize = 64;
 
-pub fn hash(password: impl Into<String>) -> Result<String, ErrorMessage> {
-    let password = password.into();
+const COMMON_PASSWORDS: &[&str] = &[
+    "password", "password1", "password123", "p@ssw0rd", "passw0rd", "p@ssword",
+    "123456", "12345678", "123456789", "1234567890", "qwerty", "qwerty123",
+    "qwertyuiop", "abc123", "111111", "123123", "letmein", "welcome", "welcome1",
+    "admin", "admin123", "iloveyou", "monkey", "dragon", "sunshine", "football",
+    "baseball", "princess", "trustno1", "changeme", "login", "master", "secret",
+];
+
+/// A plaintext password from a request body, wiped from memory when dropped.
+///
+/// Deserializes from a plain JSON string and derefs to `str` for hashing and
+/// checks. It is redacted in `Debug` output and serializes as `"[redacted]"`, so
+/// it can't leak through logs, responses or validation error params.
+#[derive(Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct SecretPassword(String);
+
+impl std::ops::Deref for SecretPassword {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Drop for SecretPassword {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl std::fmt::Debug for SecretPassword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretPassword([redacted])")
+    }
+}
+
+impl Serialize for SecretPassword {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("[redacted]")
+    }
+}
+
+impl HasLen for &SecretPassword {
+    fn length(&self) -> u64 {
+        self.0.chars().count() as u64
+    }
+}
+
+/// `length(min = MIN)` for `SecretPassword`, which validator's `length` rejects
+/// at compile time as not string-like.
+pub fn validate_min_length<const MIN: u64>(password: &SecretPassword) -> Result<(), ValidationError> {
+    if validator::validate_length(password, Some(MIN), None, None) {
+        Ok(())
+    } else {
+        let mut error = ValidationError::new("length");
+        error.add_param(Cow::from("min"), &MIN);
+        Err(error)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_uppercase: bool,
+    pub require_lowercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+}
+
+pub fn validate_strength(password: &str, policy: &PasswordPolicy) -> Result<(), ValidationError> {
+    let mut missing = Vec::new();
 
+    if policy.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+        missing.push("an uppercase letter");
+    }
+    if policy.require_lowercase && !password.chars().any(|c| c.is_lowercase()) {
+        missing.push("a lowercase letter");
+    }
+    if policy.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+        missing.push("a digit");
+    }
+    if policy.require_symbol && !password.chars().any(|c| !c.is_alphanumeric()) {
+        missing.push("a symbol");
+    }
+
+    if password.chars().count() < policy.min_length {
+        let mut error = ValidationError::new("password_too_short");
+        error.message = Some(Cow::from(format!(
+            "Password must be at least {} characters",
+            policy.min_length
+        )));
+        error.add_param(Cow::from("min_length"), &policy.min_length);
+        return Err(error);
+    }
+
+    if !missing.is_empty() {
+        let mut error = ValidationError::new("password_too_weak");
+        error.message = Some(Cow::from(format!("Password must contain {}", missing.join(", "))));
+        error.add_param(Cow::from("missing"), &missing);
+        return Err(error);
+    }
+
+    // Also catch the usual "Password1!" style of padding a common word to satisfy the rules.
+    let lowercase = password.to_lowercase();
+    let stem = lowercase.trim_end_matches(|c: char| !c.is_alphabetic());
+    if COMMON_PASSWORDS.contains(&lowercase.as_str()) || COMMON_PASSWORDS.contains(&stem) {
+        let mut error = ValidationError::new("password_too_common");
+        error.message = Some(Cow::from("Password is too common"));
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+pub fn hash(password: &str, params: &Params) -> Result<String, ErrorMessage> {
     if password.is_empty() {
         return Err(ErrorMessage::EmptyPassword);
     }
@@ -19,7 +139,7 @@ pub fn hash(password: impl Into<String>) -> Result<String, ErrorMessage> {
     }
 
     let salt  = SaltString::generate(&mut OsRng);
-    let hashed_password = Argon2::default()
+    let hashed_password = Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone())
         .hash_password(password.as_bytes(), &salt)
         .map_err(|_| ErrorMessage::HashingError)?
         .to_string();
@@ -36,12 +156,42 @@ pub fn compare(password: &str, hashed_password: &str) -> Result<bool, ErrorMessa
         return Err(ErrorMessage::ExceededMaxPasswordLength(MAX_PASSWORD_LENGTH));
     }
 
+    // The cost parameters are read back from the PHC string, so hashes made
+    // under older settings keep verifying.
     let parsed_hash = 
         PasswordHash::new(hashed_password).map_err(|_| ErrorMessage::InvalidHashFormate)?;
 
     let password_matches = Argon2::default()
         .verify_password(password.as_bytes(), &parsed_hash)
-        .map_or(false, |_| true);
+        .is_ok();
 
     Ok(password_matches)
-}
\ No newline at end of file
+}
+
+/// Runs a comparison against a throwaway hash made with the configured `params`.
+///
+/// Call it when no account matches the submitted email: without it that path skips
+/// the Argon2 work and answers measurably faster, revealing which emails exist.
+pub fn dummy_compare(password: &str, params: &Params) {
+    let _ = compare(password, dummy_hash(params));
+}
+
+// Built on first use, so every dummy comparison costs the same as a real one.
+fn dummy_hash(params: &Params) -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    DUMMY_HASH.get_or_init(|| hash("dummy-password", params).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dummy_hash_is_a_real_argon2_hash() {
+        let params = Params::default();
+
+        assert!(dummy_hash(&params).starts_with("$argon2id$"));
+        assert_eq!(compare("Passw0rd!", dummy_hash(&params)), Ok(false));
+    }
+}
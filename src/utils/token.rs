@@ -1,53 +1,525 @@
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use serde::de::DeserializeOwned;
+use rand::{rngs::OsRng, Rng, RngCore};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
 
+use crate::config::Config;
 use crate::error::{ErrorMessage, HttpError};
+use crate::models::UserRole;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JwtAlgorithm {
+    HS256,
+    RS256,
+    ES256,
+}
+
+impl JwtAlgorithm {
+    pub fn is_asymmetric(self) -> bool {
+        !matches!(self, JwtAlgorithm::HS256)
+    }
+
+    fn encoding_key(self, key: &[u8]) -> Result<EncodingKey, jsonwebtoken::errors::Error> {
+        match self {
+            JwtAlgorithm::HS256 => Ok(EncodingKey::from_secret(key)),
+            JwtAlgorithm::RS256 => EncodingKey::from_rsa_pem(key),
+            JwtAlgorithm::ES256 => EncodingKey::from_ec_pem(key),
+        }
+    }
+
+    fn decoding_key(self, key: &[u8]) -> Result<DecodingKey, jsonwebtoken::errors::Error> {
+        match self {
+            JwtAlgorithm::HS256 => Ok(DecodingKey::from_secret(key)),
+            JwtAlgorithm::RS256 => DecodingKey::from_rsa_pem(key),
+            JwtAlgorithm::ES256 => DecodingKey::from_ec_pem(key),
+        }
+    }
+}
+
+impl From<JwtAlgorithm> for Algorithm {
+    fn from(value: JwtAlgorithm) -> Self {
+        match value {
+            JwtAlgorithm::HS256 => Algorithm::HS256,
+            JwtAlgorithm::RS256 => Algorithm::RS256,
+            JwtAlgorithm::ES256 => Algorithm::ES256,
+        }
+    }
+}
+
+impl std::str::FromStr for JwtAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "HS256" => Ok(JwtAlgorithm::HS256),
+            "RS256" => Ok(JwtAlgorithm::RS256),
+            "ES256" => Ok(JwtAlgorithm::ES256),
+            other => Err(format!("Unsupported JWT algorithm: {}", other)),
+        }
+    }
+}
+
+/// Claims carried by access tokens. `role`, `email` and `sid` reflect the user when
+/// the token was issued and are absent from tokens minted before they were added.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct TokenClaims {
     pub sub: String,
+    pub jti: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<UserRole>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    /// Id of the login session (refresh token family) the token was issued for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// Id of the admin who issued the token through impersonation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonated_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
     pub iat: usize,
     pub exp: usize,
 }
 
-pub fn create_token(
-    user_id: &str,
-    secret: &[u8],
-    expires_in_seconds: i64,
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TwoFactorChallengeClaims {
+    pub sub: String,
+    pub purpose: String,
+    pub iat: usize,
+    pub exp: usize,
+}
+
+const TWO_FACTOR_CHALLENGE_PURPOSE: &str = "2fa_challenge";
+const KEY_ID_LENGTH: usize = 16;
+const API_KEY_PREFIX: &str = "rak_";
+
+/// Issues and verifies the signed tokens handed to clients. Handlers and the auth
+/// middleware go through this trait so the signing backend can be replaced.
+pub trait TokenService: Send + Sync + std::fmt::Debug {
+    fn create_token(
+        &self,
+        user_id: &str,
+        role: UserRole,
+        email: &str,
+        session_id: uuid::Uuid,
+        expires_in_minutes: i64,
+    ) -> Result<String, HttpError>;
+
+    /// Issues an access token for `user_id` marked as obtained by `admin_id` impersonating them.
+    fn create_impersonation_token(
+        &self,
+        user_id: &str,
+        role: UserRole,
+        email: &str,
+        admin_id: &str,
+        expires_in_minutes: i64,
+    ) -> Result<String, HttpError>;
+
+    fn decode_token(&self, token: &str) -> Result<TokenClaims, HttpError>;
+
+    fn create_two_factor_challenge(
+        &self,
+        user_id: &str,
+        expires_in_minutes: i64,
+    ) -> Result<String, HttpError>;
+
+    /// Returns the user id the challenge was issued for.
+    fn decode_two_factor_challenge(&self, token: &str) -> Result<String, HttpError>;
+}
+
+/// A verification key with the `kid` put in the header of tokens it verifies.
+#[derive(Debug, Clone)]
+struct JwtKey {
+    kid: String,
+    key: Vec<u8>,
+}
+
+impl JwtKey {
+    // Derived from the key so every replica agrees on it without extra config. A
+    // truncated hash of a 32+ byte secret gives nothing away about the secret.
+    fn new(key: &[u8]) -> Self {
+        JwtKey {
+            kid: hex::encode(Sha256::digest(key))[..KEY_ID_LENGTH].to_string(),
+            key: key.to_vec(),
+        }
+    }
+}
+
+/// `TokenService` backed by jsonwebtoken, using the configured algorithm and keys.
+#[derive(Debug, Clone)]
+pub struct JwtTokenService {
+    algorithm: JwtAlgorithm,
+    signing_key: Vec<u8>,
+    verifying_keys: Vec<JwtKey>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    require_issuer_audience: bool,
+}
+
+impl JwtTokenService {
+    pub fn new(config: &Config) -> Self {
+        JwtTokenService {
+            algorithm: config.jwt_algorithm,
+            signing_key: config.jwt_signing_key.clone(),
+            verifying_keys: config
+                .jwt_verifying_keys
+                .iter()
+                .map(|key| JwtKey::new(key))
+                .collect(),
+            issuer: config.jwt_issuer.clone(),
+            audience: config.jwt_audience.clone(),
+            require_issuer_audience: config.jwt_require_issuer_audience,
+        }
+    }
+
+    /// Tokens carrying an `iss` or `aud` other than the configured one are always
+    /// rejected; tokens missing them only when `require_issuer_audience` is set.
+    fn access_token_validation(&self) -> Validation {
+        let mut validation = Validation::new(self.algorithm.into());
+        let mut required_claims = vec!["exp"];
+
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+            if self.require_issuer_audience {
+                required_claims.push("iss");
+            }
+        }
+        if let Some(audience) = &self.audience {
+            validation.set_audience(&[audience]);
+            if self.require_issuer_audience {
+                required_claims.push("aud");
+            }
+        }
+        validation.set_required_spec_claims(&required_claims);
+
+        validation
+    }
+
+    /// Header for newly signed tokens, naming the key that verifies them.
+    fn header(&self) -> Header {
+        let mut header = Header::new(self.algorithm.into());
+        header.kid = self.verifying_keys.first().map(|key| key.kid.clone());
+        header
+    }
+
+    /// The keys to try for `token`: the one its `kid` names first, then the rest,
+    /// since tokens signed before rotation support carry no `kid`.
+    fn candidate_keys(&self, token: &str) -> Vec<&JwtKey> {
+        let kid = decode_header(token).ok().and_then(|header| header.kid);
+        let (mut named, others): (Vec<&JwtKey>, Vec<&JwtKey>) = self
+            .verifying_keys
+            .iter()
+            .partition(|key| Some(&key.kid) == kid.as_ref());
+        named.extend(others);
+        named
+    }
+
+    fn claims(
+        &self,
+        user_id: &str,
+        role: UserRole,
+        email: &str,
+        session_id: uuid::Uuid,
+        impersonated_by: Option<&str>,
+        expires_in_minutes: i64,
+    ) -> TokenClaims {
+        let now = Utc::now();
+
+        TokenClaims {
+            sub: user_id.to_string(),
+            jti: uuid::Uuid::new_v4().to_string(),
+            role: Some(role),
+            email: Some(email.to_string()),
+            sid: Some(session_id.to_string()),
+            impersonated_by: impersonated_by.map(str::to_string),
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            iat: now.timestamp() as usize,
+            exp: (now + Duration::minutes(expires_in_minutes)).timestamp() as usize,
+        }
+    }
+}
+
+impl TokenService for JwtTokenService {
+    fn create_token(
+        &self,
+        user_id: &str,
+        role: UserRole,
+        email: &str,
+        session_id: uuid::Uuid,
+        expires_in_minutes: i64,
+    ) -> Result<String, HttpError> {
+        let claims = self.claims(user_id, role, email, session_id, None, expires_in_minutes);
+
+        create_token(&claims, self.header(), &self.signing_key, self.algorithm)
+            .map_err(|e| HttpError::server_error(e.to_string()))
+    }
+
+    // Impersonation tokens get a session of their own, so they never show up as or
+    // revoke one of the user's real sessions.
+    fn create_impersonation_token(
+        &self,
+        user_id: &str,
+        role: UserRole,
+        email: &str,
+        admin_id: &str,
+        expires_in_minutes: i64,
+    ) -> Result<String, HttpError> {
+        let claims = self.claims(
+            user_id,
+            role,
+            email,
+            uuid::Uuid::new_v4(),
+            Some(admin_id),
+            expires_in_minutes,
+        );
+
+        create_token(&claims, self.header(), &self.signing_key, self.algorithm)
+            .map_err(|e| HttpError::server_error(e.to_string()))
+    }
+
+    fn decode_token(&self, token: &str) -> Result<TokenClaims, HttpError> {
+        decode_with_keys(
+            token,
+            &self.candidate_keys(token),
+            self.algorithm,
+            &self.access_token_validation(),
+        )
+    }
+
+    fn create_two_factor_challenge(
+        &self,
+        user_id: &str,
+        expires_in_minutes: i64,
+    ) -> Result<String, HttpError> {
+        create_two_factor_challenge(
+            user_id,
+            self.header(),
+            &self.signing_key,
+            self.algorithm,
+            expires_in_minutes,
+        )
+        .map_err(|e| HttpError::server_error(e.to_string()))
+    }
+
+    fn decode_two_factor_challenge(&self, token: &str) -> Result<String, HttpError> {
+        let claims: TwoFactorChallengeClaims = decode_with_keys(
+            token,
+            &self.candidate_keys(token),
+            self.algorithm,
+            &Validation::new(self.algorithm.into()),
+        )?;
+
+        if claims.purpose == TWO_FACTOR_CHALLENGE_PURPOSE {
+            Ok(claims.sub)
+        } else {
+            Err(HttpError::unauthorized(ErrorMessage::InvalidToken))
+        }
+    }
+}
+
+fn create_token(
+    claims: &TokenClaims,
+    header: Header,
+    key: &[u8],
+    algorithm: JwtAlgorithm,
 ) -> Result<String,  jsonwebtoken::errors::Error> {
-    if user_id.is_empty() {
+    if claims.sub.is_empty() {
         return Err(jsonwebtoken::errors::ErrorKind::InvalidSubject.into());
     }
 
+    encode(
+        &header, 
+        claims, 
+        &algorithm.encoding_key(key)?,
+    )
+}
+
+/// Returns the claims of the first key in `keys` whose signature checks out.
+fn decode_with_keys<T: DeserializeOwned>(
+    token: &str,
+    keys: &[&JwtKey],
+    algorithm: JwtAlgorithm,
+    validation: &Validation,
+) -> Result<T, HttpError> {
+    for key in keys {
+        let decoding_key = algorithm
+            .decoding_key(&key.key)
+            .map_err(|_| HttpError::server_error(ErrorMessage::ServerError))?;
+
+        if let Ok(token) = decode::<T>(token, &decoding_key, validation) {
+            return Ok(token.claims);
+        }
+    }
+
+    Err(HttpError::unauthorized(ErrorMessage::InvalidToken))
+}
+
+// Challenge tokens carry no `jti`, so they can never pass as access tokens.
+fn create_two_factor_challenge(
+    user_id: &str,
+    header: Header,
+    key: &[u8],
+    algorithm: JwtAlgorithm,
+    expires_in_minutes: i64,
+) -> Result<String, jsonwebtoken::errors::Error> {
     let now = Utc::now();
-    let iat = now.timestamp() as usize;
-    let exp = (now + Duration::minutes(expires_in_seconds)).timestamp() as usize;
-    let claims: TokenClaims = TokenClaims { 
-        sub: user_id.to_string(), 
-        iat, 
-        exp, 
+    let claims = TwoFactorChallengeClaims {
+        sub: user_id.to_string(),
+        purpose: TWO_FACTOR_CHALLENGE_PURPOSE.to_string(),
+        iat: now.timestamp() as usize,
+        exp: (now + Duration::minutes(expires_in_minutes)).timestamp() as usize,
     };
 
-    encode(
-        &Header::default(), 
-        &claims, 
-        &EncodingKey::from_secret(secret),
-    )
+    encode(&header, &claims, &algorithm.encoding_key(key)?)
+}
+
+pub fn create_refresh_token() -> String {
+    create_random_token()
 }
 
-pub fn decode_token<T: Into<String>>(
-    token: T,
-    secret: &[u8],
-) -> Result<String, HttpError> {
-    let decoded = decode::<TokenClaims>(
-        &token.into(),
-        &DecodingKey::from_secret(secret),
-        &Validation::new(Algorithm::HS256),
-    );
+pub fn create_verification_token() -> String {
+    create_random_token()
+}
+
+pub fn create_invitation_token() -> String {
+    create_random_token()
+}
 
-    match decoded {
-        Ok(token) => Ok(token.claims.sub),
-        Err(_) => Err(HttpError::new(ErrorMessage::InvalidToken.to_string(), 401)),
+/// Six digits, short enough to type from a text message.
+pub fn create_phone_verification_code() -> String {
+    format!("{:06}", OsRng.gen_range(0..1_000_000))
+}
+
+pub fn create_password_reset_token() -> String {
+    create_random_token()
+}
+
+/// A random API key. The `rak_` prefix makes leaked keys easy to spot in logs and scans.
+pub fn create_api_key() -> String {
+    format!("{}{}", API_KEY_PREFIX, create_random_token())
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn create_random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Hands out opaque tokens and remembers the claims behind them, for tests that
+/// need the auth middleware without signing real JWTs.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct FakeTokenService {
+    tokens: std::sync::Mutex<std::collections::HashMap<String, TokenClaims>>,
+    challenges: std::sync::Mutex<std::collections::HashMap<String, String>>,
+}
+
+#[cfg(test)]
+impl FakeTokenService {
+    fn issue(&self, claims: TokenClaims) -> String {
+        let token = format!("fake-{}", claims.jti);
+        self.tokens.lock().unwrap().insert(token.clone(), claims);
+        token
     }
-}
\ No newline at end of file
+
+    fn claims(
+        user_id: &str,
+        role: UserRole,
+        email: &str,
+        session_id: uuid::Uuid,
+        impersonated_by: Option<&str>,
+        expires_in_minutes: i64,
+    ) -> TokenClaims {
+        let now = Utc::now();
+
+        TokenClaims {
+            sub: user_id.to_string(),
+            jti: uuid::Uuid::new_v4().to_string(),
+            role: Some(role),
+            email: Some(email.to_string()),
+            sid: Some(session_id.to_string()),
+            impersonated_by: impersonated_by.map(str::to_string),
+            iss: None,
+            aud: None,
+            iat: now.timestamp() as usize,
+            exp: (now + Duration::minutes(expires_in_minutes)).timestamp() as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+impl TokenService for FakeTokenService {
+    fn create_token(
+        &self,
+        user_id: &str,
+        role: UserRole,
+        email: &str,
+        session_id: uuid::Uuid,
+        expires_in_minutes: i64,
+    ) -> Result<String, HttpError> {
+        let claims = FakeTokenService::claims(user_id, role, email, session_id, None, expires_in_minutes);
+        Ok(self.issue(claims))
+    }
+
+    fn create_impersonation_token(
+        &self,
+        user_id: &str,
+        role: UserRole,
+        email: &str,
+        admin_id: &str,
+        expires_in_minutes: i64,
+    ) -> Result<String, HttpError> {
+        let claims = FakeTokenService::claims(
+            user_id,
+            role,
+            email,
+            uuid::Uuid::new_v4(),
+            Some(admin_id),
+            expires_in_minutes,
+        );
+        Ok(self.issue(claims))
+    }
+
+    fn decode_token(&self, token: &str) -> Result<TokenClaims, HttpError> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .get(token)
+            .cloned()
+            .ok_or_else(|| HttpError::unauthorized(ErrorMessage::InvalidToken))
+    }
+
+    fn create_two_factor_challenge(
+        &self,
+        user_id: &str,
+        _expires_in_minutes: i64,
+    ) -> Result<String, HttpError> {
+        let token = format!("fake-challenge-{}", uuid::Uuid::new_v4());
+        self.challenges
+            .lock()
+            .unwrap()
+            .insert(token.clone(), user_id.to_string());
+        Ok(token)
+    }
+
+    fn decode_two_factor_challenge(&self, token: &str) -> Result<String, HttpError> {
+        self.challenges
+            .lock()
+            .unwrap()
+            .get(token)
+            .cloned()
+            .ok_or_else(|| HttpError::unauthorized(ErrorMessage::InvalidToken))
+    }
+}
@@ -0,0 +1,64 @@
+use rand::{rngs::OsRng, RngCore};
+use std::time::{SystemTime, UNIX_EPOCH};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::error::ErrorMessage;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const STEP_SECONDS: u64 = 30;
+// Codes from the previous and next step are accepted too, to allow for clock drift.
+const SKEW: u64 = 1;
+
+pub fn generate_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+pub fn otpauth_url(secret: &str, issuer: &str, account_name: &str) -> Result<String, ErrorMessage> {
+    let totp = build(secret, Some(issuer.to_string()), account_name.to_string())?;
+    Ok(totp.get_url())
+}
+
+/// Checks `code` against the current time step and its neighbours, and returns
+/// the step it belongs to. Callers record that step so the code can't be replayed.
+pub fn verify_code(secret: &str, code: &str) -> Result<Option<i64>, ErrorMessage> {
+    let totp = build(secret, None, String::new())?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| ErrorMessage::ServerError)?
+        .as_secs();
+    let current_step = now / STEP_SECONDS;
+
+    let step = (current_step.saturating_sub(SKEW)..=current_step + SKEW)
+        .find(|step| totp.check(code.trim(), step * STEP_SECONDS));
+
+    Ok(step.map(|step| step as i64))
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 5];
+            OsRng.fill_bytes(&mut bytes);
+            let code = hex::encode(bytes);
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+// Built without skew: `verify_code` walks the accepted steps itself so it
+// knows which one matched.
+fn build(secret: &str, issuer: Option<String>, account_name: String) -> Result<TOTP, ErrorMessage> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|_| ErrorMessage::ServerError)?;
+
+    TOTP::new(Algorithm::SHA1, 6, 0, STEP_SECONDS, secret, issuer, account_name)
+        .map_err(|_| ErrorMessage::ServerError)
+}
@@ -0,0 +1,171 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::WebhookConfig;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_ATTEMPTS: u32 = 4;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    UserRegistered,
+    UserVerified,
+    RoleChanged,
+}
+
+impl WebhookEvent {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            WebhookEvent::UserRegistered => "user_registered",
+            WebhookEvent::UserVerified => "user_verified",
+            WebhookEvent::RoleChanged => "role_changed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookPayload {
+    pub event: WebhookEvent,
+    #[serde(rename = "userId")]
+    pub user_id: uuid::Uuid,
+    #[serde(rename = "occurredAt")]
+    pub occurred_at: DateTime<Utc>,
+    pub data: serde_json::Value,
+}
+
+impl WebhookPayload {
+    pub fn new(event: WebhookEvent, user_id: uuid::Uuid, data: serde_json::Value) -> Self {
+        WebhookPayload {
+            event,
+            user_id,
+            occurred_at: Utc::now(),
+            data,
+        }
+    }
+}
+
+/// Notifies an external system of account events.
+pub trait WebhookDispatcher: Send + Sync + std::fmt::Debug {
+    /// Hands the event off for delivery and returns straight away; delivery
+    /// failures are retried and logged, never reported to the caller.
+    fn dispatch(&self, payload: WebhookPayload);
+}
+
+/// Drops every event. Used when no webhook URL is configured.
+#[derive(Debug, Default)]
+pub struct NoopWebhookDispatcher;
+
+impl WebhookDispatcher for NoopWebhookDispatcher {
+    fn dispatch(&self, _payload: WebhookPayload) {}
+}
+
+/// POSTs each event as JSON, signed with HMAC-SHA256 over the raw body.
+#[derive(Debug, Clone)]
+pub struct HttpWebhookDispatcher {
+    client: reqwest::Client,
+    url: Arc<str>,
+    secret: Arc<str>,
+}
+
+impl HttpWebhookDispatcher {
+    pub fn new(config: &WebhookConfig) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .user_agent("rust_auth")
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        Ok(HttpWebhookDispatcher {
+            client,
+            url: config.url.as_str().into(),
+            secret: config.secret.as_str().into(),
+        })
+    }
+
+    async fn deliver(&self, event: WebhookEvent, body: &str) -> Result<(), String> {
+        let response = self
+            .client
+            .post(&*self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, event.to_str())
+            .header(SIGNATURE_HEADER, format!("sha256={}", sign(&self.secret, body)))
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("webhook endpoint answered {}", response.status()))
+        }
+    }
+}
+
+impl WebhookDispatcher for HttpWebhookDispatcher {
+    fn dispatch(&self, payload: WebhookPayload) {
+        let body = match serde_json::to_string(&payload) {
+            Ok(body) => body,
+            Err(e) => {
+                log::error!("Error serializing webhook payload: {}", e);
+                return;
+            }
+        };
+        let dispatcher = self.clone();
+
+        actix_web::rt::spawn(async move {
+            let mut backoff = INITIAL_BACKOFF;
+
+            for attempt in 1..=MAX_ATTEMPTS {
+                match dispatcher.deliver(payload.event, &body).await {
+                    Ok(()) => return,
+                    Err(e) if attempt == MAX_ATTEMPTS => {
+                        log::error!(
+                            "Giving up on {} webhook for user {} after {} attempts: {}",
+                            payload.event.to_str(),
+                            payload.user_id,
+                            attempt,
+                            e
+                        );
+                    }
+                    Err(_) => {
+                        actix_web::rt::time::sleep(backoff).await;
+                        backoff *= 2;
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Hex-encoded HMAC-SHA256 of `body`, for the receiver to check with the shared secret.
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(body.as_bytes());
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Keeps every dispatched event so tests can check what would have been sent.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct RecordingWebhookDispatcher {
+    pub payloads: std::sync::Mutex<Vec<WebhookPayload>>,
+}
+
+#[cfg(test)]
+impl WebhookDispatcher for RecordingWebhookDispatcher {
+    fn dispatch(&self, payload: WebhookPayload) {
+        self.payloads.lock().unwrap().push(payload);
+    }
+}